@@ -4,9 +4,14 @@ use clap::App;
 use clap::Arg;
 use url::Url;
 use clap::SubCommand;
+use keta_core::chain_spec::Network;
 use std::str::FromStr;
 
-const DEFAULT_RPC_URL: &str = "ws://localhost:5454";
+fn default_rpc_url(network: Network) -> Url {
+    format!("ws://localhost:{}", network.default_rpc_port())
+        .parse()
+        .unwrap()
+}
 
 #[derive(Debug)]
 pub struct Args {
@@ -19,6 +24,7 @@ pub enum Command {
     Balance(commands::Balance),
     Generate(commands::Generate),
     Pay(commands::Pay),
+    Status(commands::Status),
 }
 
 #[async_trait]
@@ -28,6 +34,7 @@ impl commands::Command for Command {
             Command::Balance(command) => command.run(ctx),
             Command::Generate(command) => command.run(ctx),
             Command::Pay(command) => command.run(ctx),
+            Command::Status(command) => command.run(ctx),
         }.await
     }
 }
@@ -39,13 +46,25 @@ pub fn parse_args() -> Args {
         .author(clap::crate_authors!())
         .about("keta cli implementation")
         .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("network")
+                .long("network")
+                .help("Network the node is on, which sets the default RPC URL")
+                .possible_values(&["mainnet", "testnet", "devnet"])
+                .default_value("mainnet"),
+        )
         .arg(
             Arg::with_name("rpc-url")
                 .long("rpc-url")
-                .help("RPC URL")
-                .default_value(DEFAULT_RPC_URL),
+                .help(
+                    "RPC URL, over WebSocket for ws://, HTTP for http:// and the node's \
+                     socket for ipc://<path> [default: localhost on the network's RPC port]",
+                )
+                .takes_value(true),
         )
         .subcommand(balance())
+        .subcommand(pay())
+        .subcommand(SubCommand::with_name("status").about("View the chain and node status"))
         .get_matches();
 
     let (name, sub_matches) = match matches.subcommand() {
@@ -62,11 +81,38 @@ pub fn parse_args() -> Args {
                 address: account::Address::from_str(address).unwrap(),
             })
         }
+        "pay" => {
+            use keta_core::account;
+            use keta_crypto::Keypair;
+            use keta_crypto::SecretKey;
+
+            let secret_key_file = sub_matches.value_of("secret-key-file").unwrap();
+            let secret_key = std::fs::read_to_string(secret_key_file).unwrap();
+            let optional = |name| {
+                sub_matches
+                    .value_of(name)
+                    .map(|value| value.parse().unwrap())
+            };
+
+            Command::Pay(commands::Pay {
+                keypair: Keypair::from_secret(SecretKey::from_str(secret_key.trim()).unwrap()),
+                to: account::Address::from_str(sub_matches.value_of("to").unwrap()).unwrap(),
+                value: sub_matches.value_of("value").unwrap().parse().unwrap(),
+                fee: sub_matches.value_of("fee").unwrap().parse().unwrap(),
+                nonce: optional("nonce"),
+                valid_until: optional("valid-until"),
+                wait: optional("wait"),
+            })
+        }
+        "status" => Command::Status(commands::Status {}),
         _ => panic!("unexpected command"),
     };
 
+    let network: Network = matches.value_of("network").unwrap().parse().unwrap();
     Args {
-        rpc_url: matches.value_of("rpc-url").unwrap().parse().unwrap(),
+        rpc_url: matches
+            .value_of("rpc-url")
+            .map_or_else(|| default_rpc_url(network), |url| url.parse().unwrap()),
         command,
     }
 }
@@ -81,3 +127,54 @@ fn balance() -> App<'static, 'static> {
                 .takes_value(true),
         )
 }
+
+fn pay() -> App<'static, 'static> {
+    SubCommand::with_name("pay")
+        .about("Send a payment from an account")
+        .arg(
+            Arg::with_name("to")
+                .help("Address to pay")
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("value")
+                .help("Amount to pay")
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("secret-key-file")
+                .long("secret-key-file")
+                .help("File holding the hex encoded secret key of the paying account")
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("fee")
+                .long("fee")
+                .help("Fee paid on top of the value")
+                .default_value("0"),
+        )
+        .arg(
+            Arg::with_name("nonce")
+                .long("nonce")
+                .help(
+                    "Nonce to use, e.g. to replace a pending transaction [default: next free one]",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("valid-until")
+                .long("valid-until")
+                .help("Last block height the payment may be included at")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("wait")
+                .long("wait")
+                .help("Wait until the payment is included and has this many confirmations")
+                .value_name("confirmations")
+                .takes_value(true),
+        )
+}
@@ -11,7 +11,10 @@ pub struct Command {
 impl super::Command for Command {
     async fn run(self, mut ctx: super::Context) -> anyhow::Result<()> {
         let rpc = ctx.rpc().await?;
-        let balance = rpc.get_balance(self.address.clone()).await.unwrap()?;
+        let balance = rpc
+            .get_balance(self.address.clone())
+            .await
+            .map_err(keta_rpc::Error::from)?;
         tracing::info!("Balance of {} is {}", self.address, balance,);
         Ok(())
     }
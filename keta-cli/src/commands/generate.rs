@@ -8,7 +8,7 @@ pub struct Command {}
 impl super::Command for Command {
     async fn run(self, mut ctx: super::Context) -> anyhow::Result<()> {
         let rpc = ctx.rpc().await?;
-        let block = rpc.generate_block().await.unwrap();
+        let block = rpc.generate_block().await.map_err(keta_rpc::Error::from)?;
         tracing::info!("Generated new block: {:?}", block);
         Ok(())
     }
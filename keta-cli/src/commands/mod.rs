@@ -1,10 +1,12 @@
 mod balance;
 mod generate;
 mod pay;
+mod status;
 
 pub use balance::Command as Balance;
 pub use generate::Command as Generate;
 pub use pay::Command as Pay;
+pub use status::Command as Status;
 
 use anyhow::anyhow;
 use async_trait::async_trait;
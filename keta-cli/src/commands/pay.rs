@@ -1,32 +1,109 @@
+use anyhow::bail;
 use async_trait::async_trait;
 use keta_core::account;
 use keta_core::transaction::Transaction;
+use keta_crypto::Hash;
 use keta_crypto::Keypair;
 use keta_rpc::RpcClient;
+use keta_rpc::TransactionStatus;
+use std::time::Duration;
+
+/// How often the status of a sent transaction is checked while waiting for it.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
 
 #[derive(Debug)]
 pub struct Command {
     pub keypair: Keypair,
     pub to: account::Address,
     pub value: u64,
+    pub fee: u64,
+    /// Nonce to use instead of the next free one, e.g. to replace a pending transaction.
+    pub nonce: Option<u64>,
+    /// Last block height the payment may be included at.
+    pub valid_until: Option<u64>,
+    /// Confirmations to wait for after sending, if any.
+    pub wait: Option<u64>,
 }
 
 #[async_trait]
 impl super::Command for Command {
     async fn run(self, mut ctx: super::Context) -> anyhow::Result<()> {
         let rpc = ctx.rpc().await?;
+        let nonce = match self.nonce {
+            Some(nonce) => nonce,
+            None => rpc
+                .get_nonce(self.keypair.public.clone())
+                .await
+                .map_err(keta_rpc::Error::from)?,
+        };
         let transaction = Transaction {
             from: self.keypair.public.clone(),
             to: self.to,
             value: self.value,
+            fee: self.fee,
+            nonce,
+            valid_until: self.valid_until,
         };
         let transaction = transaction.sign(&self.keypair);
-        rpc.send_transaction(transaction.clone()).await.unwrap()?;
+        let sent = rpc
+            .send_transaction(transaction.clone())
+            .await
+            .map_err(keta_rpc::Error::from)?;
+        if let Some(replaced) = sent.replaced {
+            tracing::info!("Replaced pending transaction {}", replaced);
+        }
         tracing::info!(
-            "Sent transaction to {} with value: {}",
+            "Sent transaction {} to {} with value: {}, fee: {}",
+            sent.hash,
             transaction.to,
-            transaction.value
+            transaction.value,
+            transaction.fee
         );
-        Ok(())
+        match self.wait {
+            Some(confirmations) => wait(rpc, &sent.hash, confirmations).await,
+            None => Ok(()),
+        }
+    }
+}
+
+/// Blocks until transaction `hash` is included and has at least `confirmations`.
+async fn wait(rpc: &keta_rpc::Client, hash: &Hash, confirmations: u64) -> anyhow::Result<()> {
+    let mut last = None;
+    loop {
+        let status = rpc
+            .get_transaction_status(hash.clone())
+            .await
+            .map_err(keta_rpc::Error::from)?;
+        match &status {
+            TransactionStatus::Included {
+                height,
+                confirmations: confirmed,
+            } if *confirmed >= confirmations => {
+                tracing::info!(
+                    "Transaction {} included at height {} with {} confirmations",
+                    hash,
+                    height,
+                    confirmed
+                );
+                return Ok(());
+            }
+            TransactionStatus::Included {
+                height,
+                confirmations: confirmed,
+            } if last.as_ref() != Some(&status) => tracing::info!(
+                "Transaction {} included at height {}, {}/{} confirmations",
+                hash,
+                height,
+                confirmed,
+                confirmations
+            ),
+            TransactionStatus::Dropped { reason } => {
+                bail!("Transaction {} was dropped: {}", hash, reason)
+            }
+            TransactionStatus::Unknown => bail!("Transaction {} is unknown to the node", hash),
+            _ => {}
+        }
+        last = Some(status);
+        tokio::time::sleep(POLL_INTERVAL).await;
     }
 }
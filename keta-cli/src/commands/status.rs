@@ -0,0 +1,38 @@
+use async_trait::async_trait;
+use keta_rpc::RpcClient;
+
+#[derive(Debug)]
+pub struct Command {}
+
+#[async_trait]
+impl super::Command for Command {
+    async fn run(self, mut ctx: super::Context) -> anyhow::Result<()> {
+        let rpc = ctx.rpc().await?;
+        let chain = rpc.get_chain_info().await.map_err(keta_rpc::Error::from)?;
+        let node = rpc.get_node_status().await.map_err(keta_rpc::Error::from)?;
+
+        tracing::info!("Chain: {} (genesis {})", chain.chain_id, chain.genesis_hash);
+        tracing::info!(
+            "Tip: {} ({}) at {}",
+            chain.height,
+            chain.tip_hash,
+            chain.tip_timestamp
+        );
+        tracing::info!("Difficulty: {} bits", chain.difficulty);
+        tracing::info!("Total supply: {}", chain.total_supply);
+        tracing::info!("Node version: {}", node.version);
+        tracing::info!("Peers: {}", node.peer_count);
+        tracing::info!("Pending transactions: {}", node.mempool_size);
+        if node.sync.syncing {
+            tracing::info!(
+                "Syncing: {}/{} at {:.1} blocks/s",
+                node.sync.current_height,
+                node.sync.target_height,
+                node.sync.blocks_per_second
+            );
+        } else {
+            tracing::info!("Synced");
+        }
+        Ok(())
+    }
+}
@@ -1,10 +1,19 @@
+use keta_crypto::Hash;
 use keta_crypto::PublicKey;
 use serde::Deserialize;
 use serde::Serialize;
+use std::collections::BTreeMap;
 
 pub type Address = PublicKey;
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Account {
     pub balance: u64,
+    /// Nonce expected in the next transaction sent from this account.
+    pub nonce: u64,
+}
+
+/// Commitment to a complete set of accounts, independent of how they were stored.
+pub fn state_root(accounts: &BTreeMap<Address, Account>) -> Hash {
+    Hash::new(bincode::serialize(accounts).unwrap())
 }
@@ -140,6 +140,38 @@ pub struct HashedBlock {
     pub nonce: Nonce,
 }
 
+/// Everything the proof of work of a block commits to, with the transactions replaced by their
+/// root, so that a chain of headers can be checked without downloading the transactions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    pub index: Index,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub timestamp: DateTime<Utc>,
+    pub prev_hash: Hash,
+    pub transactions_root: Hash,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HashedHeader {
+    pub header: Header,
+    pub hash: Hash,
+    pub nonce: Nonce,
+}
+
+pub fn transactions_root(transactions: &[SignedTransaction]) -> Hash {
+    Hash::new(bincode::serialize(transactions).unwrap())
+}
+
+impl HashedBlock {
+    pub fn header(&self) -> HashedHeader {
+        HashedHeader {
+            header: self.block.header(),
+            hash: self.hash.clone(),
+            nonce: self.nonce,
+        }
+    }
+}
+
 impl std::ops::Deref for HashedBlock {
     type Target = Block;
 
@@ -148,6 +180,21 @@ impl std::ops::Deref for HashedBlock {
     }
 }
 
+impl std::ops::Deref for HashedHeader {
+    type Target = Header;
+
+    fn deref(&self) -> &Self::Target {
+        &self.header
+    }
+}
+
+impl Header {
+    pub fn hash_with_nonce(&self, nonce: Nonce) -> Hash {
+        let serialized = bincode::serialize(self).unwrap();
+        keta_crypto::Hash::new_with_nonce(serialized, nonce)
+    }
+}
+
 impl Block {
     pub fn generate(prev_block: &HashedBlock, transactions: Vec<SignedTransaction>) -> Self {
         Self {
@@ -158,9 +205,17 @@ impl Block {
         }
     }
 
+    pub fn header(&self) -> Header {
+        Header {
+            index: self.index.clone(),
+            timestamp: self.timestamp,
+            prev_hash: self.prev_hash.clone(),
+            transactions_root: transactions_root(&self.transactions),
+        }
+    }
+
     pub fn hash_with_nonce(&self, nonce: Nonce) -> Hash {
-        let serialized = bincode::serialize(self).unwrap();
-        keta_crypto::Hash::new_with_nonce(serialized, nonce)
+        self.header().hash_with_nonce(nonce)
     }
 }
 
@@ -0,0 +1,199 @@
+use crate::account;
+use crate::account::Account;
+use crate::account::Address;
+use crate::block::Block;
+use crate::block::HashedBlock;
+use crate::block::Index;
+use chrono::TimeZone;
+use chrono::Utc;
+use keta_crypto::Hash;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// Secret key of the account funded at devnet genesis. Published so that anyone can pay from it
+/// on devnet, worthless anywhere else.
+pub const DEVNET_FAUCET_SECRET_KEY: &str =
+    "e48930baed8d6fa132f88eacec450a5d816ee1d28e6097dcea35c85ab2e3b78c";
+
+/// What makes a chain distinct from others. Nodes only talk to peers with the same chain ID and
+/// genesis block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChainSpec {
+    pub chain_id: &'static str,
+    /// Timestamp of the genesis block, in seconds since the epoch.
+    pub genesis_timestamp: i64,
+    /// Hex encoded addresses funded at genesis, with their balances. No coins are created after
+    /// genesis.
+    pub allocations: &'static [(&'static str, u64)],
+}
+
+impl ChainSpec {
+    pub const MAINNET: Self = Self {
+        chain_id: "keta",
+        genesis_timestamp: 0,
+        allocations: &[],
+    };
+
+    pub const TESTNET: Self = Self {
+        chain_id: "keta-testnet",
+        genesis_timestamp: 1_633_046_400,
+        allocations: &[],
+    };
+
+    pub const DEVNET: Self = Self {
+        chain_id: "keta-devnet",
+        genesis_timestamp: 1_633_132_800,
+        // Address of `DEVNET_FAUCET_SECRET_KEY`.
+        allocations: &[(
+            "b68b24c6c7a789f625ab6bbd3dd8069564ae4c0564be9c6a2bed6ca0605f6b40",
+            1_000_000_000_000,
+        )],
+    };
+
+    /// First block of the chain, identical on every node.
+    pub fn genesis(&self) -> HashedBlock {
+        // Having no parent, the genesis block commits to the allocations instead, so that chains
+        // differing only in them do not share it. Zero without any, as mainnet always had.
+        let prev_hash = if self.allocations.is_empty() {
+            Hash::ZERO
+        } else {
+            account::state_root(&self.genesis_accounts())
+        };
+        let block = Block {
+            index: Index::ZERO,
+            timestamp: Utc.timestamp(self.genesis_timestamp, 0),
+            transactions: Vec::new(),
+            prev_hash,
+        };
+        let nonce = 0;
+        HashedBlock {
+            hash: block.hash_with_nonce(nonce),
+            block,
+            nonce,
+        }
+    }
+
+    /// Accounts as of the genesis block, before any transaction.
+    pub fn genesis_accounts(&self) -> BTreeMap<Address, Account> {
+        self.allocations
+            .iter()
+            .map(|(address, balance)| {
+                let address = address.parse().expect("invalid genesis allocation address");
+                let account = Account {
+                    balance: *balance,
+                    nonce: 0,
+                };
+                (address, account)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("unknown network {0}, expected one of mainnet, testnet or devnet")]
+pub struct UnknownNetwork(String);
+
+/// The networks a node can join, bundling the chain with the defaults to join it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    Devnet,
+}
+
+impl Network {
+    pub fn name(self) -> &'static str {
+        match self {
+            Network::Mainnet => "mainnet",
+            Network::Testnet => "testnet",
+            Network::Devnet => "devnet",
+        }
+    }
+
+    pub fn chain_spec(self) -> ChainSpec {
+        match self {
+            Network::Mainnet => ChainSpec::MAINNET,
+            Network::Testnet => ChainSpec::TESTNET,
+            Network::Devnet => ChainSpec::DEVNET,
+        }
+    }
+
+    pub fn default_rpc_port(self) -> u16 {
+        match self {
+            Network::Mainnet => 5454,
+            Network::Testnet => 15454,
+            Network::Devnet => 25454,
+        }
+    }
+
+    pub fn default_p2p_port(self) -> u16 {
+        self.default_rpc_port() + 1
+    }
+
+    pub fn default_http_rpc_port(self) -> u16 {
+        self.default_rpc_port() + 2
+    }
+
+    /// Subdirectory of the data directory the network's data is kept in. Mainnet data stays
+    /// directly in the data directory, where it was kept before there were other networks.
+    pub fn data_dir(self) -> Option<&'static str> {
+        match self {
+            Network::Mainnet => None,
+            Network::Testnet => Some("testnet"),
+            Network::Devnet => Some("devnet"),
+        }
+    }
+
+    /// `host:port` of peers to discover others through. None are published for any network
+    /// yet, they have to be given with `--bootnodes`.
+    pub fn bootnodes(self) -> &'static [&'static str] {
+        &[]
+    }
+}
+
+impl FromStr for Network {
+    type Err = UnknownNetwork;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mainnet" => Ok(Network::Mainnet),
+            "testnet" => Ok(Network::Testnet),
+            "devnet" => Ok(Network::Devnet),
+            _ => Err(UnknownNetwork(s.to_string())),
+        }
+    }
+}
+
+impl std::fmt::Display for Network {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChainSpec;
+    use super::DEVNET_FAUCET_SECRET_KEY;
+    use keta_crypto::Keypair;
+
+    #[test]
+    fn genesis_blocks() {
+        // Existing mainnet databases start with this block.
+        assert_eq!(
+            ChainSpec::MAINNET.genesis().hash.to_string(),
+            "039b659e9f1ae259d36992b3bacbb1c725ba10c7528dad7169b9af03d7cbabcf"
+        );
+        assert_ne!(
+            ChainSpec::TESTNET.genesis().hash,
+            ChainSpec::DEVNET.genesis().hash
+        );
+    }
+
+    #[test]
+    fn devnet_faucet_is_funded() {
+        let faucet = Keypair::from_secret(DEVNET_FAUCET_SECRET_KEY.parse().unwrap());
+        let accounts = ChainSpec::DEVNET.genesis_accounts();
+        assert_eq!(accounts[&faucet.public].balance, 1_000_000_000_000);
+        assert!(ChainSpec::MAINNET.genesis_accounts().is_empty());
+    }
+}
@@ -1,3 +1,4 @@
 pub mod account;
 pub mod block;
+pub mod chain_spec;
 pub mod transaction;
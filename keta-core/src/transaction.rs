@@ -1,4 +1,5 @@
 use crate::account::Address;
+use keta_crypto::Hash;
 use keta_crypto::Keypair;
 use keta_crypto::PublicKey;
 use keta_crypto::Signature;
@@ -16,6 +17,12 @@ pub struct Transaction {
     pub from: Address,
     pub to: Address,
     pub value: u64,
+    /// Paid by the sender on top of `value`, used to prioritise transactions.
+    pub fee: u64,
+    /// Must equal the number of transactions previously sent from `from`.
+    pub nonce: u64,
+    /// Last block height this transaction may be included at, if it expires at all.
+    pub valid_until: Option<u64>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -25,6 +32,15 @@ pub struct SignedTransaction {
 }
 
 impl Transaction {
+    /// Amount debited from the sender.
+    pub fn cost(&self) -> Option<u64> {
+        self.value.checked_add(self.fee)
+    }
+
+    pub fn is_expired_at(&self, height: u64) -> bool {
+        matches!(self.valid_until, Some(valid_until) if height > valid_until)
+    }
+
     pub fn sign(self, keypair: &Keypair) -> SignedTransaction {
         let serialized = bincode::serialize(&self).unwrap();
         let signature = keypair.sign(serialized);
@@ -37,8 +53,12 @@ impl Transaction {
 }
 
 impl SignedTransaction {
+    pub fn hash(&self) -> Hash {
+        Hash::new(bincode::serialize(self).unwrap())
+    }
+
     pub fn verify(&self, key: &PublicKey) -> Result<(), VerifyError> {
-        let serialized = bincode::serialize(&self).unwrap();
+        let serialized = bincode::serialize(&self.transaction).unwrap();
         key.verify(&serialized, self.signature.clone())
             .map_err(|_| VerifyError::InvalidSignature)
     }
@@ -51,3 +71,60 @@ impl std::ops::Deref for SignedTransaction {
         &self.transaction
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Transaction;
+    use keta_crypto::Keypair;
+
+    #[test]
+    fn sign_verify() {
+        let keypair = Keypair::generate();
+        let transaction = Transaction {
+            from: keypair.public.clone(),
+            to: Keypair::generate().public,
+            value: 10,
+            fee: 1,
+            nonce: 0,
+            valid_until: None,
+        }
+        .sign(&keypair);
+        transaction.verify(&keypair.public).unwrap();
+    }
+
+    #[test]
+    fn sign_verify_tampered() {
+        let keypair = Keypair::generate();
+        let mut transaction = Transaction {
+            from: keypair.public.clone(),
+            to: Keypair::generate().public,
+            value: 10,
+            fee: 1,
+            nonce: 0,
+            valid_until: None,
+        }
+        .sign(&keypair);
+        transaction.transaction.value = 1000;
+        transaction.verify(&keypair.public).unwrap_err();
+    }
+
+    #[test]
+    fn expiry() {
+        let keypair = Keypair::generate();
+        let transaction = Transaction {
+            from: keypair.public.clone(),
+            to: keypair.public.clone(),
+            value: 10,
+            fee: 1,
+            nonce: 0,
+            valid_until: Some(5),
+        };
+        assert!(!transaction.is_expired_at(5));
+        assert!(transaction.is_expired_at(6));
+        let transaction = Transaction {
+            valid_until: None,
+            ..transaction
+        };
+        assert!(!transaction.is_expired_at(u64::MAX));
+    }
+}
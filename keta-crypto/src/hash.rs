@@ -2,7 +2,7 @@ pub type Nonce = u64;
 
 const HASH_SIZE: usize = 32;
 
-#[derive(Clone, PartialEq, Eq)]
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Hash([u8; HASH_SIZE]);
 
 #[derive(Debug, thiserror::Error)]
@@ -24,6 +24,12 @@ impl Hash {
         &self.0
     }
 
+    pub fn new(value: impl AsRef<[u8]>) -> Self {
+        let mut sha3 = Sha3::v256();
+        sha3.update(value.as_ref());
+        Hash::from(sha3)
+    }
+
     pub fn new_with_nonce(value: impl AsRef<[u8]>, nonce: Nonce) -> Self {
         let mut sha3 = Sha3::v256();
         sha3.update(value.as_ref());
@@ -35,11 +41,10 @@ impl Hash {
 use std::convert::TryInto;
 use std::str::FromStr;
 
-impl FromStr for Hash {
-    type Err = Error;
+impl std::convert::TryFrom<&[u8]> for Hash {
+    type Error = Error;
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let bytes = hex::decode(s)?;
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
         let bytes_length = bytes.len();
         let bytes: [u8; HASH_SIZE] = bytes.try_into().map_err(|_| Error::InvalidSize {
             expected: &HASH_SIZE,
@@ -49,6 +54,30 @@ impl FromStr for Hash {
     }
 }
 
+#[cfg(feature = "sled")]
+impl std::convert::TryFrom<sled::IVec> for Hash {
+    type Error = Error;
+
+    fn try_from(value: sled::IVec) -> Result<Self, Self::Error> {
+        Self::try_from(value.as_ref())
+    }
+}
+
+impl AsRef<[u8]> for Hash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl FromStr for Hash {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = hex::decode(s)?;
+        std::convert::TryFrom::try_from(bytes.as_slice())
+    }
+}
+
 impl std::fmt::Display for Hash {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(hex::encode(self.0).as_str())
@@ -16,7 +16,7 @@ pub enum Error {
 
 macro_rules! impl_key {
     ($ident:ident, $size:literal) => {
-        #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+        #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
         pub struct $ident([u8; $size]);
 
         impl $ident {
@@ -178,6 +178,12 @@ mod tests {
         kp.public.verify(MESSAGE, signature).unwrap_err();
     }
 
+    #[test]
+    fn from_secret() {
+        let kp = Keypair::generate();
+        assert_eq!(Keypair::from_secret(kp.secret.clone()), kp);
+    }
+
     #[test]
     fn from_hex() {
         const PKEY: &str = "4a51e55ca2ebd01141515b6a86f0d3dd3a6b3e26a99eb733f6e3483fd92f219d";
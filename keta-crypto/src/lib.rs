@@ -2,6 +2,7 @@ mod hash;
 mod keys;
 mod signature;
 
+pub use hash::Error as HashError;
 pub use hash::Hash;
 pub use hash::Nonce;
 
@@ -36,6 +37,16 @@ impl Keypair {
         }
     }
 
+    /// Rebuilds the keypair a secret key belongs to.
+    pub fn from_secret(secret: SecretKey) -> Self {
+        let public = ed25519_dalek::PublicKey::from(&secret.to_ed25519_dalek());
+
+        Self {
+            public: PublicKey::from_ed25519_dalek(public),
+            secret,
+        }
+    }
+
     pub fn sign(&self, message: impl AsRef<[u8]>) -> Signature {
         use ed25519_dalek::Signer;
         let signature = self.to_ed25519_dalek().sign(message.as_ref());
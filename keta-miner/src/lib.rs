@@ -3,7 +3,8 @@ use keta_crypto::Hash;
 use keta_crypto::Nonce;
 use num_bigint::BigInt;
 
-const TARGET_BITS: u64 = 4;
+/// Leading zero bits a block hash needs.
+pub const TARGET_BITS: u64 = 4;
 
 use lazy_static::lazy_static;
 
@@ -17,11 +18,16 @@ pub struct MineResult {
     pub nonce: Nonce,
 }
 
+pub fn meets_target(hash: &Hash) -> bool {
+    let hash_int = BigInt::from_bytes_be(num_bigint::Sign::Plus, hash.as_bytes());
+    hash_int.cmp(&TARGET) == std::cmp::Ordering::Less
+}
+
 pub fn mine_block(block: &Block) -> MineResult {
+    let header = block.header();
     for nonce in 0..Nonce::MAX {
-        let hash = block.hash_with_nonce(nonce);
-        let hash_int = BigInt::from_bytes_be(num_bigint::Sign::Plus, hash.as_bytes());
-        if hash_int.cmp(&TARGET) == std::cmp::Ordering::Less {
+        let hash = header.hash_with_nonce(nonce);
+        if meets_target(&hash) {
             return MineResult { hash, nonce };
         }
     }
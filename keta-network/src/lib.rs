@@ -0,0 +1,810 @@
+//! Peer-to-peer transport between nodes: bincode messages over encrypted TCP connections, where
+//! both sides authenticate with their node identity and exchange a handshake making sure they
+//! follow the same chain. Misbehaving peers are scored and banned once they cross a threshold.
+
+mod message;
+mod misbehaviour;
+mod peer_address;
+mod proxy;
+mod transport;
+
+pub use message::CompactBlock;
+pub use message::Handshake;
+pub use message::Message;
+pub use message::ShortId;
+pub use misbehaviour::Ban;
+pub use misbehaviour::Misbehaviour;
+pub use misbehaviour::BAN_THRESHOLD;
+pub use peer_address::Error as PeerAddressError;
+pub use peer_address::PeerAddress;
+pub use proxy::Error as ProxyError;
+pub use proxy::Proxy;
+
+use chrono::Utc;
+use keta_crypto::Hash;
+use keta_crypto::Keypair;
+use keta_crypto::PublicKey;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::BufReader;
+use tokio::io::BufWriter;
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::sync::Semaphore;
+
+/// Bump whenever the handshake or any message changes encoding or meaning.
+pub const PROTOCOL_VERSION: u32 = 8;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Messages queued for a peer before it is considered too slow and disconnected.
+const PEER_QUEUE_CAPACITY: usize = 1024;
+
+/// Events queued for the receiver before peers stop being read from.
+const EVENT_CAPACITY: usize = 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("bincode: {0}")]
+    Bincode(#[from] bincode::Error),
+
+    #[error("noise: {0}")]
+    Noise(#[from] snow::Error),
+
+    #[error("proxy: {0}")]
+    Proxy(#[from] proxy::Error),
+
+    #[error("message of {0} bytes exceeds the size limit")]
+    MessageTooLarge(u32),
+
+    #[error("malformed frame")]
+    MalformedFrame,
+
+    #[error("connection closed")]
+    ConnectionClosed,
+
+    #[error("handshake timed out")]
+    HandshakeTimeout,
+
+    #[error("protocol version mismatch: {theirs}, ours: {ours}")]
+    VersionMismatch { ours: u32, theirs: u32 },
+
+    #[error("chain mismatch: {theirs}, ours: {ours}")]
+    ChainMismatch { ours: String, theirs: String },
+
+    #[error("genesis mismatch: {theirs}, ours: {ours}")]
+    GenesisMismatch { ours: Hash, theirs: Hash },
+
+    #[error("peer failed to prove its identity")]
+    InvalidIdentity,
+
+    #[error("unexpected peer identity: {received}, expected: {expected}")]
+    UnexpectedIdentity {
+        expected: PublicKey,
+        received: PublicKey,
+    },
+
+    #[error("connected to ourselves")]
+    SelfConnection,
+
+    #[error("address {0} is banned")]
+    Banned(IpAddr),
+
+    #[error("unknown peer {0}")]
+    UnknownPeer(PeerId),
+
+    #[error("peer {0} is not keeping up and was disconnected")]
+    PeerTooSlow(PeerId),
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Key the node authenticates itself with.
+    pub identity: Keypair,
+    pub listen_address: SocketAddr,
+    pub chain_id: String,
+    pub genesis: Hash,
+    /// How long peers stay banned after misbehaving.
+    pub ban_duration: Duration,
+    /// SOCKS5 proxy to make outbound connections through.
+    pub proxy: Option<Proxy>,
+    /// Inbound connections accepted at once, counting those still in the handshake.
+    pub max_inbound: usize,
+}
+
+/// Identifies a connection for as long as it is open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PeerId(u64);
+
+impl std::fmt::Display for PeerId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    pub address: SocketAddr,
+    /// Address the peer accepts connections on. Same as `address` for outbound connections.
+    pub listen_address: SocketAddr,
+    /// Public key the peer proved to hold during the handshake.
+    pub identity: PublicKey,
+    pub direction: Direction,
+    /// Best height announced in the handshake.
+    pub best_height: u64,
+}
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    Connected(PeerId, PeerInfo),
+    Disconnected(PeerId, PeerInfo),
+    Message(PeerId, Message),
+    /// A peer crossed the misbehaviour threshold and was disconnected.
+    Banned(Ban),
+}
+
+#[derive(Debug)]
+struct Peer {
+    info: PeerInfo,
+    sender: mpsc::Sender<Message>,
+    /// Sum of the penalties for everything the peer did wrong.
+    score: u32,
+}
+
+/// A connection that completed the handshake.
+struct Connection {
+    identity: PublicKey,
+    handshake: Handshake,
+    reader: transport::Reader<BufReader<OwnedReadHalf>>,
+    writer: transport::Writer<BufWriter<OwnedWriteHalf>>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    config: Config,
+    keys: transport::LocalKeys,
+    local_address: SocketAddr,
+    best_height: AtomicU64,
+    next_peer_id: AtomicU64,
+    peers: Mutex<HashMap<PeerId, Peer>>,
+    bans: Mutex<HashMap<IpAddr, Ban>>,
+    events: mpsc::Sender<Event>,
+    /// One permit per inbound connection that may still be accepted.
+    inbound: Arc<Semaphore>,
+}
+
+/// Handle to the set of open connections. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct Network {
+    inner: Arc<Inner>,
+}
+
+impl Network {
+    /// Starts listening for peers. Everything they send, along with connections opening and
+    /// closing, is delivered through the returned receiver. Peers are not read from while it is
+    /// full.
+    pub async fn start(config: Config) -> Result<(Self, mpsc::Receiver<Event>), Error> {
+        let listener = TcpListener::bind(config.listen_address).await?;
+        let (events, receiver) = mpsc::channel(EVENT_CAPACITY);
+        let network = Self {
+            inner: Arc::new(Inner {
+                local_address: listener.local_addr()?,
+                keys: transport::LocalKeys::new(&config.identity)?,
+                inbound: Arc::new(Semaphore::new(config.max_inbound)),
+                config,
+                best_height: AtomicU64::new(0),
+                next_peer_id: AtomicU64::new(0),
+                peers: Mutex::new(HashMap::new()),
+                bans: Mutex::new(HashMap::new()),
+                events,
+            }),
+        };
+        tokio::spawn(network.clone().accept(listener));
+        Ok((network, receiver))
+    }
+
+    pub fn local_address(&self) -> SocketAddr {
+        self.inner.local_address
+    }
+
+    pub fn identity(&self) -> &PublicKey {
+        self.inner.keys.identity()
+    }
+
+    /// Height announced to peers in future handshakes.
+    pub fn set_best_height(&self, height: u64) {
+        self.inner.best_height.store(height, Ordering::Relaxed);
+    }
+
+    pub async fn connect(&self, address: SocketAddr) -> Result<PeerId, Error> {
+        self.dial(address, None).await
+    }
+
+    /// Connects to `address`, refusing the peer unless it proves to hold `identity`.
+    pub async fn connect_pinned(
+        &self,
+        address: SocketAddr,
+        identity: &PublicKey,
+    ) -> Result<PeerId, Error> {
+        self.dial(address, Some(identity)).await
+    }
+
+    async fn dial(&self, address: SocketAddr, pinned: Option<&PublicKey>) -> Result<PeerId, Error> {
+        if self.is_banned(address.ip()) {
+            return Err(Error::Banned(address.ip()));
+        }
+        let stream = match &self.inner.config.proxy {
+            Some(proxy) => proxy.connect(address).await?,
+            None => TcpStream::connect(address).await?,
+        };
+        let connection = self.handshake(stream, Direction::Outbound, pinned).await?;
+        Ok(self
+            .register(connection, address, Direction::Outbound, None)
+            .await)
+    }
+
+    /// Queues `message` for `peer`, disconnecting the peer if its queue is full.
+    pub fn send(&self, peer: PeerId, message: Message) -> Result<(), Error> {
+        let mut peers = self.inner.peers.lock().unwrap();
+        let connection = peers.get(&peer).ok_or(Error::UnknownPeer(peer))?;
+        match connection.sender.try_send(message) {
+            Ok(()) => Ok(()),
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                tracing::debug!("Disconnecting peer {}, its queue is full", peer);
+                // Dropping the sender stops the connection task.
+                peers.remove(&peer);
+                Err(Error::PeerTooSlow(peer))
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => Err(Error::UnknownPeer(peer)),
+        }
+    }
+
+    /// Sends `message` to every connected peer except `except`, disconnecting the ones whose
+    /// queue is full.
+    pub fn broadcast(&self, message: Message, except: Option<PeerId>) {
+        let mut peers = self.inner.peers.lock().unwrap();
+        peers.retain(|id, peer| {
+            if Some(*id) == except {
+                return true;
+            }
+            match peer.sender.try_send(message.clone()) {
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    tracing::debug!("Disconnecting peer {}, its queue is full", id);
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+
+    pub fn disconnect(&self, peer: PeerId) {
+        // Dropping the sender stops the connection task.
+        self.inner.peers.lock().unwrap().remove(&peer);
+    }
+
+    /// Adds the penalty for `misbehaviour` to the score of `peer`, banning its address once the
+    /// score reaches [`BAN_THRESHOLD`].
+    pub fn report(&self, peer: PeerId, misbehaviour: Misbehaviour) {
+        let mut peers = self.inner.peers.lock().unwrap();
+        let connection = match peers.get_mut(&peer) {
+            Some(connection) => connection,
+            None => return,
+        };
+        connection.score = connection.score.saturating_add(misbehaviour.penalty());
+        tracing::debug!(
+            "Peer {} misbehaved: {}, score: {}",
+            peer,
+            misbehaviour,
+            connection.score
+        );
+        if connection.score < BAN_THRESHOLD {
+            return;
+        }
+        let ban_duration = chrono::Duration::from_std(self.inner.config.ban_duration)
+            .unwrap_or_else(|_| chrono::Duration::max_value());
+        let ban = Ban {
+            address: connection.info.address.ip(),
+            until: Utc::now()
+                .checked_add_signed(ban_duration)
+                .unwrap_or(chrono::MAX_DATETIME),
+            reason: misbehaviour.to_string(),
+        };
+        drop(peers);
+        tracing::info!(
+            "Banning {} until {}: {}",
+            ban.address,
+            ban.until,
+            ban.reason
+        );
+        self.ban(ban.clone());
+        let events = self.inner.events.clone();
+        tokio::spawn(async move { events.send(Event::Banned(ban)).await });
+    }
+
+    /// Refuses connections from and to `ban.address`, dropping the open ones.
+    pub fn ban(&self, ban: Ban) {
+        let address = ban.address;
+        self.inner.bans.lock().unwrap().insert(address, ban);
+        self.inner
+            .peers
+            .lock()
+            .unwrap()
+            .retain(|_, peer| peer.info.address.ip() != address);
+    }
+
+    pub fn unban(&self, address: IpAddr) -> Option<Ban> {
+        self.inner.bans.lock().unwrap().remove(&address)
+    }
+
+    /// Bans still in force, forgetting the expired ones.
+    pub fn bans(&self) -> Vec<Ban> {
+        let now = Utc::now();
+        let mut bans = self.inner.bans.lock().unwrap();
+        bans.retain(|_, ban| ban.until > now);
+        let mut bans: Vec<_> = bans.values().cloned().collect();
+        bans.sort_by_key(|ban| ban.address);
+        bans
+    }
+
+    pub fn is_banned(&self, address: IpAddr) -> bool {
+        let bans = self.inner.bans.lock().unwrap();
+        matches!(bans.get(&address), Some(ban) if ban.until > Utc::now())
+    }
+
+    pub fn peers(&self) -> Vec<(PeerId, PeerInfo)> {
+        let peers = self.inner.peers.lock().unwrap();
+        let mut peers: Vec<_> = peers
+            .iter()
+            .map(|(id, peer)| (*id, peer.info.clone()))
+            .collect();
+        peers.sort_by_key(|(id, _)| *id);
+        peers
+    }
+
+    async fn accept(self, listener: TcpListener) {
+        loop {
+            let (stream, address) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    tracing::warn!("Failed to accept connection: {}", err);
+                    continue;
+                }
+            };
+            if self.is_banned(address.ip()) {
+                tracing::debug!("Refusing connection from banned {}", address);
+                continue;
+            }
+            let permit = match self.inner.inbound.clone().try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    tracing::debug!("Refusing connection from {}, too many inbound", address);
+                    continue;
+                }
+            };
+            let network = self.clone();
+            tokio::spawn(async move {
+                match network.handshake(stream, Direction::Inbound, None).await {
+                    Ok(connection) => {
+                        network
+                            .register(connection, address, Direction::Inbound, Some(permit))
+                            .await;
+                    }
+                    Err(err) => tracing::debug!("Handshake with {} failed: {}", address, err),
+                }
+            });
+        }
+    }
+
+    /// Secures `stream` and exchanges handshakes over it, refusing peers on another chain or
+    /// not proving the pinned identity.
+    async fn handshake(
+        &self,
+        stream: TcpStream,
+        direction: Direction,
+        pinned: Option<&PublicKey>,
+    ) -> Result<Connection, Error> {
+        let config = &self.inner.config;
+        let ours = Handshake {
+            version: PROTOCOL_VERSION,
+            chain_id: config.chain_id.clone(),
+            genesis: config.genesis.clone(),
+            best_height: self.inner.best_height.load(Ordering::Relaxed),
+            listen_port: self.inner.local_address.port(),
+        };
+        let exchange = async {
+            let (reader, writer) = stream.into_split();
+            let (reader, writer) = (BufReader::new(reader), BufWriter::new(writer));
+            let (identity, mut reader, mut writer) =
+                transport::secure(reader, writer, &self.inner.keys, direction).await?;
+            if identity == *self.inner.keys.identity() {
+                return Err(Error::SelfConnection);
+            }
+            if let Some(pinned) = pinned {
+                if identity != *pinned {
+                    return Err(Error::UnexpectedIdentity {
+                        expected: pinned.clone(),
+                        received: identity,
+                    });
+                }
+            }
+            writer.write(&ours).await?;
+            let handshake = reader
+                .read::<Handshake>()
+                .await?
+                .ok_or(Error::ConnectionClosed)?;
+            Ok(Connection {
+                identity,
+                handshake,
+                reader,
+                writer,
+            })
+        };
+        let connection = tokio::time::timeout(HANDSHAKE_TIMEOUT, exchange)
+            .await
+            .map_err(|_| Error::HandshakeTimeout)??;
+
+        let theirs = &connection.handshake;
+        if theirs.version != ours.version {
+            return Err(Error::VersionMismatch {
+                ours: ours.version,
+                theirs: theirs.version,
+            });
+        }
+        if theirs.chain_id != ours.chain_id {
+            return Err(Error::ChainMismatch {
+                ours: ours.chain_id,
+                theirs: theirs.chain_id.clone(),
+            });
+        }
+        if theirs.genesis != ours.genesis {
+            return Err(Error::GenesisMismatch {
+                ours: ours.genesis,
+                theirs: theirs.genesis.clone(),
+            });
+        }
+        Ok(connection)
+    }
+
+    /// Adds the peer and starts serving it, holding on to `permit` until it disconnects.
+    async fn register(
+        &self,
+        connection: Connection,
+        address: SocketAddr,
+        direction: Direction,
+        permit: Option<OwnedSemaphorePermit>,
+    ) -> PeerId {
+        let id = PeerId(self.inner.next_peer_id.fetch_add(1, Ordering::Relaxed));
+        let listen_address = match direction {
+            Direction::Inbound => SocketAddr::new(address.ip(), connection.handshake.listen_port),
+            Direction::Outbound => address,
+        };
+        let info = PeerInfo {
+            address,
+            listen_address,
+            identity: connection.identity,
+            direction,
+            best_height: connection.handshake.best_height,
+        };
+        let (sender, receiver) = mpsc::channel(PEER_QUEUE_CAPACITY);
+        self.inner.peers.lock().unwrap().insert(
+            id,
+            Peer {
+                info: info.clone(),
+                sender,
+                score: 0,
+            },
+        );
+        tracing::debug!(
+            "Connected to peer {} at {} ({})",
+            id,
+            address,
+            info.identity
+        );
+        let _ = self
+            .inner
+            .events
+            .send(Event::Connected(id, info.clone()))
+            .await;
+        let network = self.clone();
+        let (reader, writer) = (connection.reader, connection.writer);
+        tokio::spawn(async move {
+            network.serve(id, info, reader, writer, receiver).await;
+            drop(permit);
+        });
+        id
+    }
+
+    async fn serve(
+        self,
+        id: PeerId,
+        info: PeerInfo,
+        mut reader: transport::Reader<BufReader<OwnedReadHalf>>,
+        mut writer: transport::Writer<BufWriter<OwnedWriteHalf>>,
+        mut outgoing: mpsc::Receiver<Message>,
+    ) {
+        let events = &self.inner.events;
+        let reading = async {
+            while let Some(message) = reader.read().await? {
+                let _ = events.send(Event::Message(id, message)).await;
+            }
+            Ok::<_, Error>(())
+        };
+        let writing = async {
+            while let Some(message) = outgoing.recv().await {
+                writer.write(&message).await?;
+            }
+            Ok::<_, Error>(())
+        };
+        let result = tokio::select! {
+            result = reading => result,
+            result = writing => result,
+        };
+        if let Err(err) = result {
+            tracing::debug!("Connection to peer {} failed: {}", id, err);
+            if matches!(
+                err,
+                Error::Bincode(_)
+                    | Error::MessageTooLarge(_)
+                    | Error::MalformedFrame
+                    | Error::Noise(_)
+            ) {
+                self.report(id, Misbehaviour::MalformedMessage);
+            }
+        }
+        self.inner.peers.lock().unwrap().remove(&id);
+        tracing::debug!("Disconnected from peer {}", id);
+        let _ = events.send(Event::Disconnected(id, info)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+    use super::Error;
+    use super::Event;
+    use super::Message;
+    use super::Misbehaviour;
+    use super::Network;
+    use keta_crypto::Hash;
+    use keta_crypto::Keypair;
+    use std::convert::TryInto;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+    use tokio::net::TcpStream;
+    use tokio::sync::mpsc::Receiver;
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    async fn start(chain_id: &str) -> (Network, Receiver<Event>) {
+        start_with_max_inbound(chain_id, 8).await
+    }
+
+    async fn start_with_max_inbound(
+        chain_id: &str,
+        max_inbound: usize,
+    ) -> (Network, Receiver<Event>) {
+        Network::start(Config {
+            identity: Keypair::generate(),
+            listen_address: "127.0.0.1:0".parse().unwrap(),
+            chain_id: chain_id.to_string(),
+            genesis: Hash::ZERO,
+            ban_duration: Duration::from_secs(60),
+            proxy: None,
+            max_inbound,
+        })
+        .await
+        .unwrap()
+    }
+
+    /// Accepts SOCKS5 connect requests and relays them, sending the requested targets through
+    /// the returned receiver.
+    async fn socks5_proxy() -> (SocketAddr, UnboundedReceiver<SocketAddr>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (targets, receiver) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                let (mut client, _) = listener.accept().await.unwrap();
+                let targets = targets.clone();
+                tokio::spawn(async move {
+                    let mut greeting = [0; 3];
+                    client.read_exact(&mut greeting).await.unwrap();
+                    assert_eq!(greeting, [5, 1, 0]);
+                    client.write_all(&[5, 0]).await.unwrap();
+                    let mut request = [0; 10];
+                    client.read_exact(&mut request).await.unwrap();
+                    assert_eq!(request[..4], [5, 1, 0, 1]);
+                    let ip: [u8; 4] = request[4..8].try_into().unwrap();
+                    let port = u16::from_be_bytes([request[8], request[9]]);
+                    let target = SocketAddr::from((ip, port));
+                    targets.send(target).unwrap();
+                    let mut server = TcpStream::connect(target).await.unwrap();
+                    client
+                        .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+                        .await
+                        .unwrap();
+                    let _ = tokio::io::copy_bidirectional(&mut client, &mut server).await;
+                });
+            }
+        });
+        (address, receiver)
+    }
+
+    async fn next_message(events: &mut Receiver<Event>) -> Message {
+        loop {
+            match events.recv().await.unwrap() {
+                Event::Message(_, message) => return message,
+                _ => continue,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn broadcast_between_nodes() {
+        let (a, mut a_events) = start("test").await;
+        let (b, mut b_events) = start("test").await;
+        let (c, mut c_events) = start("test").await;
+        a.set_best_height(7);
+        b.connect(a.local_address()).await.unwrap();
+        c.connect(a.local_address()).await.unwrap();
+        match b_events.recv().await.unwrap() {
+            Event::Connected(_, info) => assert_eq!(info.best_height, 7),
+            other => panic!("unexpected event: {:?}", other),
+        }
+
+        // Wait for both inbound connections to be registered on `a`.
+        for _ in 0..2 {
+            assert!(matches!(
+                a_events.recv().await.unwrap(),
+                Event::Connected(..)
+            ));
+        }
+        let message = Message::NewTransactions(vec![Hash::ZERO]);
+        a.broadcast(message.clone(), None);
+        assert_eq!(next_message(&mut b_events).await, message);
+        assert_eq!(next_message(&mut c_events).await, message);
+
+        let (peer, _) = c.peers()[0];
+        let reply = Message::GetBlocks { from: 1, count: 2 };
+        c.send(peer, reply.clone()).unwrap();
+        assert_eq!(next_message(&mut a_events).await, reply);
+    }
+
+    #[tokio::test]
+    async fn reject_other_chain() {
+        let (a, _a_events) = start("test").await;
+        let (b, _b_events) = start("other").await;
+        assert!(matches!(
+            b.connect(a.local_address()).await,
+            Err(Error::ChainMismatch { .. })
+        ));
+        assert!(b.peers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reject_self_connection() {
+        let (a, _a_events) = start("test").await;
+        assert!(matches!(
+            a.connect(a.local_address()).await,
+            Err(Error::SelfConnection)
+        ));
+    }
+
+    #[tokio::test]
+    async fn ban_misbehaving_peer() {
+        let (a, mut a_events) = start("test").await;
+        let (b, _b_events) = start("test").await;
+        b.connect(a.local_address()).await.unwrap();
+        let peer = match a_events.recv().await.unwrap() {
+            Event::Connected(peer, _) => peer,
+            other => panic!("unexpected event: {:?}", other),
+        };
+
+        a.report(peer, Misbehaviour::RejectedTransaction);
+        assert_eq!(a.peers().len(), 1);
+        a.report(peer, Misbehaviour::InvalidBlock);
+        assert!(a.peers().is_empty());
+        let ban = loop {
+            if let Event::Banned(ban) = a_events.recv().await.unwrap() {
+                break ban;
+            }
+        };
+        assert_eq!(ban.address, b.local_address().ip());
+        assert!(matches!(
+            a.connect(b.local_address()).await,
+            Err(Error::Banned(_))
+        ));
+
+        assert!(a.unban(ban.address).is_some());
+        assert!(a.bans().is_empty());
+        a.connect(b.local_address()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuse_inbound_over_limit() {
+        let (a, mut a_events) = start_with_max_inbound("test", 1).await;
+        let (b, _b_events) = start("test").await;
+        let (c, _c_events) = start("test").await;
+        b.connect(a.local_address()).await.unwrap();
+        assert!(matches!(
+            a_events.recv().await.unwrap(),
+            Event::Connected(..)
+        ));
+        assert!(c.connect(a.local_address()).await.is_err());
+        assert_eq!(a.peers().len(), 1);
+
+        // The slot is free again once the first peer is gone.
+        let (peer, _) = a.peers()[0];
+        a.disconnect(peer);
+        loop {
+            if let Event::Disconnected(..) = a_events.recv().await.unwrap() {
+                break;
+            }
+        }
+        c.connect(a.local_address()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn authenticate_pinned_identity() {
+        let (a, _a_events) = start("test").await;
+        let (b, _b_events) = start("test").await;
+        let stranger = Keypair::generate().public;
+        assert!(matches!(
+            b.connect_pinned(a.local_address(), &stranger).await,
+            Err(Error::UnexpectedIdentity { .. })
+        ));
+
+        b.connect_pinned(a.local_address(), a.identity())
+            .await
+            .unwrap();
+        assert_eq!(b.peers()[0].1.identity, *a.identity());
+    }
+
+    #[tokio::test]
+    async fn connect_through_proxy() {
+        let (proxy, mut targets) = socks5_proxy().await;
+        let (a, mut a_events) = start("test").await;
+        let (b, _) = Network::start(Config {
+            identity: Keypair::generate(),
+            listen_address: "127.0.0.1:0".parse().unwrap(),
+            chain_id: "test".to_string(),
+            genesis: Hash::ZERO,
+            ban_duration: Duration::from_secs(60),
+            proxy: Some(format!("socks5://{}", proxy).parse().unwrap()),
+            max_inbound: 8,
+        })
+        .await
+        .unwrap();
+
+        let peer = b.connect(a.local_address()).await.unwrap();
+        assert_eq!(targets.recv().await.unwrap(), a.local_address());
+        assert!(matches!(
+            a_events.recv().await.unwrap(),
+            Event::Connected(..)
+        ));
+        let message = Message::GetAddresses;
+        b.send(peer, message.clone()).unwrap();
+        assert_eq!(next_message(&mut a_events).await, message);
+    }
+}
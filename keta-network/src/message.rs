@@ -0,0 +1,101 @@
+use keta_core::block::HashedBlock;
+use keta_core::block::HashedHeader;
+use keta_core::transaction::SignedTransaction;
+use keta_crypto::Hash;
+use serde::Deserialize;
+use serde::Serialize;
+use std::convert::TryInto;
+use std::net::SocketAddr;
+
+/// Identifies a transaction within a compact block.
+pub type ShortId = u64;
+
+/// First message sent by both sides of a connection. Peers on a different protocol version,
+/// chain or genesis block are disconnected.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Handshake {
+    pub version: u32,
+    pub chain_id: String,
+    pub genesis: Hash,
+    pub best_height: u64,
+    /// Port the sender accepts connections on, to tell others about.
+    pub listen_port: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Message {
+    /// A block became the tip of the sender's chain.
+    NewBlock {
+        hash: Hash,
+        height: u64,
+    },
+    /// A block that became the tip of the sender's chain, pushed without its transactions.
+    CompactBlock(CompactBlock),
+    /// Asks for the transactions at `indexes` of the block `hash` at `height`, the ones missing
+    /// to rebuild it from a `CompactBlock`.
+    GetBlockTransactions {
+        hash: Hash,
+        height: u64,
+        indexes: Vec<u32>,
+    },
+    /// Transactions of the block `hash`, in response to `GetBlockTransactions`.
+    BlockTransactions {
+        hash: Hash,
+        transactions: Vec<SignedTransaction>,
+    },
+    /// Asks for up to `count` consecutive headers following the first block in `locator` that
+    /// is on the receiver's chain. `locator` holds heights and hashes of the sender's blocks from
+    /// its tip down, ever sparser, so that the chains are found to part close to where they do.
+    GetHeaders {
+        locator: Vec<(u64, Hash)>,
+        count: u32,
+    },
+    /// Headers in ascending height order, in response to `GetHeaders`.
+    Headers(Vec<HashedHeader>),
+    /// Asks for up to `count` consecutive blocks starting at height `from`.
+    GetBlocks {
+        from: u64,
+        count: u32,
+    },
+    /// Blocks in ascending height order, in response to `GetBlocks`.
+    Blocks(Vec<HashedBlock>),
+    /// Transactions that entered the sender's mempool.
+    NewTransactions(Vec<Hash>),
+    GetTransactions(Vec<Hash>),
+    /// Transactions the sender knows of, in response to `GetTransactions`.
+    Transactions(Vec<SignedTransaction>),
+    /// A transaction in its stem phase, to be passed on to a single peer or broadcast.
+    StemTransaction(SignedTransaction),
+    GetAddresses,
+    /// Addresses of other peers, in response to `GetAddresses`.
+    Addresses(Vec<SocketAddr>),
+}
+
+/// A block as its header and the short IDs of its transactions, in order. The receiver rebuilds
+/// it from the transactions it already has pending and only fetches the rest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompactBlock {
+    pub header: HashedHeader,
+    pub short_ids: Vec<ShortId>,
+}
+
+impl CompactBlock {
+    pub fn new(block: &HashedBlock) -> Self {
+        let short_ids = block
+            .transactions
+            .iter()
+            .map(|transaction| Self::short_id(&block.hash, &transaction.hash()))
+            .collect();
+        Self {
+            header: block.header(),
+            short_ids,
+        }
+    }
+
+    /// Short ID of the transaction `transaction` in the block `block`. Salting it with the block
+    /// hash keeps anyone from arranging collisions ahead of time.
+    pub fn short_id(block: &Hash, transaction: &Hash) -> ShortId {
+        let hash = Hash::new([&block.as_bytes()[..], &transaction.as_bytes()[..]].concat());
+        u64::from_be_bytes(hash.as_bytes()[..8].try_into().unwrap())
+    }
+}
@@ -0,0 +1,49 @@
+use chrono::DateTime;
+use chrono::Utc;
+use std::net::IpAddr;
+
+/// Score at which a peer gets banned.
+pub const BAN_THRESHOLD: u32 = 100;
+
+/// Ways a peer can misbehave, each adding its penalty to the peer's score.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Misbehaviour {
+    /// A message that could not be decoded or exceeded the size limit.
+    MalformedMessage,
+    /// A block or header failing validation.
+    InvalidBlock,
+    /// A transaction that can never become valid, e.g. one with a bad signature.
+    InvalidTransaction,
+    /// A transaction the sender should have known would be rejected, e.g. a stale nonce.
+    RejectedTransaction,
+}
+
+impl Misbehaviour {
+    pub fn penalty(self) -> u32 {
+        match self {
+            Self::MalformedMessage | Self::InvalidBlock => BAN_THRESHOLD,
+            Self::InvalidTransaction => 20,
+            Self::RejectedTransaction => 2,
+        }
+    }
+}
+
+impl std::fmt::Display for Misbehaviour {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            Self::MalformedMessage => "malformed message",
+            Self::InvalidBlock => "invalid block",
+            Self::InvalidTransaction => "invalid transaction",
+            Self::RejectedTransaction => "rejected transaction",
+        };
+        f.write_str(description)
+    }
+}
+
+/// Connections from and to a banned address are refused until the ban expires.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ban {
+    pub address: IpAddr,
+    pub until: DateTime<Utc>,
+    pub reason: String,
+}
@@ -0,0 +1,46 @@
+use keta_crypto::PublicKey;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid public key: {0}")]
+    PublicKey(#[from] keta_crypto::PublicKeyError),
+
+    #[error("invalid address: {0}")]
+    Address(#[from] std::net::AddrParseError),
+}
+
+/// Where to reach a peer, optionally pinned to the identity it has to prove. Written as
+/// `<public key>@<ip>:<port>` or just `<ip>:<port>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerAddress {
+    pub identity: Option<PublicKey>,
+    pub address: SocketAddr,
+}
+
+impl FromStr for PeerAddress {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('@') {
+            Some((identity, address)) => Ok(Self {
+                identity: Some(identity.parse()?),
+                address: address.parse()?,
+            }),
+            None => Ok(Self {
+                identity: None,
+                address: s.parse()?,
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for PeerAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.identity {
+            Some(identity) => write!(f, "{}@{}", identity, self.address),
+            None => write!(f, "{}", self.address),
+        }
+    }
+}
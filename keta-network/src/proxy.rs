@@ -0,0 +1,147 @@
+//! Outbound connections through a SOCKS5 proxy (RFC 1928), without authentication.
+
+use std::net::SocketAddr;
+use std::str::FromStr;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+const VERSION: u8 = 5;
+
+const NO_AUTHENTICATION: u8 = 0;
+
+const NO_ACCEPTABLE_METHODS: u8 = 0xff;
+
+const CONNECT: u8 = 1;
+
+const IPV4: u8 = 1;
+
+const DOMAIN_NAME: u8 = 3;
+
+const IPV6: u8 = 4;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("unsupported proxy scheme in {0}, expected socks5://<host>:<port>")]
+    UnsupportedScheme(String),
+
+    #[error("invalid proxy address {0}, expected socks5://<host>:<port>")]
+    InvalidAddress(String),
+
+    #[error("proxy requires authentication")]
+    AuthenticationRequired,
+
+    #[error("proxy refused to connect: {0}")]
+    Refused(&'static str),
+
+    #[error("malformed reply from proxy")]
+    MalformedReply,
+}
+
+/// A SOCKS5 proxy, written as `socks5://<host>:<port>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proxy {
+    host: String,
+    port: u16,
+}
+
+impl FromStr for Proxy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let address = s
+            .strip_prefix("socks5://")
+            .ok_or_else(|| Error::UnsupportedScheme(s.to_string()))?;
+        let (host, port) = address
+            .rsplit_once(':')
+            .ok_or_else(|| Error::InvalidAddress(s.to_string()))?;
+        // Brackets around IPv6 addresses are not part of the host.
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let port = port
+            .parse()
+            .map_err(|_| Error::InvalidAddress(s.to_string()))?;
+        if host.is_empty() {
+            return Err(Error::InvalidAddress(s.to_string()));
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+impl std::fmt::Display for Proxy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "socks5://[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "socks5://{}:{}", self.host, self.port)
+        }
+    }
+}
+
+impl Proxy {
+    /// Opens a connection to `target` through the proxy.
+    pub async fn connect(&self, target: SocketAddr) -> Result<TcpStream, Error> {
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+
+        stream.write_all(&[VERSION, 1, NO_AUTHENTICATION]).await?;
+        let mut reply = [0; 2];
+        stream.read_exact(&mut reply).await?;
+        match reply {
+            [VERSION, NO_AUTHENTICATION] => {}
+            [VERSION, NO_ACCEPTABLE_METHODS] => return Err(Error::AuthenticationRequired),
+            _ => return Err(Error::MalformedReply),
+        }
+
+        let mut request = vec![VERSION, CONNECT, 0];
+        match target {
+            SocketAddr::V4(address) => {
+                request.push(IPV4);
+                request.extend_from_slice(&address.ip().octets());
+            }
+            SocketAddr::V6(address) => {
+                request.push(IPV6);
+                request.extend_from_slice(&address.ip().octets());
+            }
+        }
+        request.extend_from_slice(&target.port().to_be_bytes());
+        stream.write_all(&request).await?;
+
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply).await?;
+        if reply[0] != VERSION {
+            return Err(Error::MalformedReply);
+        }
+        if reply[1] != 0 {
+            return Err(Error::Refused(reply_message(reply[1])));
+        }
+        // The address the proxy bound for the connection, of no use here.
+        let length = match reply[3] {
+            IPV4 => 4,
+            IPV6 => 16,
+            DOMAIN_NAME => stream.read_u8().await? as usize,
+            _ => return Err(Error::MalformedReply),
+        };
+        let mut bound = vec![0; length + 2];
+        stream.read_exact(&mut bound).await?;
+        Ok(stream)
+    }
+}
+
+fn reply_message(code: u8) -> &'static str {
+    match code {
+        1 => "general failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
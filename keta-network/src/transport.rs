@@ -0,0 +1,281 @@
+//! Encrypted, authenticated connections. A Noise XX handshake sets up the session keys, and each
+//! side proves its node identity by signing its Noise static key with its identity key. Messages
+//! are then bincode-encoded, prefixed with a u32 big-endian length and sent as a sequence of
+//! encrypted Noise frames, each preceded by its u16 big-endian length.
+
+use crate::Direction;
+use crate::Error;
+use keta_crypto::Keypair;
+use keta_crypto::PublicKey;
+use keta_crypto::Signature;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde::Serialize;
+use snow::StatelessTransportState;
+use std::convert::TryFrom;
+use std::convert::TryInto;
+use std::sync::Arc;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWrite;
+use tokio::io::AsyncWriteExt;
+
+pub const MAX_MESSAGE_SIZE: u32 = 32 * 1024 * 1024;
+
+const NOISE_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+
+const MAX_FRAME_SIZE: usize = 65535;
+
+const TAG_SIZE: usize = 16;
+
+const MAX_CHUNK_SIZE: usize = MAX_FRAME_SIZE - TAG_SIZE;
+
+/// Prepended to the Noise static key before signing it, so the signature cannot be mistaken for
+/// anything else signed with the identity key.
+const STATIC_KEY_CONTEXT: &[u8] = b"keta-network noise static key:";
+
+/// Sent inside the handshake, binding the Noise static key to the node identity.
+#[derive(Serialize, Deserialize)]
+struct IdentityProof {
+    identity: PublicKey,
+    signature: Signature,
+}
+
+/// Keys a node secures its connections with.
+pub struct LocalKeys {
+    identity: PublicKey,
+    noise: snow::Keypair,
+    proof: Vec<u8>,
+}
+
+impl LocalKeys {
+    pub fn new(identity: &Keypair) -> Result<Self, Error> {
+        let noise = snow::Builder::new(noise_params()).generate_keypair()?;
+        let proof = IdentityProof {
+            identity: identity.public.clone(),
+            signature: identity.sign([STATIC_KEY_CONTEXT, &noise.public].concat()),
+        };
+        Ok(Self {
+            identity: identity.public.clone(),
+            noise,
+            proof: bincode::serialize(&proof)?,
+        })
+    }
+
+    pub fn identity(&self) -> &PublicKey {
+        &self.identity
+    }
+}
+
+impl std::fmt::Debug for LocalKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LocalKeys")
+            .field("identity", &self.identity)
+            .finish()
+    }
+}
+
+fn noise_params() -> snow::params::NoiseParams {
+    NOISE_PARAMS.parse().unwrap()
+}
+
+/// Runs the Noise handshake over `reader` and `writer`, returning the authenticated identity of the other
+/// side along with the halves of the secured connection.
+pub async fn secure<R, W>(
+    mut reader: R,
+    mut writer: W,
+    keys: &LocalKeys,
+    direction: Direction,
+) -> Result<(PublicKey, Reader<R>, Writer<W>), Error>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let builder = snow::Builder::new(noise_params()).local_private_key(&keys.noise.private);
+    let mut buffer = vec![0; MAX_FRAME_SIZE];
+    let mut payload = vec![0; MAX_FRAME_SIZE];
+    let (handshake, proof) = match direction {
+        Direction::Outbound => {
+            let mut handshake = builder.build_initiator()?;
+            // -> e
+            let length = handshake.write_message(&[], &mut buffer)?;
+            write_frame(&mut writer, &buffer[..length]).await?;
+            // <- e, ee, s, es
+            let frame = read_frame(&mut reader)
+                .await?
+                .ok_or(Error::ConnectionClosed)?;
+            let length = handshake.read_message(&frame, &mut payload)?;
+            let proof = payload[..length].to_vec();
+            // -> s, se
+            let length = handshake.write_message(&keys.proof, &mut buffer)?;
+            write_frame(&mut writer, &buffer[..length]).await?;
+            (handshake, proof)
+        }
+        Direction::Inbound => {
+            let mut handshake = builder.build_responder()?;
+            let frame = read_frame(&mut reader)
+                .await?
+                .ok_or(Error::ConnectionClosed)?;
+            handshake.read_message(&frame, &mut payload)?;
+            let length = handshake.write_message(&keys.proof, &mut buffer)?;
+            write_frame(&mut writer, &buffer[..length]).await?;
+            let frame = read_frame(&mut reader)
+                .await?
+                .ok_or(Error::ConnectionClosed)?;
+            let length = handshake.read_message(&frame, &mut payload)?;
+            (handshake, payload[..length].to_vec())
+        }
+    };
+
+    let remote_static = handshake
+        .get_remote_static()
+        .ok_or(Error::InvalidIdentity)?
+        .to_vec();
+    let proof: IdentityProof = bincode::deserialize(&proof).map_err(|_| Error::InvalidIdentity)?;
+    proof
+        .identity
+        .verify(
+            [STATIC_KEY_CONTEXT, &remote_static].concat(),
+            proof.signature,
+        )
+        .map_err(|_| Error::InvalidIdentity)?;
+
+    let transport = Arc::new(handshake.into_stateless_transport_mode()?);
+    let reader = Reader {
+        inner: reader,
+        transport: transport.clone(),
+        nonce: 0,
+    };
+    let writer = Writer {
+        inner: writer,
+        transport,
+        nonce: 0,
+    };
+    Ok((proof.identity, reader, writer))
+}
+
+async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, frame: &[u8]) -> Result<(), Error> {
+    // Frames never exceed `MAX_FRAME_SIZE`, Noise refuses to produce larger ones.
+    let length = u16::try_from(frame.len()).unwrap();
+    writer.write_all(&length.to_be_bytes()).await?;
+    writer.write_all(frame).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Returns `None` if the connection was closed on a frame boundary.
+async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<Vec<u8>>, Error> {
+    let mut length = [0; 2];
+    match reader.read(&mut length[..1]).await? {
+        0 => return Ok(None),
+        _ => reader.read_exact(&mut length[1..]).await?,
+    };
+    let mut frame = vec![0; u16::from_be_bytes(length) as usize];
+    reader.read_exact(&mut frame).await?;
+    Ok(Some(frame))
+}
+
+pub struct Writer<W> {
+    inner: W,
+    transport: Arc<StatelessTransportState>,
+    nonce: u64,
+}
+
+impl<W: AsyncWrite + Unpin> Writer<W> {
+    pub async fn write<T: Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        let encoded = bincode::serialize(value)?;
+        let length = u32::try_from(encoded.len()).unwrap_or(u32::MAX);
+        if length > MAX_MESSAGE_SIZE {
+            return Err(Error::MessageTooLarge(length));
+        }
+        let plaintext = [&length.to_be_bytes()[..], &encoded].concat();
+        let mut frame = vec![0; MAX_FRAME_SIZE];
+        for chunk in plaintext.chunks(MAX_CHUNK_SIZE) {
+            let length = self
+                .transport
+                .write_message(self.nonce, chunk, &mut frame)?;
+            self.nonce += 1;
+            let length = u16::try_from(length).unwrap();
+            self.inner.write_all(&length.to_be_bytes()).await?;
+            self.inner.write_all(&frame[..length as usize]).await?;
+        }
+        self.inner.flush().await?;
+        Ok(())
+    }
+}
+
+pub struct Reader<R> {
+    inner: R,
+    transport: Arc<StatelessTransportState>,
+    nonce: u64,
+}
+
+impl<R: AsyncRead + Unpin> Reader<R> {
+    /// Returns `None` if the connection was closed on a message boundary.
+    pub async fn read<T: DeserializeOwned>(&mut self) -> Result<Option<T>, Error> {
+        let mut plaintext = match self.read_chunk().await? {
+            Some(chunk) => chunk,
+            None => return Ok(None),
+        };
+        if plaintext.len() < 4 {
+            return Err(Error::MalformedFrame);
+        }
+        let length = u32::from_be_bytes(plaintext[..4].try_into().unwrap());
+        if length > MAX_MESSAGE_SIZE {
+            return Err(Error::MessageTooLarge(length));
+        }
+        let total = 4 + length as usize;
+        while plaintext.len() < total {
+            let chunk = self.read_chunk().await?.ok_or(Error::ConnectionClosed)?;
+            plaintext.extend_from_slice(&chunk);
+        }
+        if plaintext.len() > total {
+            return Err(Error::MalformedFrame);
+        }
+        Ok(Some(bincode::deserialize(&plaintext[4..])?))
+    }
+
+    async fn read_chunk(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        let frame = match read_frame(&mut self.inner).await? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        let mut chunk = vec![0; frame.len()];
+        let length = self
+            .transport
+            .read_message(self.nonce, &frame, &mut chunk)?;
+        self.nonce += 1;
+        chunk.truncate(length);
+        Ok(Some(chunk))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LocalKeys;
+    use crate::Direction;
+    use keta_crypto::Keypair;
+
+    #[tokio::test]
+    async fn message_spanning_frames() {
+        let (alice, bob) = (Keypair::generate(), Keypair::generate());
+        let (a, b) = tokio::io::duplex(1024);
+        let (a_reader, a_writer) = tokio::io::split(a);
+        let (b_reader, b_writer) = tokio::io::split(b);
+        let alice_keys = LocalKeys::new(&alice).unwrap();
+        let bob_keys = LocalKeys::new(&bob).unwrap();
+        let (alice_side, bob_side) = tokio::join!(
+            super::secure(a_reader, a_writer, &alice_keys, Direction::Outbound),
+            super::secure(b_reader, b_writer, &bob_keys, Direction::Inbound),
+        );
+        let (bob_identity, _, mut writer) = alice_side.unwrap();
+        let (alice_identity, mut reader, _) = bob_side.unwrap();
+        assert_eq!(bob_identity, bob.public);
+        assert_eq!(alice_identity, alice.public);
+
+        let message: Vec<u8> = (0..200_000).map(|i| i as u8).collect();
+        let (written, read) = tokio::join!(writer.write(&message), reader.read::<Vec<u8>>());
+        written.unwrap();
+        assert_eq!(read.unwrap(), Some(message));
+    }
+}
@@ -1,11 +1,30 @@
+use super::Error;
 use keta_core::account::Account;
 use keta_core::account::Address;
+use sled::IVec;
+use std::convert::TryFrom;
 
 #[derive(Debug, Clone)]
 pub struct Tree {
     tree: sled::Tree,
 }
 
+impl Tree {
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = Result<(Address, Account), Error>> {
+        fn key_value_to_item(key: IVec, value: IVec) -> Result<(Address, Account), Error> {
+            let address =
+                Address::try_from(key).map_err(|err| Error::InvalidKey(err.to_string()))?;
+            let account: Account = bincode::deserialize(&value)?;
+            Ok((address, account))
+        }
+
+        self.tree.iter().map(|item| match item {
+            Ok((key, value)) => key_value_to_item(key, value),
+            Err(err) => Err(Error::SledError(err)),
+        })
+    }
+}
+
 impl crate::Tree<Address, Account> for Tree {}
 
 impl AsRef<sled::Tree> for Tree {
@@ -0,0 +1,61 @@
+use super::Error;
+use chrono::DateTime;
+use chrono::Utc;
+use serde::Deserialize;
+use serde::Serialize;
+use std::net::IpAddr;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ban {
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub until: DateTime<Utc>,
+    pub reason: String,
+}
+
+/// Banned peer addresses, keyed by their textual form.
+#[derive(Debug, Clone)]
+pub struct Tree {
+    tree: sled::Tree,
+}
+
+impl Tree {
+    pub fn insert(&self, address: &IpAddr, ban: &Ban) -> Result<(), Error> {
+        self.tree
+            .insert(address.to_string(), bincode::serialize(ban)?)?;
+        self.tree.flush()?;
+        Ok(())
+    }
+
+    pub fn remove(&self, address: &IpAddr) -> Result<(), Error> {
+        self.tree.remove(address.to_string())?;
+        self.tree.flush()?;
+        Ok(())
+    }
+
+    pub fn clear(&self) -> Result<(), Error> {
+        self.tree.clear()?;
+        self.tree.flush()?;
+        Ok(())
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = Result<(IpAddr, Ban), Error>> {
+        self.tree.iter().map(|item| {
+            let (key, value) = item?;
+            let key = String::from_utf8(key.to_vec())?;
+            let address = key.parse().map_err(|_| Error::InvalidKey(key))?;
+            Ok((address, bincode::deserialize(&value)?))
+        })
+    }
+}
+
+impl AsRef<sled::Tree> for Tree {
+    fn as_ref(&self) -> &sled::Tree {
+        &self.tree
+    }
+}
+
+impl From<sled::Tree> for Tree {
+    fn from(tree: sled::Tree) -> Self {
+        Self { tree }
+    }
+}
@@ -26,6 +26,17 @@ impl Tree {
 
         iter
     }
+
+    /// Iterates over blocks starting at `index`.
+    pub fn iter_from(
+        &self,
+        index: &block::Index,
+    ) -> impl DoubleEndedIterator<Item = Result<HashedBlock, Error>> {
+        self.tree.range(index.clone()..).map(|item| {
+            let (_, value) = item?;
+            Ok(bincode::deserialize(&value)?)
+        })
+    }
 }
 
 impl crate::Tree<block::Index, HashedBlock> for Tree {}
@@ -1,10 +1,31 @@
+use keta_core::account::Account;
+use keta_core::account::Address;
+use keta_core::block;
+use keta_core::block::HashedBlock;
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
 
 mod accounts;
+mod bans;
 mod blocks;
+mod mempool;
+mod meta;
+pub mod migrations;
+mod peers;
+mod transactions;
 
 pub use accounts::Tree as AccountsTree;
+pub use bans::Ban;
+pub use bans::Tree as BansTree;
 pub use blocks::Tree as BlocksTree;
+pub use mempool::Entry as MempoolEntry;
+pub use mempool::Tree as MempoolTree;
+pub use meta::Tree as MetaTree;
+pub use meta::SNAPSHOT_BASE_KEY;
+pub use migrations::SCHEMA_VERSION;
+pub use peers::Peer;
+pub use peers::Tree as PeersTree;
+pub use transactions::Tree as TransactionsTree;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -19,6 +40,9 @@ pub enum Error {
 
     #[error("utf8 error: {0}")]
     Utf8Error(#[from] std::string::FromUtf8Error),
+
+    #[error("database schema v{found} is newer than supported v{supported}, upgrade the node")]
+    UnsupportedSchemaVersion { found: u32, supported: u32 },
 }
 
 pub trait Tree<K, V>
@@ -37,7 +61,7 @@ where
     fn get(&self, key: &K) -> Result<Option<V>, Error> {
         let tree = self.as_ref();
         match tree.get(key.as_ref()) {
-            Ok(Some(value)) => Ok(bincode::deserialize(&value)?),
+            Ok(Some(value)) => Ok(Some(bincode::deserialize(&value)?)),
             Ok(None) => Ok(None),
             Err(err) => Err(Error::SledError(err)),
         }
@@ -53,14 +77,207 @@ where
 pub struct Database {
     pub blocks: blocks::Tree,
     pub accounts: accounts::Tree,
+    pub mempool: mempool::Tree,
+    pub meta: meta::Tree,
+    pub peers: peers::Tree,
+    pub bans: bans::Tree,
+    pub transactions: transactions::Tree,
 }
 
 impl Database {
     pub fn new(path: impl AsRef<std::path::Path>) -> Result<Self, Error> {
-        let database = sled::open(path)?;
+        Self::open(sled::open(path)?)
+    }
+
+    /// Database removed once dropped, for tests.
+    pub fn temporary() -> Result<Self, Error> {
+        Self::open(sled::Config::new().temporary(true).open()?)
+    }
+
+    pub(crate) fn open(database: sled::Db) -> Result<Self, Error> {
+        let meta = MetaTree::from(database.open_tree("meta")?);
+        migrations::run(&database, &meta)?;
         Ok(Self {
             blocks: BlocksTree::from(database.open_tree("blocks")?),
             accounts: AccountsTree::from(database.open_tree("accounts")?),
+            mempool: MempoolTree::from(database.open_tree("mempool")?),
+            meta,
+            peers: PeersTree::from(database.open_tree("peers")?),
+            bans: BansTree::from(database.open_tree("bans")?),
+            transactions: TransactionsTree::from(database.open_tree("transactions")?),
         })
     }
+
+    /// Stores a new block together with the accounts it modified, indexes its transactions and
+    /// removes them from the mempool, in a single transaction.
+    pub fn commit_block<'a>(
+        &self,
+        block: &HashedBlock,
+        accounts: impl IntoIterator<Item = (&'a Address, &'a Account)>,
+    ) -> Result<(), Error> {
+        use sled::transaction::ConflictableTransactionError;
+        use sled::transaction::TransactionError;
+        use sled::Transactional;
+
+        let serialized_block = bincode::serialize(block)?;
+        let serialized_index = bincode::serialize(&block.index)?;
+        let serialized_accounts = accounts
+            .into_iter()
+            .map(|(address, account)| Ok((address.clone(), bincode::serialize(account)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let transaction_hashes: Vec<_> = block
+            .transactions
+            .iter()
+            .map(|transaction| transaction.hash())
+            .collect();
+
+        (
+            self.blocks.as_ref(),
+            self.accounts.as_ref(),
+            self.mempool.as_ref(),
+            self.transactions.as_ref(),
+        )
+            .transaction(|(blocks, accounts, mempool, transactions)| {
+                for (address, account) in &serialized_accounts {
+                    accounts.insert(address.as_ref(), account.as_slice())?;
+                }
+                for hash in &transaction_hashes {
+                    mempool.remove(hash.as_ref())?;
+                    transactions.insert(hash.as_ref(), serialized_index.as_slice())?;
+                }
+                blocks.insert(block.index.as_ref(), serialized_block.as_slice())?;
+                Ok::<_, ConflictableTransactionError<std::convert::Infallible>>(())
+            })
+            .map_err(|err| match err {
+                TransactionError::Abort(never) => match never {},
+                TransactionError::Storage(err) => Error::SledError(err),
+            })?;
+        self.blocks.as_ref().flush()?;
+        self.accounts.as_ref().flush()?;
+        self.mempool.as_ref().flush()?;
+        self.transactions.as_ref().flush()?;
+        Ok(())
+    }
+
+    /// Removes every block above `index` together with its transactions from the index, and
+    /// replaces the accounts with `accounts`, the complete state as of `index`, in a single
+    /// transaction. Returns the removed blocks in ascending order.
+    pub fn rewind(
+        &self,
+        index: &block::Index,
+        accounts: &BTreeMap<Address, Account>,
+    ) -> Result<Vec<HashedBlock>, Error> {
+        use sled::transaction::ConflictableTransactionError;
+        use sled::transaction::TransactionError;
+        use sled::Transactional;
+
+        let removed = self
+            .blocks
+            .iter_from(&index.increment())
+            .collect::<Result<Vec<_>, _>>()?;
+        let transaction_hashes: Vec<_> = removed
+            .iter()
+            .flat_map(|block| &block.transactions)
+            .map(|transaction| transaction.hash())
+            .collect();
+        let stale_addresses = self
+            .accounts
+            .iter()
+            .filter_map(|account| match account {
+                Ok((address, _)) if accounts.contains_key(&address) => None,
+                Ok((address, _)) => Some(Ok(address)),
+                Err(err) => Some(Err(err)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let serialized_accounts = accounts
+            .iter()
+            .map(|(address, account)| Ok((address, bincode::serialize(account)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        (
+            self.blocks.as_ref(),
+            self.accounts.as_ref(),
+            self.transactions.as_ref(),
+        )
+            .transaction(|(blocks, accounts, transactions)| {
+                for block in &removed {
+                    blocks.remove(block.index.as_ref())?;
+                }
+                for hash in &transaction_hashes {
+                    transactions.remove(hash.as_ref())?;
+                }
+                for address in &stale_addresses {
+                    accounts.remove(address.as_ref())?;
+                }
+                for (address, account) in &serialized_accounts {
+                    accounts.insert(address.as_ref(), account.as_slice())?;
+                }
+                Ok::<_, ConflictableTransactionError<std::convert::Infallible>>(())
+            })
+            .map_err(|err| match err {
+                TransactionError::Abort(never) => match never {},
+                TransactionError::Storage(err) => Error::SledError(err),
+            })?;
+        self.blocks.as_ref().flush()?;
+        self.accounts.as_ref().flush()?;
+        self.transactions.as_ref().flush()?;
+        Ok(removed)
+    }
+
+    /// Replaces every tree that can be rebuilt by replaying the blocks: the accounts with
+    /// `accounts`, the complete state as of the tip, and the transaction index with the
+    /// transactions of `blocks`, in a single transaction.
+    pub fn replace_derived<'a>(
+        &self,
+        accounts: &BTreeMap<Address, Account>,
+        blocks: impl IntoIterator<Item = &'a HashedBlock>,
+    ) -> Result<(), Error> {
+        use sled::transaction::ConflictableTransactionError;
+        use sled::transaction::TransactionError;
+        use sled::Transactional;
+
+        let keys = |tree: &sled::Tree| {
+            tree.iter()
+                .keys()
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(Error::SledError)
+        };
+        let stale_accounts = keys(self.accounts.as_ref())?;
+        let stale_transactions = keys(self.transactions.as_ref())?;
+        let serialized_accounts = accounts
+            .iter()
+            .map(|(address, account)| Ok((address, bincode::serialize(account)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut indexed_transactions = Vec::new();
+        for block in blocks {
+            let index = bincode::serialize(&block.index)?;
+            for transaction in &block.transactions {
+                indexed_transactions.push((transaction.hash(), index.clone()));
+            }
+        }
+
+        (self.accounts.as_ref(), self.transactions.as_ref())
+            .transaction(|(accounts, transactions)| {
+                for key in &stale_accounts {
+                    accounts.remove(key)?;
+                }
+                for key in &stale_transactions {
+                    transactions.remove(key)?;
+                }
+                for (address, account) in &serialized_accounts {
+                    accounts.insert(address.as_ref(), account.as_slice())?;
+                }
+                for (hash, index) in &indexed_transactions {
+                    transactions.insert(hash.as_ref(), index.as_slice())?;
+                }
+                Ok::<_, ConflictableTransactionError<std::convert::Infallible>>(())
+            })
+            .map_err(|err| match err {
+                TransactionError::Abort(never) => match never {},
+                TransactionError::Storage(err) => Error::SledError(err),
+            })?;
+        self.accounts.as_ref().flush()?;
+        self.transactions.as_ref().flush()?;
+        Ok(())
+    }
 }
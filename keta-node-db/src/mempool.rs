@@ -0,0 +1,48 @@
+use super::Error;
+use chrono::DateTime;
+use chrono::Utc;
+use keta_core::transaction::SignedTransaction;
+use keta_crypto::Hash;
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub transaction: SignedTransaction,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub received_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Tree {
+    tree: sled::Tree,
+}
+
+impl Tree {
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = Result<Entry, Error>> {
+        self.tree.iter().map(|item| {
+            let (_, value) = item?;
+            Ok(bincode::deserialize(&value)?)
+        })
+    }
+
+    pub fn remove(&self, hash: &Hash) -> Result<(), Error> {
+        self.tree.remove(hash)?;
+        self.tree.flush()?;
+        Ok(())
+    }
+}
+
+impl crate::Tree<Hash, Entry> for Tree {}
+
+impl AsRef<sled::Tree> for Tree {
+    fn as_ref(&self) -> &sled::Tree {
+        &self.tree
+    }
+}
+
+impl From<sled::Tree> for Tree {
+    fn from(tree: sled::Tree) -> Self {
+        Self { tree }
+    }
+}
@@ -0,0 +1,47 @@
+use super::Error;
+
+const SCHEMA_VERSION_KEY: &str = "schema-version";
+
+/// Key of the state snapshot the database was bootstrapped from, if any.
+pub const SNAPSHOT_BASE_KEY: &str = "snapshot-base";
+
+#[derive(Debug, Clone)]
+pub struct Tree {
+    tree: sled::Tree,
+}
+
+impl Tree {
+    pub fn get<V: serde::de::DeserializeOwned>(&self, key: &str) -> Result<Option<V>, Error> {
+        match self.tree.get(key)? {
+            Some(value) => Ok(Some(bincode::deserialize(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn insert<V: serde::Serialize>(&self, key: &str, value: &V) -> Result<(), Error> {
+        self.tree.insert(key, bincode::serialize(value)?)?;
+        self.tree.flush()?;
+        Ok(())
+    }
+
+    /// Returns `None` for databases created before the schema version was tracked.
+    pub fn schema_version(&self) -> Result<Option<u32>, Error> {
+        self.get(SCHEMA_VERSION_KEY)
+    }
+
+    pub fn set_schema_version(&self, version: u32) -> Result<(), Error> {
+        self.insert(SCHEMA_VERSION_KEY, &version)
+    }
+}
+
+impl AsRef<sled::Tree> for Tree {
+    fn as_ref(&self) -> &sled::Tree {
+        &self.tree
+    }
+}
+
+impl From<sled::Tree> for Tree {
+    fn from(tree: sled::Tree) -> Self {
+        Self { tree }
+    }
+}
@@ -0,0 +1,482 @@
+mod v1;
+mod v2;
+mod v3;
+mod v4;
+mod v5;
+mod v6;
+
+use super::Error;
+use super::MetaTree;
+
+/// A single schema upgrade, moving the database from version `index` to `index + 1`,
+/// where `index` is the position in [`MIGRATIONS`].
+pub struct Migration {
+    pub description: &'static str,
+    pub run: fn(&sled::Db) -> Result<(), Error>,
+}
+
+/// Every migration ever shipped, in order. Never reorder or remove entries, only append.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        description: "track schema version",
+        run: |_| Ok(()),
+    },
+    Migration {
+        description: "add nonces and fees to transactions, nonces to accounts",
+        run: v2::migrate,
+    },
+    Migration {
+        description: "add expiry heights to transactions",
+        run: v3::migrate,
+    },
+    Migration {
+        description: "hash blocks over headers with a transactions root",
+        run: v4::migrate,
+    },
+    Migration {
+        description: "index transactions by hash",
+        run: v5::migrate,
+    },
+    Migration {
+        description: "remember when peers were last connected to",
+        run: v6::migrate,
+    },
+];
+
+/// Version written by this build of the node.
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// Brings the database up to [`SCHEMA_VERSION`], refusing to touch databases written by a newer
+/// node.
+pub(crate) fn run(database: &sled::Db, meta: &MetaTree) -> Result<(), Error> {
+    let version = match meta.schema_version()? {
+        Some(version) => version,
+        None if is_empty(database)? => {
+            meta.set_schema_version(SCHEMA_VERSION)?;
+            return Ok(());
+        }
+        None => 0,
+    };
+
+    if version > SCHEMA_VERSION {
+        return Err(Error::UnsupportedSchemaVersion {
+            found: version,
+            supported: SCHEMA_VERSION,
+        });
+    }
+    if version == SCHEMA_VERSION {
+        return Ok(());
+    }
+
+    tracing::info!(
+        "Migrating database schema from v{} to v{}",
+        version,
+        SCHEMA_VERSION
+    );
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let to = from as u32 + 1;
+        tracing::info!(
+            "[{}/{}] v{} -> v{}: {}",
+            to - version,
+            SCHEMA_VERSION - version,
+            from,
+            to,
+            migration.description
+        );
+        (migration.run)(database)?;
+        database.flush()?;
+        // Stamp after every step, so an interrupted upgrade resumes where it stopped.
+        meta.set_schema_version(to)?;
+    }
+    tracing::info!("Database schema is up to date");
+
+    Ok(())
+}
+
+const PROGRESS_INTERVAL: usize = 10_000;
+
+/// Rewrites every record of `tree` through `convert`, logging progress along the way. Records
+/// for which `convert` returns `None` are removed.
+fn convert_tree<Old, New>(
+    tree: &sled::Tree,
+    name: &str,
+    mut convert: impl FnMut(&[u8], Old) -> Result<Option<New>, Error>,
+) -> Result<(), Error>
+where
+    Old: serde::de::DeserializeOwned,
+    New: serde::Serialize,
+{
+    let total = tree.len();
+    let mut batch = sled::Batch::default();
+    for (converted, item) in tree.iter().enumerate() {
+        let (key, value) = item?;
+        match convert(&key, bincode::deserialize(&value)?)? {
+            Some(record) => batch.insert(key, bincode::serialize(&record)?),
+            None => batch.remove(key),
+        }
+        if (converted + 1) % PROGRESS_INTERVAL == 0 {
+            tree.apply_batch(std::mem::take(&mut batch))?;
+            tracing::info!("Converted {}/{} {}", converted + 1, total, name);
+        }
+    }
+    tree.apply_batch(batch)?;
+    tracing::info!("Converted {} {}", total, name);
+    Ok(())
+}
+
+fn is_empty(database: &sled::Db) -> Result<bool, Error> {
+    for name in database.tree_names() {
+        if !database.open_tree(name)?.is_empty() {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::v1;
+    use super::v2;
+    use super::v3;
+    use super::v5;
+    use super::v6;
+    use super::SCHEMA_VERSION;
+    use crate::Database;
+    use crate::Error;
+    use crate::Tree;
+    use keta_core::block;
+    use keta_core::block::HashedBlock;
+    use keta_crypto::Hash;
+    use keta_crypto::Keypair;
+
+    fn temporary() -> sled::Db {
+        sled::Config::new().temporary(true).open().unwrap()
+    }
+
+    #[test]
+    fn fresh_database_is_stamped() {
+        let database = Database::open(temporary()).unwrap();
+        assert_eq!(
+            database.meta.schema_version().unwrap(),
+            Some(SCHEMA_VERSION)
+        );
+    }
+
+    #[test]
+    fn legacy_database_is_migrated() {
+        let db = temporary();
+        let account = bincode::serialize(&v1::Account { balance: 5 }).unwrap();
+        db.open_tree("accounts")
+            .unwrap()
+            .insert(Keypair::generate().public, account)
+            .unwrap();
+        let database = Database::open(db).unwrap();
+        assert_eq!(
+            database.meta.schema_version().unwrap(),
+            Some(SCHEMA_VERSION)
+        );
+    }
+
+    #[test]
+    fn newer_database_is_refused() {
+        let db = temporary();
+        Database::open(db.clone())
+            .unwrap()
+            .meta
+            .set_schema_version(SCHEMA_VERSION + 1)
+            .unwrap();
+        match Database::open(db) {
+            Err(Error::UnsupportedSchemaVersion { found, supported }) => {
+                assert_eq!(found, SCHEMA_VERSION + 1);
+                assert_eq!(supported, SCHEMA_VERSION);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn v1_to_v2() {
+        let (alice, bob) = (Keypair::generate(), Keypair::generate());
+        let transaction = |value| {
+            let transaction = v1::Transaction {
+                from: alice.public.clone(),
+                to: bob.public.clone(),
+                value,
+            };
+            v1::SignedTransaction {
+                signature: alice.sign(bincode::serialize(&transaction).unwrap()),
+                transaction,
+            }
+        };
+        let block = |index: u64, transactions| v1::HashedBlock {
+            block: v1::Block {
+                index: block::Index::from(index),
+                timestamp: chrono::MIN_DATETIME,
+                transactions,
+                prev_hash: Hash::ZERO,
+            },
+            hash: Hash::ZERO,
+            nonce: 0,
+        };
+
+        let db = temporary();
+        let meta = db.open_tree("meta").unwrap();
+        meta.insert("schema-version", bincode::serialize(&1u32).unwrap())
+            .unwrap();
+        let blocks = db.open_tree("blocks").unwrap();
+        for block in [
+            block(0, vec![]),
+            block(1, vec![transaction(10), transaction(20)]),
+        ] {
+            blocks
+                .insert(&block.block.index, bincode::serialize(&block).unwrap())
+                .unwrap();
+        }
+        let accounts = db.open_tree("accounts").unwrap();
+        for (address, balance) in [(&alice.public, 70), (&bob.public, 30)] {
+            let account = v1::Account { balance };
+            accounts
+                .insert(address, bincode::serialize(&account).unwrap())
+                .unwrap();
+        }
+        db.open_tree("mempool")
+            .unwrap()
+            .insert(Hash::ZERO, vec![0])
+            .unwrap();
+
+        let database = Database::open(db).unwrap();
+        let block = database
+            .blocks
+            .get(&block::Index::from(1))
+            .unwrap()
+            .unwrap();
+        let nonces: Vec<_> = block
+            .transactions
+            .iter()
+            .map(|transaction| (transaction.nonce, transaction.fee))
+            .collect();
+        assert_eq!(nonces, vec![(0, 0), (1, 0)]);
+        let alice = database.accounts.get(&alice.public).unwrap().unwrap();
+        assert_eq!((alice.balance, alice.nonce), (70, 2));
+        let bob = database.accounts.get(&bob.public).unwrap().unwrap();
+        assert_eq!((bob.balance, bob.nonce), (30, 0));
+        assert_eq!(database.mempool.len(), 0);
+        let base: v3::Snapshot = database
+            .meta
+            .get(crate::SNAPSHOT_BASE_KEY)
+            .unwrap()
+            .unwrap();
+        assert_eq!(base.block.hash, block.hash);
+        assert_eq!(base.accounts.len(), 2);
+    }
+
+    #[test]
+    fn v2_to_v3() {
+        let keypair = Keypair::generate();
+        let transaction = v2::Transaction {
+            from: keypair.public.clone(),
+            to: keypair.public.clone(),
+            value: 1,
+            fee: 2,
+            nonce: 0,
+        };
+        let transaction = v2::SignedTransaction {
+            signature: keypair.sign(bincode::serialize(&transaction).unwrap()),
+            transaction,
+        };
+        let block = v2::HashedBlock {
+            block: v2::Block {
+                index: block::Index::from(1),
+                timestamp: chrono::MIN_DATETIME,
+                transactions: vec![transaction],
+                prev_hash: Hash::ZERO,
+            },
+            hash: Hash::ZERO,
+            nonce: 0,
+        };
+
+        let db = temporary();
+        let meta = db.open_tree("meta").unwrap();
+        meta.insert("schema-version", bincode::serialize(&2u32).unwrap())
+            .unwrap();
+        db.open_tree("blocks")
+            .unwrap()
+            .insert(&block.block.index, bincode::serialize(&block).unwrap())
+            .unwrap();
+        let account = v2::Account {
+            balance: 7,
+            nonce: 1,
+        };
+        db.open_tree("accounts")
+            .unwrap()
+            .insert(&keypair.public, bincode::serialize(&account).unwrap())
+            .unwrap();
+
+        let database = Database::open(db).unwrap();
+        let block = database
+            .blocks
+            .get(&block::Index::from(1))
+            .unwrap()
+            .unwrap();
+        assert_eq!(block.transactions[0].valid_until, None);
+        assert_eq!(block.transactions[0].nonce, 0);
+        let base: v3::Snapshot = database
+            .meta
+            .get(crate::SNAPSHOT_BASE_KEY)
+            .unwrap()
+            .unwrap();
+        assert_eq!(base.block.block.index, block.index);
+        assert_eq!(base.accounts[&keypair.public].balance, 7);
+    }
+
+    #[test]
+    fn v3_to_v4_replaces_genesis() {
+        let genesis = v3::HashedBlock {
+            block: v3::Block {
+                index: block::Index::ZERO,
+                timestamp: chrono::MIN_DATETIME,
+                transactions: Vec::new(),
+                prev_hash: Hash::ZERO,
+            },
+            hash: Hash::ZERO,
+            nonce: 0,
+        };
+        let db = temporary();
+        db.open_tree("meta")
+            .unwrap()
+            .insert("schema-version", bincode::serialize(&3u32).unwrap())
+            .unwrap();
+        db.open_tree("blocks")
+            .unwrap()
+            .insert(&genesis.block.index, bincode::serialize(&genesis).unwrap())
+            .unwrap();
+
+        let database = Database::open(db).unwrap();
+        assert_eq!(database.blocks.len(), 0);
+        assert!(database
+            .meta
+            .get::<v3::Snapshot>(crate::SNAPSHOT_BASE_KEY)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn v4_to_v5_indexes_transactions() {
+        let keypair = Keypair::generate();
+        let transaction = v3::Transaction {
+            from: keypair.public.clone(),
+            to: keypair.public.clone(),
+            value: 1,
+            fee: 2,
+            nonce: 0,
+            valid_until: None,
+        };
+        let transaction = v3::SignedTransaction {
+            signature: keypair.sign(bincode::serialize(&transaction).unwrap()),
+            transaction,
+        };
+        let block = v5::HashedBlock {
+            block: v3::Block {
+                index: block::Index::from(3),
+                timestamp: chrono::MIN_DATETIME,
+                transactions: vec![transaction.clone()],
+                prev_hash: Hash::ZERO,
+            },
+            hash: Hash::ZERO,
+            nonce: 0,
+        };
+        let db = temporary();
+        db.open_tree("meta")
+            .unwrap()
+            .insert("schema-version", bincode::serialize(&4u32).unwrap())
+            .unwrap();
+        db.open_tree("blocks")
+            .unwrap()
+            .insert(&block.block.index, bincode::serialize(&block).unwrap())
+            .unwrap();
+
+        let database = Database::open(db).unwrap();
+        let hash = Hash::new(bincode::serialize(&transaction).unwrap());
+        assert_eq!(
+            database.transactions.get(&hash).unwrap(),
+            Some(block::Index::from(3))
+        );
+    }
+
+    #[test]
+    fn v5_to_v6_marks_peers_not_connected() {
+        let peer = v6::LegacyPeer {
+            last_seen: chrono::Utc::now(),
+            failures: 1,
+            last_failure: chrono::Utc::now(),
+        };
+        let db = temporary();
+        db.open_tree("meta")
+            .unwrap()
+            .insert("schema-version", bincode::serialize(&5u32).unwrap())
+            .unwrap();
+        db.open_tree("peers")
+            .unwrap()
+            .insert("127.0.0.1:5455", bincode::serialize(&peer).unwrap())
+            .unwrap();
+
+        let database = Database::open(db).unwrap();
+        let stored = database
+            .peers
+            .get(&"127.0.0.1:5455".parse().unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.failures, 1);
+        assert_eq!(stored.last_connected, None);
+    }
+
+    /// The newest frozen schema must encode exactly like the current types.
+    #[test]
+    fn latest_schema_matches_current_types() {
+        use keta_core::transaction::Transaction;
+
+        let keypair = Keypair::generate();
+        let transaction = Transaction {
+            from: keypair.public.clone(),
+            to: keypair.public.clone(),
+            value: 1,
+            fee: 2,
+            nonce: 3,
+            valid_until: Some(5),
+        }
+        .sign(&keypair);
+        let block = keta_core::block::Block::generate(
+            &keta_core::chain_spec::ChainSpec::MAINNET.genesis(),
+            vec![transaction],
+        );
+        let block = HashedBlock {
+            hash: block.hash_with_nonce(4),
+            block,
+            nonce: 4,
+        };
+
+        let encoded = bincode::serialize(&block).unwrap();
+        let frozen: v5::HashedBlock = bincode::deserialize(&encoded).unwrap();
+        assert_eq!(bincode::serialize(&frozen).unwrap(), encoded);
+
+        let account = keta_core::account::Account {
+            balance: 1,
+            nonce: 2,
+        };
+        let encoded = bincode::serialize(&account).unwrap();
+        // Accounts last changed in v2.
+        let frozen: v3::Account = bincode::deserialize(&encoded).unwrap();
+        assert_eq!(bincode::serialize(&frozen).unwrap(), encoded);
+
+        let now = chrono::Utc::now();
+        let peer = crate::Peer {
+            last_seen: now,
+            failures: 1,
+            last_failure: now,
+            last_connected: Some(now),
+        };
+        let encoded = bincode::serialize(&peer).unwrap();
+        let frozen: v6::Peer = bincode::deserialize(&encoded).unwrap();
+        assert_eq!(bincode::serialize(&frozen).unwrap(), encoded);
+    }
+}
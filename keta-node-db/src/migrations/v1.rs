@@ -0,0 +1,45 @@
+//! Records as stored by schema v1. Frozen, never change these.
+
+use chrono::DateTime;
+use chrono::Utc;
+use keta_core::account::Address;
+use keta_core::block::Index;
+use keta_crypto::Hash;
+use keta_crypto::Nonce;
+use keta_crypto::Signature;
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    pub balance: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transaction {
+    pub from: Address,
+    pub to: Address,
+    pub value: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedTransaction {
+    pub signature: Signature,
+    pub transaction: Transaction,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Block {
+    pub index: Index,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub timestamp: DateTime<Utc>,
+    pub transactions: Vec<SignedTransaction>,
+    pub prev_hash: Hash,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HashedBlock {
+    pub block: Block,
+    pub hash: Hash,
+    pub nonce: Nonce,
+}
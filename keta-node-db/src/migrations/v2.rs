@@ -0,0 +1,148 @@
+//! Records as stored by schema v2, which added nonces and fees to transactions and nonces to
+//! accounts. Frozen, never change these.
+
+use super::v1;
+use crate::Error;
+use crate::SNAPSHOT_BASE_KEY;
+use chrono::DateTime;
+use chrono::Utc;
+use keta_core::account::Address;
+use keta_core::block::Index;
+use keta_crypto::Hash;
+use keta_crypto::Nonce;
+use keta_crypto::Signature;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    pub balance: u64,
+    pub nonce: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transaction {
+    pub from: Address,
+    pub to: Address,
+    pub value: u64,
+    pub fee: u64,
+    pub nonce: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedTransaction {
+    pub signature: Signature,
+    pub transaction: Transaction,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Block {
+    pub index: Index,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub timestamp: DateTime<Utc>,
+    pub transactions: Vec<SignedTransaction>,
+    pub prev_hash: Hash,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HashedBlock {
+    pub block: Block,
+    pub hash: Hash,
+    pub nonce: Nonce,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub block: HashedBlock,
+    pub state_root: Hash,
+    pub accounts: BTreeMap<Address, Account>,
+}
+
+/// Legacy transactions get sequential nonces per sender and no fee. Their signatures cover the
+/// v1 encoding, so the converted blocks can't be validated again: the database is rebased onto
+/// a snapshot of the current tip, and replays start from there.
+pub fn migrate(database: &sled::Db) -> Result<(), Error> {
+    let blocks = database.open_tree("blocks")?;
+    let mut nonces: HashMap<Address, u64> = HashMap::new();
+    let mut tip = None;
+    super::convert_tree(&blocks, "blocks", |_, block: v1::HashedBlock| {
+        let transactions = block
+            .block
+            .transactions
+            .into_iter()
+            .map(|transaction| {
+                let nonce = nonces
+                    .entry(transaction.transaction.from.clone())
+                    .or_default();
+                *nonce += 1;
+                SignedTransaction {
+                    signature: transaction.signature,
+                    transaction: Transaction {
+                        from: transaction.transaction.from,
+                        to: transaction.transaction.to,
+                        value: transaction.transaction.value,
+                        fee: 0,
+                        nonce: *nonce - 1,
+                    },
+                }
+            })
+            .collect();
+        let block = HashedBlock {
+            block: Block {
+                index: block.block.index,
+                timestamp: block.block.timestamp,
+                transactions,
+                prev_hash: block.block.prev_hash,
+            },
+            hash: block.hash,
+            nonce: block.nonce,
+        };
+        tip = Some(block.clone());
+        Ok(Some(block))
+    })?;
+
+    let mut accounts = BTreeMap::new();
+    let accounts_tree = database.open_tree("accounts")?;
+    super::convert_tree(&accounts_tree, "accounts", |key, account: v1::Account| {
+        let address = Address::try_from(key).map_err(|err| Error::InvalidKey(err.to_string()))?;
+        let account = Account {
+            balance: account.balance,
+            nonce: nonces.get(&address).copied().unwrap_or(0),
+        };
+        accounts.insert(address, account.clone());
+        Ok(Some(account))
+    })?;
+
+    let mempool = database.open_tree("mempool")?;
+    if !mempool.is_empty() {
+        tracing::warn!(
+            "Dropping {} pending transactions signed with the v1 encoding",
+            mempool.len()
+        );
+        mempool.clear()?;
+    }
+
+    let meta = database.open_tree("meta")?;
+    let rebase = meta.contains_key(SNAPSHOT_BASE_KEY)?;
+    match tip {
+        Some(tip) if rebase || tip.block.index != Index::ZERO => {
+            let state_root = Hash::new(bincode::serialize(&accounts)?);
+            let snapshot = Snapshot {
+                block: tip,
+                state_root,
+                accounts,
+            };
+            tracing::info!(
+                "Rebasing database onto block {} ({})",
+                snapshot.block.block.index,
+                snapshot.block.hash
+            );
+            meta.insert(SNAPSHOT_BASE_KEY, bincode::serialize(&snapshot)?)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
@@ -0,0 +1,139 @@
+//! Records as stored by schema v3, which added an optional expiry height to transactions.
+//! Frozen, never change these.
+
+use super::v2;
+use crate::Error;
+use crate::SNAPSHOT_BASE_KEY;
+use chrono::DateTime;
+use chrono::Utc;
+use keta_core::account::Address;
+use keta_core::block::Index;
+use keta_crypto::Hash;
+use keta_crypto::Nonce;
+use keta_crypto::Signature;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+pub use v2::Account;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transaction {
+    pub from: Address,
+    pub to: Address,
+    pub value: u64,
+    pub fee: u64,
+    pub nonce: u64,
+    pub valid_until: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedTransaction {
+    pub signature: Signature,
+    pub transaction: Transaction,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Block {
+    pub index: Index,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub timestamp: DateTime<Utc>,
+    pub transactions: Vec<SignedTransaction>,
+    pub prev_hash: Hash,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HashedBlock {
+    pub block: Block,
+    pub hash: Hash,
+    pub nonce: Nonce,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub block: HashedBlock,
+    pub state_root: Hash,
+    pub accounts: BTreeMap<Address, Account>,
+}
+
+fn convert_block(block: v2::HashedBlock) -> HashedBlock {
+    let transactions = block
+        .block
+        .transactions
+        .into_iter()
+        .map(|transaction| SignedTransaction {
+            signature: transaction.signature,
+            transaction: Transaction {
+                from: transaction.transaction.from,
+                to: transaction.transaction.to,
+                value: transaction.transaction.value,
+                fee: transaction.transaction.fee,
+                nonce: transaction.transaction.nonce,
+                valid_until: None,
+            },
+        })
+        .collect();
+    HashedBlock {
+        block: Block {
+            index: block.block.index,
+            timestamp: block.block.timestamp,
+            transactions,
+            prev_hash: block.block.prev_hash,
+        },
+        hash: block.hash,
+        nonce: block.nonce,
+    }
+}
+
+/// Existing transactions never expire. As with v2, their signatures cover the previous
+/// encoding, so the database is rebased onto a snapshot of the current tip.
+pub fn migrate(database: &sled::Db) -> Result<(), Error> {
+    let blocks = database.open_tree("blocks")?;
+    let mut tip = None;
+    super::convert_tree(&blocks, "blocks", |_, block: v2::HashedBlock| {
+        let block = convert_block(block);
+        tip = Some(block.clone());
+        Ok(Some(block))
+    })?;
+
+    let mempool = database.open_tree("mempool")?;
+    if !mempool.is_empty() {
+        tracing::warn!(
+            "Dropping {} pending transactions signed with the v2 encoding",
+            mempool.len()
+        );
+        mempool.clear()?;
+    }
+
+    let meta = database.open_tree("meta")?;
+    let rebase = meta.contains_key(SNAPSHOT_BASE_KEY)?;
+    match tip {
+        Some(tip) if rebase || tip.block.index != Index::ZERO => self::rebase(database, tip),
+        _ => Ok(()),
+    }
+}
+
+/// Stores a snapshot of the current accounts at `tip` as the base replays start from.
+pub fn rebase(database: &sled::Db, tip: HashedBlock) -> Result<(), Error> {
+    let mut accounts = BTreeMap::new();
+    for item in database.open_tree("accounts")?.iter() {
+        let (key, value) = item?;
+        let address = Address::try_from(&*key).map_err(|err| Error::InvalidKey(err.to_string()))?;
+        accounts.insert(address, bincode::deserialize::<Account>(&value)?);
+    }
+    let state_root = Hash::new(bincode::serialize(&accounts)?);
+    let snapshot = Snapshot {
+        block: tip,
+        state_root,
+        accounts,
+    };
+    tracing::info!(
+        "Rebasing database onto block {} ({})",
+        snapshot.block.block.index,
+        snapshot.block.hash
+    );
+    let meta = database.open_tree("meta")?;
+    meta.insert(SNAPSHOT_BASE_KEY, bincode::serialize(&snapshot)?)?;
+    Ok(())
+}
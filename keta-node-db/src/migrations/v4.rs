@@ -0,0 +1,27 @@
+//! Schema v4 keeps the v3 records, but block hashes now commit to a header with a root of the
+//! transactions instead of the whole block. Stored hashes no longer verify under the new rule.
+
+use super::v3;
+use crate::Error;
+use crate::SNAPSHOT_BASE_KEY;
+use keta_core::block::Index;
+
+pub use v3::HashedBlock;
+
+/// A database holding only the old genesis block is reset, so the new one gets written on
+/// startup. Anything longer is rebased onto a snapshot of its tip.
+pub fn migrate(database: &sled::Db) -> Result<(), Error> {
+    let blocks = database.open_tree("blocks")?;
+    let tip = match blocks.last()? {
+        Some((_, tip)) => bincode::deserialize::<HashedBlock>(&tip)?,
+        None => return Ok(()),
+    };
+    let meta = database.open_tree("meta")?;
+    if tip.block.index == Index::ZERO && !meta.contains_key(SNAPSHOT_BASE_KEY)? {
+        tracing::info!("Replacing genesis block");
+        blocks.clear()?;
+        database.open_tree("accounts")?.clear()?;
+        return Ok(());
+    }
+    v3::rebase(database, tip)
+}
@@ -0,0 +1,27 @@
+//! Schema v5 keeps the v4 records and adds an index of the block height every stored
+//! transaction was included at.
+
+use super::v4;
+use crate::Error;
+use keta_crypto::Hash;
+
+pub use v4::HashedBlock;
+
+pub fn migrate(database: &sled::Db) -> Result<(), Error> {
+    let transactions = database.open_tree("transactions")?;
+    let mut indexed = 0;
+    for item in database.open_tree("blocks")?.iter() {
+        let (_, block) = item?;
+        let block: HashedBlock = bincode::deserialize(&block)?;
+        let index = bincode::serialize(&block.block.index)?;
+        let mut batch = sled::Batch::default();
+        for transaction in &block.block.transactions {
+            let hash = Hash::new(bincode::serialize(transaction)?);
+            batch.insert(hash.as_ref(), index.as_slice());
+            indexed += 1;
+        }
+        transactions.apply_batch(batch)?;
+    }
+    tracing::info!("Indexed {} transactions", indexed);
+    Ok(())
+}
@@ -0,0 +1,43 @@
+//! Records as stored by schema v6, which remembers when a connection to a peer address last
+//! succeeded. Frozen, never change these.
+
+use crate::Error;
+use chrono::DateTime;
+use chrono::Utc;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Peer as stored up to v5.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LegacyPeer {
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub last_seen: DateTime<Utc>,
+    pub failures: u32,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub last_failure: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Peer {
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub last_seen: DateTime<Utc>,
+    pub failures: u32,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub last_failure: DateTime<Utc>,
+    #[serde(with = "chrono::serde::ts_seconds_option")]
+    pub last_connected: Option<DateTime<Utc>>,
+}
+
+/// Older records do not tell addresses we connected to from ones only heard of, so none of them
+/// count as connected until the next successful connection.
+pub fn migrate(database: &sled::Db) -> Result<(), Error> {
+    let peers = database.open_tree("peers")?;
+    super::convert_tree(&peers, "peers", |_, peer: LegacyPeer| {
+        Ok(Some(Peer {
+            last_seen: peer.last_seen,
+            failures: peer.failures,
+            last_failure: peer.last_failure,
+            last_connected: None,
+        }))
+    })
+}
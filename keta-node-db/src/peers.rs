@@ -0,0 +1,104 @@
+use super::Error;
+use chrono::DateTime;
+use chrono::Utc;
+use serde::Deserialize;
+use serde::Serialize;
+use std::net::SocketAddr;
+
+/// What is known about a peer address, learnt from configuration or from other peers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Peer {
+    /// Last time a connection to the peer was open, or when the address was first heard of.
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub last_seen: DateTime<Utc>,
+    /// Connection attempts failed in a row.
+    pub failures: u32,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub last_failure: DateTime<Utc>,
+    /// Last time a connection to the address succeeded, `None` if it never did.
+    #[serde(with = "chrono::serde::ts_seconds_option")]
+    pub last_connected: Option<DateTime<Utc>>,
+}
+
+/// Peer addresses, keyed by their textual form.
+#[derive(Debug, Clone)]
+pub struct Tree {
+    tree: sled::Tree,
+}
+
+impl Tree {
+    pub fn get(&self, address: &SocketAddr) -> Result<Option<Peer>, Error> {
+        match self.tree.get(address.to_string())? {
+            Some(value) => Ok(Some(bincode::deserialize(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn insert(&self, address: &SocketAddr, peer: &Peer) -> Result<(), Error> {
+        self.tree
+            .insert(address.to_string(), bincode::serialize(peer)?)?;
+        self.tree.flush()?;
+        Ok(())
+    }
+
+    pub fn remove(&self, address: &SocketAddr) -> Result<(), Error> {
+        self.tree.remove(address.to_string())?;
+        self.tree.flush()?;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = Result<(SocketAddr, Peer), Error>> {
+        self.tree.iter().map(|item| {
+            let (key, value) = item?;
+            let key = String::from_utf8(key.to_vec())?;
+            let address = key.parse().map_err(|_| Error::InvalidKey(key))?;
+            Ok((address, bincode::deserialize(&value)?))
+        })
+    }
+}
+
+impl AsRef<sled::Tree> for Tree {
+    fn as_ref(&self) -> &sled::Tree {
+        &self.tree
+    }
+}
+
+impl From<sled::Tree> for Tree {
+    fn from(tree: sled::Tree) -> Self {
+        Self { tree }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Peer;
+    use crate::Database;
+
+    #[test]
+    fn insert_and_iterate() {
+        let database = Database::temporary().unwrap();
+        let address = "127.0.0.1:5455".parse().unwrap();
+        let peer = Peer {
+            last_seen: chrono::Utc::now(),
+            failures: 2,
+            last_failure: chrono::Utc::now(),
+            last_connected: None,
+        };
+        database.peers.insert(&address, &peer).unwrap();
+
+        let stored: Vec<_> = database.peers.iter().collect::<Result<_, _>>().unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].0, address);
+        assert_eq!(stored[0].1.failures, 2);
+        database.peers.remove(&address).unwrap();
+        assert!(database.peers.is_empty());
+    }
+}
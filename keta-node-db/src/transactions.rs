@@ -0,0 +1,43 @@
+use super::Error;
+use keta_core::block;
+use keta_core::block::HashedBlock;
+use keta_crypto::Hash;
+
+/// Height of the block each stored transaction was included in, by transaction hash.
+#[derive(Debug, Clone)]
+pub struct Tree {
+    tree: sled::Tree,
+}
+
+impl Tree {
+    /// Indexes the transactions of every block in `blocks` atomically.
+    pub fn insert_blocks<'a>(
+        &self,
+        blocks: impl IntoIterator<Item = &'a HashedBlock>,
+    ) -> Result<(), Error> {
+        let mut batch = sled::Batch::default();
+        for block in blocks {
+            let index = bincode::serialize(&block.index)?;
+            for transaction in &block.transactions {
+                batch.insert(transaction.hash().as_ref(), index.as_slice());
+            }
+        }
+        self.tree.apply_batch(batch)?;
+        self.tree.flush()?;
+        Ok(())
+    }
+}
+
+impl crate::Tree<Hash, block::Index> for Tree {}
+
+impl AsRef<sled::Tree> for Tree {
+    fn as_ref(&self) -> &sled::Tree {
+        &self.tree
+    }
+}
+
+impl From<sled::Tree> for Tree {
+    fn from(tree: sled::Tree) -> Self {
+        Self { tree }
+    }
+}
@@ -0,0 +1,164 @@
+//! Portable block archive: a magic, a format version and a sequence of length-prefixed
+//! bincode-encoded `HashedBlock` records.
+
+use keta_core::block::HashedBlock;
+use std::convert::TryFrom;
+use std::io::Read;
+use std::io::Write;
+
+const MAGIC: &[u8; 4] = b"KETB";
+
+/// Bump whenever the encoding of `HashedBlock` or the way blocks are hashed changes.
+pub const FORMAT_VERSION: u32 = 4;
+
+const MAX_RECORD_SIZE: u32 = 64 * 1024 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("bincode: {0}")]
+    Bincode(#[from] bincode::Error),
+
+    #[error("not a block archive")]
+    InvalidMagic,
+
+    #[error("archive format v{found} is not supported, expected v{supported}")]
+    UnsupportedVersion { found: u32, supported: u32 },
+
+    #[error("record of {0} bytes exceeds the size limit")]
+    RecordTooLarge(u32),
+}
+
+pub struct Writer<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(mut inner: W) -> Result<Self, Error> {
+        inner.write_all(MAGIC)?;
+        inner.write_all(&FORMAT_VERSION.to_be_bytes())?;
+        Ok(Self { inner })
+    }
+
+    pub fn write(&mut self, block: &HashedBlock) -> Result<(), Error> {
+        let record = bincode::serialize(block)?;
+        let length = u32::try_from(record.len()).unwrap_or(u32::MAX);
+        if length > MAX_RECORD_SIZE {
+            return Err(Error::RecordTooLarge(length));
+        }
+        self.inner.write_all(&length.to_be_bytes())?;
+        self.inner.write_all(&record)?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+pub struct Reader<R: Read> {
+    inner: R,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(mut inner: R) -> Result<Self, Error> {
+        let mut magic = [0; 4];
+        inner.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+        let mut version = [0; 4];
+        inner.read_exact(&mut version)?;
+        let version = u32::from_be_bytes(version);
+        if version != FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion {
+                found: version,
+                supported: FORMAT_VERSION,
+            });
+        }
+        Ok(Self { inner })
+    }
+
+    fn read_block(&mut self) -> Result<Option<HashedBlock>, Error> {
+        let mut length = [0; 4];
+        // A clean end of file is only allowed on a record boundary.
+        match self.inner.read(&mut length[..1])? {
+            0 => return Ok(None),
+            _ => self.inner.read_exact(&mut length[1..])?,
+        }
+        let length = u32::from_be_bytes(length);
+        if length > MAX_RECORD_SIZE {
+            return Err(Error::RecordTooLarge(length));
+        }
+        let mut record = vec![0; length as usize];
+        self.inner.read_exact(&mut record)?;
+        Ok(Some(bincode::deserialize(&record)?))
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = Result<HashedBlock, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_block().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Error;
+    use super::Reader;
+    use super::Writer;
+    use keta_core::block::Block;
+    use keta_core::block::HashedBlock;
+    use keta_core::chain_spec::ChainSpec;
+
+    #[test]
+    fn write_read() {
+        let genesis = ChainSpec::MAINNET.genesis();
+        let block = Block {
+            timestamp: genesis.timestamp,
+            ..Block::generate(&genesis, Vec::new())
+        };
+        let block = HashedBlock {
+            hash: block.hash_with_nonce(1),
+            block,
+            nonce: 1,
+        };
+
+        let mut writer = Writer::new(Vec::new()).unwrap();
+        writer.write(&genesis).unwrap();
+        writer.write(&block).unwrap();
+        let archive = writer.finish().unwrap();
+
+        let blocks = Reader::new(archive.as_slice())
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(blocks, vec![genesis, block]);
+    }
+
+    #[test]
+    fn truncated() {
+        let mut writer = Writer::new(Vec::new()).unwrap();
+        writer.write(&ChainSpec::MAINNET.genesis()).unwrap();
+        let mut archive = writer.finish().unwrap();
+        archive.pop();
+
+        let mut reader = Reader::new(archive.as_slice()).unwrap();
+        assert!(matches!(reader.next(), Some(Err(Error::Io(_)))));
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let mut archive = Writer::new(Vec::new()).unwrap().finish().unwrap();
+        archive[7] += 1;
+        assert!(matches!(
+            Reader::new(archive.as_slice()),
+            Err(Error::UnsupportedVersion { .. })
+        ));
+    }
+}
@@ -0,0 +1,227 @@
+use crate::snapshot::Snapshot;
+use crate::state;
+use crate::state::State;
+use keta_core::block;
+use keta_core::block::HashedBlock;
+use keta_core::chain_spec::ChainSpec;
+use keta_crypto::Hash;
+use keta_node_db::Database;
+use keta_node_db::SNAPSHOT_BASE_KEY;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReplayError {
+    #[error("database: {0}")]
+    Database(#[from] keta_node_db::Error),
+
+    #[error("block {index} ({hash}): {error}")]
+    Block {
+        index: block::Index,
+        hash: Hash,
+        error: state::Error,
+    },
+
+    #[error("database is empty")]
+    Empty,
+
+    #[error("height {height} is not available, stored blocks cover {first}..={last}")]
+    HeightNotAvailable { height: u64, first: u64, last: u64 },
+}
+
+/// Writes the genesis block and accounts of `chain_spec` into an empty database, or checks that
+/// the database holds the same chain otherwise.
+pub fn initialize(database: &Database, chain_spec: &ChainSpec) -> Result<(), state::Error> {
+    let genesis = chain_spec.genesis();
+    match database.blocks.iter().next() {
+        None => {
+            tracing::info!("Initializing database with genesis block {}", genesis.hash);
+            database.commit_block(&genesis, &chain_spec.genesis_accounts())?;
+        }
+        // Databases bootstrapped from a snapshot start at the snapshot block instead.
+        Some(first) if snapshot_base(database)?.is_none() => {
+            state::validate_genesis(&first?, &genesis)?;
+        }
+        Some(_) => {}
+    }
+    Ok(())
+}
+
+/// Seeds an empty database with the state from `snapshot`, so that it continues from the
+/// snapshot block instead of genesis.
+pub fn bootstrap(database: &Database, snapshot: &Snapshot) -> Result<(), state::Error> {
+    database.commit_block(&snapshot.block, &snapshot.accounts)?;
+    database.meta.insert(SNAPSHOT_BASE_KEY, snapshot)?;
+    Ok(())
+}
+
+pub fn snapshot_base(database: &Database) -> Result<Option<Snapshot>, keta_node_db::Error> {
+    database.meta.get(SNAPSHOT_BASE_KEY)
+}
+
+pub fn tip(database: &Database) -> Result<HashedBlock, state::Error> {
+    let tip = database
+        .blocks
+        .iter()
+        .next_back()
+        .expect("database is not initialized")?;
+    Ok(tip)
+}
+
+/// Validates `block` on top of the current tip, applies its transactions and stores it.
+pub fn import_block(database: &Database, block: &HashedBlock) -> Result<(), state::Error> {
+    state::validate_block(&tip(database)?, block)?;
+    let mut state = State::new(&database.accounts);
+    state.apply_block(block)?;
+    database.commit_block(block, state.changes())?;
+    Ok(())
+}
+
+/// Replaces the stored blocks above height `ancestor` with `blocks`, returning the replaced ones.
+/// The state at `ancestor` is rebuilt by replaying the chain and `blocks` are checked on top of
+/// it in memory first, so nothing is changed unless all of them are valid.
+pub fn reorganize(
+    database: &Database,
+    chain_spec: &ChainSpec,
+    ancestor: u64,
+    blocks: &[HashedBlock],
+) -> Result<Vec<HashedBlock>, ReplayError> {
+    let (ancestor, state) = replay(database, chain_spec, Some(ancestor))?;
+    let accounts = state.into_changes();
+    let block_error = |block: &HashedBlock, error: state::Error| ReplayError::Block {
+        index: block.index.clone(),
+        hash: block.hash.clone(),
+        error,
+    };
+
+    let mut state = State::with_accounts(accounts.clone());
+    let mut prev = &ancestor;
+    for block in blocks {
+        state::validate_block(prev, block)
+            .map_err(state::Error::from)
+            .and_then(|()| state.apply_block(block))
+            .map_err(|error| block_error(block, error))?;
+        prev = block;
+    }
+
+    let replaced = database.rewind(&ancestor.index, &accounts)?;
+    for block in blocks {
+        import_block(database, block).map_err(|error| block_error(block, error))?;
+    }
+    Ok(replaced)
+}
+
+/// Replays the stored blocks up to and including height `to` in memory, starting from the
+/// genesis of `chain_spec` or from the snapshot the database was bootstrapped from. Returns the
+/// last replayed block and the resulting state.
+pub fn replay(
+    database: &Database,
+    chain_spec: &ChainSpec,
+    to: Option<u64>,
+) -> Result<(HashedBlock, State<'static>), ReplayError> {
+    let (mut state, mut prev) = match snapshot_base(database)? {
+        Some(snapshot) => (
+            State::with_accounts(snapshot.accounts),
+            Some(snapshot.block),
+        ),
+        None => (State::with_accounts(chain_spec.genesis_accounts()), None),
+    };
+    let genesis = chain_spec.genesis();
+    let first = prev.as_ref().map_or(0, |block| block.index.to_u64());
+    if let Some(to) = to.filter(|to| *to < first) {
+        let last = tip(database).map_or(first, |tip| tip.index.to_u64());
+        return Err(ReplayError::HeightNotAvailable {
+            height: to,
+            first,
+            last,
+        });
+    }
+
+    let from = prev
+        .as_ref()
+        .map_or(block::Index::ZERO, |block| block.index.increment());
+    for block in database.blocks.iter_from(&from) {
+        let block = block?;
+        if matches!(to, Some(to) if block.index.to_u64() > to) {
+            break;
+        }
+        match &prev {
+            Some(prev) => state::validate_block(prev, &block),
+            None => state::validate_genesis(&block, &genesis),
+        }
+        .map_err(state::Error::from)
+        .and_then(|()| state.apply_block(&block))
+        .map_err(|error| ReplayError::Block {
+            index: block.index.clone(),
+            hash: block.hash.clone(),
+            error,
+        })?;
+        prev = Some(block);
+    }
+
+    let last = prev.ok_or(ReplayError::Empty)?;
+    if let Some(to) = to.filter(|to| *to > last.index.to_u64()) {
+        return Err(ReplayError::HeightNotAvailable {
+            height: to,
+            first,
+            last: last.index.to_u64(),
+        });
+    }
+    Ok((last, state))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mempool;
+    use crate::world::World;
+    use keta_core::chain_spec::ChainSpec;
+    use keta_core::chain_spec::DEVNET_FAUCET_SECRET_KEY;
+    use keta_core::transaction::Transaction;
+    use keta_crypto::Keypair;
+    use keta_node_db::Database;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn replay_rebuilds_transfers_from_genesis_allocations() {
+        let database = Database::temporary().unwrap();
+        let world = World::new(
+            database.clone(),
+            ChainSpec::DEVNET,
+            mempool::Config::default(),
+        )
+        .unwrap();
+        let faucet = Keypair::from_secret(DEVNET_FAUCET_SECRET_KEY.parse().unwrap());
+        let (alice, bob) = (Keypair::generate(), Keypair::generate());
+        let supply = world.total_supply().unwrap();
+        assert_eq!(supply, world.get_balance(&faucet.public).unwrap());
+
+        let pay = |from: &Keypair, to: &Keypair, value, fee| {
+            let transaction = Transaction {
+                from: from.public.clone(),
+                to: to.public.clone(),
+                value,
+                fee,
+                nonce: world.get_nonce(&from.public).unwrap(),
+                valid_until: None,
+            };
+            world.send_transaction(transaction.sign(from)).unwrap();
+            world.generate_block().unwrap();
+        };
+        pay(&faucet, &alice, 300, 5);
+        pay(&alice, &bob, 100, 1);
+        assert_eq!(world.get_balance(&alice.public).unwrap(), 199);
+        assert_eq!(world.get_balance(&bob.public).unwrap(), 100);
+        assert_eq!(world.total_supply().unwrap(), supply - 6);
+
+        let (tip, state) = super::replay(&database, &ChainSpec::DEVNET, None).unwrap();
+        assert_eq!(tip, world.tip().unwrap());
+        let stored = database
+            .accounts
+            .iter()
+            .collect::<Result<BTreeMap<_, _>, _>>()
+            .unwrap();
+        assert_eq!(state.into_changes(), stored);
+
+        let (_, state) = super::replay(&database, &ChainSpec::DEVNET, Some(1)).unwrap();
+        assert_eq!(state.account(&alice.public).unwrap().balance, 300);
+        assert_eq!(state.account(&bob.public).unwrap().balance, 0);
+    }
+}
@@ -1,52 +1,454 @@
+use crate::commands;
+use crate::dandelion;
+use crate::mempool;
+use crate::peers;
 use clap::App;
 use clap::Arg;
+use clap::SubCommand;
+use keta_core::chain_spec::Network;
 
 fn base_directories() -> xdg::BaseDirectories {
     xdg::BaseDirectories::with_prefix("keta-node").unwrap()
 }
 
-fn default_database_path() -> std::path::PathBuf {
-    base_directories().get_data_home().join("database")
+/// Directory the data of `network` is kept in, so that networks never share any.
+fn data_directory(network: Network) -> std::path::PathBuf {
+    let data_home = base_directories().get_data_home();
+    match network.data_dir() {
+        Some(directory) => data_home.join(directory),
+        None => data_home,
+    }
+}
+
+fn default_database_path(network: Network) -> std::path::PathBuf {
+    data_directory(network).join("database")
+}
+
+fn default_identity_path(network: Network) -> std::path::PathBuf {
+    data_directory(network).join("identity")
+}
+
+fn default_ipc_path(network: Network) -> std::path::PathBuf {
+    data_directory(network).join("keta-node.ipc")
+}
+
+fn default_rpc_address(network: Network) -> std::net::SocketAddr {
+    std::net::SocketAddrV4::new(std::net::Ipv4Addr::LOCALHOST, network.default_rpc_port()).into()
+}
+
+fn default_http_rpc_address(network: Network) -> std::net::SocketAddr {
+    std::net::SocketAddrV4::new(
+        std::net::Ipv4Addr::LOCALHOST,
+        network.default_http_rpc_port(),
+    )
+    .into()
+}
+
+fn default_ban_duration() -> std::time::Duration {
+    std::time::Duration::from_secs(24 * 60 * 60)
 }
 
-fn default_rpc_port() -> u16 {
-    5454
+fn default_max_inbound_peers() -> usize {
+    64
 }
 
-fn default_rpc_address() -> std::net::SocketAddr {
-    std::net::SocketAddrV4::new(std::net::Ipv4Addr::LOCALHOST, default_rpc_port()).into()
+fn default_p2p_address(network: Network) -> std::net::SocketAddr {
+    std::net::SocketAddrV4::new(std::net::Ipv4Addr::UNSPECIFIED, network.default_p2p_port()).into()
 }
 
 #[derive(Debug)]
 pub struct Args {
+    pub network: Network,
     pub database: std::path::PathBuf,
+    /// File holding the node identity key.
+    pub identity: std::path::PathBuf,
     pub rpc_address: std::net::SocketAddr,
+    pub http_rpc_address: std::net::SocketAddr,
+    /// Origins and hosts the HTTP RPC server accepts requests from.
+    pub http_rpc_access: keta_rpc::AccessControl,
+    /// Unix socket local tools call RPC methods through.
+    pub ipc_path: std::path::PathBuf,
+    pub p2p_address: std::net::SocketAddr,
+    /// `host:port` of peers to discover others through.
+    pub bootnodes: Vec<String>,
+    pub peers: peers::Config,
+    /// How long misbehaving peers stay banned.
+    pub ban_duration: std::time::Duration,
+    /// Inbound peer connections accepted at once.
+    pub max_inbound_peers: usize,
+    /// SOCKS5 proxy for outbound peer connections.
+    pub proxy: Option<keta_network::Proxy>,
+    pub mempool: mempool::Config,
+    pub dandelion: dandelion::Config,
+    pub command: Option<Command>,
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Bootstrap(commands::Bootstrap),
+    ExportBlocks(commands::ExportBlocks),
+    ImportBlocks(commands::ImportBlocks),
+    Reindex(commands::Reindex),
+    Snapshot(commands::Snapshot),
+    VerifyDb(commands::VerifyDb),
 }
 
 pub fn parse_args() -> Args {
-    let default_database_path = default_database_path();
-    let default_rpc_address = default_rpc_address().to_string();
+    let default_ban_duration = default_ban_duration().as_secs().to_string();
+    let default_target_peers = peers::Config::default().target_peers.to_string();
+    let default_max_inbound_peers = default_max_inbound_peers().to_string();
+    let default_mempool_size = mempool::Config::default().max_size.to_string();
+    let default_replacement_fee_bump = mempool::Config::default().replacement_fee_bump.to_string();
+    let default_fluff_probability = dandelion::Config::default().fluff_probability.to_string();
     let matches = App::new("keta-node")
         .bin_name(clap::crate_name!())
         .version(clap::crate_version!())
         .author(clap::crate_authors!())
         .about("keta node implementation")
+        .arg(
+            Arg::with_name("network")
+                .long("network")
+                .help("Network to join, which sets the chain and the defaults below")
+                .global(true)
+                .possible_values(&["mainnet", "testnet", "devnet"])
+                .default_value("mainnet"),
+        )
         .arg(
             Arg::with_name("database")
                 .long("database")
-                .help("Path to database")
-                .default_value(default_database_path.as_os_str().to_str().unwrap()),
+                .help("Path to database [default: database in the network's data directory]")
+                .global(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("identity")
+                .long("identity")
+                .help(
+                    "Path to the node identity key, generated if missing \
+                     [default: identity in the network's data directory]",
+                )
+                .takes_value(true),
         )
         .arg(
             Arg::with_name("rpc-address")
                 .long("rpc-address")
-                .help("RPC listen address")
-                .default_value(default_rpc_address.as_str()),
+                .help("RPC listen address [default: localhost on the network's RPC port]")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("http-rpc-address")
+                .long("http-rpc-address")
+                .help(
+                    "HTTP RPC listen address \
+                     [default: localhost on the network's HTTP RPC port]",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rpc-cors-origins")
+                .long("rpc-cors-origins")
+                .help(
+                    "Comma separated origins browsers may call the HTTP RPC server from, \
+                     * for any [default: none]",
+                )
+                .takes_value(true)
+                .use_delimiter(true),
+        )
+        .arg(
+            Arg::with_name("rpc-allowed-hosts")
+                .long("rpc-allowed-hosts")
+                .help(
+                    "Comma separated host names the HTTP RPC server accepts requests for, \
+                     with optional port and * wildcards, * for any",
+                )
+                .takes_value(true)
+                .default_value("localhost:*,127.0.0.1:*")
+                .use_delimiter(true),
+        )
+        .arg(
+            Arg::with_name("ipc-path")
+                .long("ipc-path")
+                .help(
+                    "Path to the RPC socket for local tools \
+                     [default: keta-node.ipc in the network's data directory]",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("p2p-address")
+                .long("p2p-address")
+                .help(
+                    "Listen address for peer-to-peer connections \
+                     [default: all interfaces on the network's P2P port]",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("connect")
+                .long("connect")
+                .help("Peer to stay connected to, as [<public key>@]<ip>:<port>")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("bootnodes")
+                .long("bootnodes")
+                .help(
+                    "Comma separated host:port of peers to discover others through \
+                     [default: the network's bootnodes]",
+                )
+                .takes_value(true)
+                .use_delimiter(true),
+        )
+        .arg(
+            Arg::with_name("target-peers")
+                .long("target-peers")
+                .help("Number of outbound peer connections to keep open")
+                .default_value(default_target_peers.as_str()),
+        )
+        .arg(
+            Arg::with_name("max-inbound-peers")
+                .long("max-inbound-peers")
+                .help("Number of inbound peer connections to accept at most")
+                .default_value(default_max_inbound_peers.as_str()),
+        )
+        .arg(
+            Arg::with_name("ban-duration")
+                .long("ban-duration")
+                .help("Seconds misbehaving peers stay banned")
+                .default_value(default_ban_duration.as_str()),
         )
+        .arg(
+            Arg::with_name("proxy")
+                .long("proxy")
+                .help("Make outbound peer connections through a proxy, as socks5://<host>:<port>")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mempool-size")
+                .long("mempool-size")
+                .help("Maximum number of pending transactions")
+                .default_value(default_mempool_size.as_str()),
+        )
+        .arg(
+            Arg::with_name("replacement-fee-bump")
+                .long("replacement-fee-bump")
+                .help("Minimum fee increase, in percent, to replace a pending transaction")
+                .default_value(default_replacement_fee_bump.as_str()),
+        )
+        .arg(
+            Arg::with_name("no-dandelion")
+                .long("no-dandelion")
+                .help("Broadcast submitted transactions right away instead of relaying them first"),
+        )
+        .arg(
+            Arg::with_name("fluff-probability")
+                .long("fluff-probability")
+                .help("Chance of broadcasting transactions relayed from peers, per epoch")
+                .default_value(default_fluff_probability.as_str()),
+        )
+        .subcommand(bootstrap())
+        .subcommand(export_blocks())
+        .subcommand(import_blocks())
+        .subcommand(reindex())
+        .subcommand(snapshot())
+        .subcommand(verify_db())
         .get_matches();
 
+    let command = match matches.subcommand() {
+        ("bootstrap", Some(sub_matches)) => Some(Command::Bootstrap(commands::Bootstrap {
+            file: sub_matches.value_of("file").unwrap().into(),
+        })),
+        ("export-blocks", Some(sub_matches)) => {
+            Some(Command::ExportBlocks(commands::ExportBlocks {
+                from: sub_matches
+                    .value_of("from")
+                    .map(|from| from.parse().unwrap()),
+                to: sub_matches.value_of("to").map(|to| to.parse().unwrap()),
+                file: sub_matches.value_of("file").unwrap().into(),
+            }))
+        }
+        ("import-blocks", Some(sub_matches)) => {
+            Some(Command::ImportBlocks(commands::ImportBlocks {
+                file: sub_matches.value_of("file").unwrap().into(),
+            }))
+        }
+        ("reindex", Some(_)) => Some(Command::Reindex(commands::Reindex {})),
+        ("snapshot", Some(sub_matches)) => Some(Command::Snapshot(commands::Snapshot {
+            height: sub_matches
+                .value_of("height")
+                .map(|height| height.parse().unwrap()),
+            format: sub_matches.value_of("format").unwrap().parse().unwrap(),
+            file: sub_matches.value_of("file").unwrap().into(),
+        })),
+        ("verify-db", Some(_)) => Some(Command::VerifyDb(commands::VerifyDb {})),
+        ("", None) => None,
+        _ => panic!("unexpected command"),
+    };
+
+    let network: Network = matches.value_of("network").unwrap().parse().unwrap();
     Args {
-        database: matches.value_of("database").unwrap().parse().unwrap(),
-        rpc_address: matches.value_of("rpc-address").unwrap().parse().unwrap(),
+        network,
+        database: matches
+            .value_of("database")
+            .map_or_else(|| default_database_path(network), Into::into),
+        identity: matches
+            .value_of("identity")
+            .map_or_else(|| default_identity_path(network), Into::into),
+        rpc_address: matches.value_of("rpc-address").map_or_else(
+            || default_rpc_address(network),
+            |address| address.parse().unwrap(),
+        ),
+        http_rpc_address: matches.value_of("http-rpc-address").map_or_else(
+            || default_http_rpc_address(network),
+            |address| address.parse().unwrap(),
+        ),
+        http_rpc_access: keta_rpc::AccessControl {
+            cors_origins: matches
+                .values_of("rpc-cors-origins")
+                .into_iter()
+                .flatten()
+                .map(String::from)
+                .collect(),
+            allowed_hosts: matches
+                .values_of("rpc-allowed-hosts")
+                .into_iter()
+                .flatten()
+                .map(String::from)
+                .collect(),
+        },
+        ipc_path: matches
+            .value_of("ipc-path")
+            .map_or_else(|| default_ipc_path(network), Into::into),
+        p2p_address: matches.value_of("p2p-address").map_or_else(
+            || default_p2p_address(network),
+            |address| address.parse().unwrap(),
+        ),
+        bootnodes: match matches.values_of("bootnodes") {
+            Some(bootnodes) => bootnodes.map(String::from).collect(),
+            None => network
+                .bootnodes()
+                .iter()
+                .map(|bootnode| bootnode.to_string())
+                .collect(),
+        },
+        peers: peers::Config {
+            target_peers: matches.value_of("target-peers").unwrap().parse().unwrap(),
+            connect: matches
+                .values_of("connect")
+                .into_iter()
+                .flatten()
+                .map(|address| address.parse().unwrap())
+                .collect(),
+        },
+        ban_duration: std::time::Duration::from_secs(
+            matches.value_of("ban-duration").unwrap().parse().unwrap(),
+        ),
+        max_inbound_peers: matches
+            .value_of("max-inbound-peers")
+            .unwrap()
+            .parse()
+            .unwrap(),
+        proxy: matches
+            .value_of("proxy")
+            .map(|proxy| proxy.parse().unwrap()),
+        mempool: mempool::Config {
+            max_size: matches.value_of("mempool-size").unwrap().parse().unwrap(),
+            replacement_fee_bump: matches
+                .value_of("replacement-fee-bump")
+                .unwrap()
+                .parse()
+                .unwrap(),
+        },
+        dandelion: dandelion::Config {
+            enabled: !matches.is_present("no-dandelion"),
+            fluff_probability: matches
+                .value_of("fluff-probability")
+                .unwrap()
+                .parse()
+                .unwrap(),
+            ..dandelion::Config::default()
+        },
+        command,
     }
 }
+
+fn bootstrap() -> App<'static, 'static> {
+    SubCommand::with_name("bootstrap")
+        .about("Seed an empty database from a binary account state snapshot")
+        .arg(
+            Arg::with_name("file")
+                .help("Binary snapshot file to read")
+                .required(true)
+                .takes_value(true),
+        )
+}
+
+fn export_blocks() -> App<'static, 'static> {
+    SubCommand::with_name("export-blocks")
+        .about("Export blocks to a portable archive file")
+        .arg(
+            Arg::with_name("from")
+                .long("from")
+                .help("Index of the first block to export, defaults to the first stored block")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("to")
+                .long("to")
+                .help("Index of the last block to export, defaults to the tip")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("file")
+                .help("Archive file to write")
+                .required(true)
+                .takes_value(true),
+        )
+}
+
+fn import_blocks() -> App<'static, 'static> {
+    SubCommand::with_name("import-blocks")
+        .about("Validate and import blocks from a portable archive file")
+        .arg(
+            Arg::with_name("file")
+                .help("Archive file to read")
+                .required(true)
+                .takes_value(true),
+        )
+}
+
+fn reindex() -> App<'static, 'static> {
+    SubCommand::with_name("reindex")
+        .about("Rebuild account state by replaying every block from genesis")
+}
+
+fn snapshot() -> App<'static, 'static> {
+    SubCommand::with_name("snapshot")
+        .about("Write the account state as of a given block to a file")
+        .arg(
+            Arg::with_name("height")
+                .long("height")
+                .help("Index of the block to take the snapshot at, defaults to the tip")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .help("Output format")
+                .possible_values(&["json", "csv", "binary"])
+                .default_value("binary"),
+        )
+        .arg(
+            Arg::with_name("file")
+                .help("File to write the snapshot to")
+                .required(true)
+                .takes_value(true),
+        )
+}
+
+fn verify_db() -> App<'static, 'static> {
+    SubCommand::with_name("verify-db")
+        .about("Replay every block without writing and report the first mismatch")
+}
@@ -0,0 +1,33 @@
+use crate::chain;
+use crate::snapshot::Snapshot;
+use anyhow::anyhow;
+use keta_core::chain_spec::ChainSpec;
+use keta_node_db::Database;
+use keta_node_db::Tree;
+
+/// Seeds an empty database from a binary snapshot taken on the same network. Subsequent blocks
+/// can then be imported on top of it.
+#[derive(Debug)]
+pub struct Command {
+    pub file: std::path::PathBuf,
+}
+
+impl Command {
+    pub fn run(self, database: Database, chain_spec: &ChainSpec) -> anyhow::Result<()> {
+        if database.blocks.len() != 0 {
+            return Err(anyhow!("database is not empty"));
+        }
+        let file = std::io::BufReader::new(std::fs::File::open(&self.file)?);
+        let snapshot = Snapshot::read(file, chain_spec)?;
+        chain::bootstrap(&database, &snapshot)?;
+
+        tracing::info!(
+            "Bootstrapped {} accounts at block {} ({}) with state root {}",
+            snapshot.accounts.len(),
+            snapshot.block.index,
+            snapshot.block.hash,
+            snapshot.state_root
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,52 @@
+use crate::archive;
+use crate::chain;
+use anyhow::anyhow;
+use keta_core::block;
+use keta_node_db::Database;
+use keta_node_db::Tree;
+
+/// Writes the blocks in `from..=to` to a portable archive file.
+#[derive(Debug)]
+pub struct Command {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    pub file: std::path::PathBuf,
+}
+
+impl Command {
+    pub fn run(self, database: Database) -> anyhow::Result<()> {
+        if database.blocks.len() == 0 {
+            return Err(anyhow!("database is empty"));
+        }
+        let from = match self.from {
+            Some(from) => from,
+            None => database.blocks.iter().next().unwrap()?.index.to_u64(),
+        };
+        let to = match self.to {
+            Some(to) => to,
+            None => chain::tip(&database)?.index.to_u64(),
+        };
+        if from > to {
+            return Err(anyhow!("--from {} is above --to {}", from, to));
+        }
+
+        let file = std::io::BufWriter::new(std::fs::File::create(&self.file)?);
+        let mut writer = archive::Writer::new(file)?;
+        for index in from..=to {
+            let block = database
+                .blocks
+                .get(&block::Index::from(index))?
+                .ok_or_else(|| anyhow!("block {} is not in the database", index))?;
+            writer.write(&block)?;
+        }
+        writer.finish()?;
+
+        tracing::info!(
+            "Exported blocks {}..={} to {}",
+            from,
+            to,
+            self.file.display()
+        );
+        Ok(())
+    }
+}
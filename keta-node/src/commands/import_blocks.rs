@@ -0,0 +1,54 @@
+use crate::archive;
+use crate::chain;
+use anyhow::anyhow;
+use anyhow::Context;
+use keta_core::chain_spec::ChainSpec;
+use keta_node_db::Database;
+use keta_node_db::Tree;
+
+const PROGRESS_INTERVAL: u64 = 1000;
+
+/// Imports blocks from a portable archive file, validating and applying each of them. Blocks
+/// that are already in the database are skipped if they match.
+#[derive(Debug)]
+pub struct Command {
+    pub file: std::path::PathBuf,
+}
+
+impl Command {
+    pub fn run(self, database: Database, chain_spec: &ChainSpec) -> anyhow::Result<()> {
+        chain::initialize(&database, chain_spec)?;
+
+        let file = std::io::BufReader::new(std::fs::File::open(&self.file)?);
+        let (mut imported, mut skipped) = (0, 0);
+        for block in archive::Reader::new(file)? {
+            let block = block?;
+            if let Some(existing) = database.blocks.get(&block.index)? {
+                if existing != block {
+                    return Err(anyhow!(
+                        "block {} ({}) conflicts with stored block {}",
+                        block.index,
+                        block.hash,
+                        existing.hash
+                    ));
+                }
+                skipped += 1;
+                continue;
+            }
+
+            chain::import_block(&database, &block)
+                .with_context(|| format!("block {} ({})", block.index, block.hash))?;
+            imported += 1;
+            if imported % PROGRESS_INTERVAL == 0 {
+                tracing::info!("Imported {} blocks, at {}", imported, block.index);
+            }
+        }
+
+        tracing::info!(
+            "Imported {} blocks, skipped {} already known",
+            imported,
+            skipped
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,13 @@
+mod bootstrap;
+mod export_blocks;
+mod import_blocks;
+mod reindex;
+mod snapshot;
+mod verify_db;
+
+pub use bootstrap::Command as Bootstrap;
+pub use export_blocks::Command as ExportBlocks;
+pub use import_blocks::Command as ImportBlocks;
+pub use reindex::Command as Reindex;
+pub use snapshot::Command as Snapshot;
+pub use verify_db::Command as VerifyDb;
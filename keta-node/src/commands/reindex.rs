@@ -0,0 +1,103 @@
+use crate::chain;
+use keta_core::chain_spec::ChainSpec;
+use keta_node_db::Database;
+use keta_node_db::Tree;
+
+/// Rebuilds the account state by replaying every stored block from genesis, or from the
+/// snapshot the database was bootstrapped from, and the index of included transactions.
+#[derive(Debug)]
+pub struct Command {}
+
+impl Command {
+    pub fn run(self, database: Database, chain_spec: &ChainSpec) -> anyhow::Result<()> {
+        tracing::info!("Reindexing {} blocks", database.blocks.len());
+        let (tip, state) = chain::replay(&database, chain_spec, None)?;
+        let blocks = database.blocks.iter().collect::<Result<Vec<_>, _>>()?;
+        database.replace_derived(state.changes(), &blocks)?;
+
+        tracing::info!(
+            "Reindex complete at block {} ({}), {} accounts rebuilt",
+            tip.index,
+            tip.hash,
+            state.changes().len()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Command;
+    use crate::chain;
+    use crate::mempool;
+    use crate::world::World;
+    use keta_core::account::Account;
+    use keta_core::block;
+    use keta_core::chain_spec::ChainSpec;
+    use keta_core::chain_spec::DEVNET_FAUCET_SECRET_KEY;
+    use keta_core::transaction::Transaction;
+    use keta_crypto::Hash;
+    use keta_crypto::Keypair;
+    use keta_node_db::Database;
+    use keta_node_db::Tree;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn rebuilds_accounts_and_transaction_index() {
+        let database = Database::temporary().unwrap();
+        let world = World::new(
+            database.clone(),
+            ChainSpec::DEVNET,
+            mempool::Config::default(),
+        )
+        .unwrap();
+        let faucet = Keypair::from_secret(DEVNET_FAUCET_SECRET_KEY.parse().unwrap());
+        let alice = Keypair::generate();
+        let transaction = Transaction {
+            from: faucet.public.clone(),
+            to: alice.public.clone(),
+            value: 300,
+            fee: 5,
+            nonce: 0,
+            valid_until: None,
+        }
+        .sign(&faucet);
+        let hash = world.send_transaction(transaction).unwrap().hash;
+        world.generate_block().unwrap();
+        drop(world);
+
+        // Out of sync with the blocks: a wrong balance, an account and an index entry that
+        // should not exist, and a missing index entry.
+        let wrong = Account {
+            balance: 1,
+            nonce: 0,
+        };
+        database.accounts.insert(&alice.public, &wrong).unwrap();
+        database
+            .accounts
+            .insert(&Keypair::generate().public, &wrong)
+            .unwrap();
+        database.transactions.as_ref().remove(&hash).unwrap();
+        database
+            .transactions
+            .insert(&Hash::ZERO, &block::Index::from(1))
+            .unwrap();
+
+        Command {}
+            .run(database.clone(), &ChainSpec::DEVNET)
+            .unwrap();
+        let (_, state) = chain::replay(&database, &ChainSpec::DEVNET, None).unwrap();
+        let stored = database
+            .accounts
+            .iter()
+            .collect::<Result<BTreeMap<_, _>, _>>()
+            .unwrap();
+        assert_eq!(stored, state.into_changes());
+        assert_eq!(stored[&alice.public].balance, 300);
+        assert_eq!(
+            database.transactions.get(&hash).unwrap(),
+            Some(block::Index::from(1))
+        );
+        assert_eq!(database.transactions.get(&Hash::ZERO).unwrap(), None);
+    }
+}
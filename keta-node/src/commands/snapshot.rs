@@ -0,0 +1,32 @@
+use crate::chain;
+use crate::snapshot;
+use crate::snapshot::Snapshot;
+use keta_core::chain_spec::ChainSpec;
+use keta_node_db::Database;
+
+/// Writes the account state as of block `height` to a file.
+#[derive(Debug)]
+pub struct Command {
+    pub height: Option<u64>,
+    pub format: snapshot::Format,
+    pub file: std::path::PathBuf,
+}
+
+impl Command {
+    pub fn run(self, database: Database, chain_spec: &ChainSpec) -> anyhow::Result<()> {
+        let (block, state) = chain::replay(&database, chain_spec, self.height)?;
+        let snapshot = Snapshot::new(block, state.into_changes());
+        let file = std::io::BufWriter::new(std::fs::File::create(&self.file)?);
+        snapshot.write(self.format, chain_spec, file)?;
+
+        tracing::info!(
+            "Wrote snapshot of {} accounts at block {} ({}) with state root {} to {}",
+            snapshot.accounts.len(),
+            snapshot.block.index,
+            snapshot.block.hash,
+            snapshot.state_root,
+            self.file.display()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+use crate::chain;
+use crate::chain::ReplayError;
+use anyhow::anyhow;
+use keta_core::chain_spec::ChainSpec;
+use keta_node_db::Database;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+
+/// Replays every stored block in memory and compares the result against the stored accounts,
+/// without writing anything.
+#[derive(Debug)]
+pub struct Command {}
+
+impl Command {
+    pub fn run(self, database: Database, chain_spec: &ChainSpec) -> anyhow::Result<()> {
+        let (tip, state) = match chain::replay(&database, chain_spec, None) {
+            Ok(replay) => replay,
+            Err(ReplayError::Block { index, hash, error }) => {
+                return Err(anyhow!(
+                    "first mismatching block {} ({}): {}",
+                    index,
+                    hash,
+                    error
+                ))
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let expected = state.into_changes();
+        let stored = database
+            .accounts
+            .iter()
+            .collect::<Result<BTreeMap<_, _>, _>>()?;
+        let addresses: BTreeSet<_> = expected.keys().chain(stored.keys()).collect();
+        for address in addresses {
+            let (expected, stored) = (expected.get(address), stored.get(address));
+            if expected != stored {
+                return Err(anyhow!(
+                    "first mismatching account {}: expected {:?}, stored {:?}",
+                    address,
+                    expected,
+                    stored
+                ));
+            }
+        }
+
+        tracing::info!(
+            "Database is consistent up to block {} ({}), {} accounts",
+            tip.index,
+            tip.hash,
+            expected.len()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+//! Rebuilds blocks relayed as compact blocks from the transactions pending here. Blocks with
+//! transactions missing are held until the peer that relayed them sends the rest.
+
+use keta_core::block::transactions_root;
+use keta_core::block::Block;
+use keta_core::block::HashedBlock;
+use keta_core::block::HashedHeader;
+use keta_core::transaction::SignedTransaction;
+use keta_crypto::Hash;
+use keta_network::CompactBlock;
+use keta_network::PeerId;
+use keta_network::ShortId;
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// Most blocks waiting for transactions at once, the oldest are dropped first.
+const MAX_PARTIAL_BLOCKS: usize = 16;
+
+#[derive(Debug)]
+struct PartialBlock {
+    peer: PeerId,
+    header: HashedHeader,
+    transactions: Vec<Option<SignedTransaction>>,
+    received_at: Instant,
+}
+
+#[derive(Debug)]
+pub enum Reconstruction {
+    Complete(HashedBlock),
+    /// The transactions at these positions have to be fetched.
+    Missing(HashedHeader, Vec<u32>),
+    /// The transactions do not add up to the header, the block has to be fetched in full.
+    Mismatch(HashedHeader),
+}
+
+#[derive(Debug, Default)]
+pub struct CompactBlocks {
+    partial: HashMap<Hash, PartialBlock>,
+}
+
+impl CompactBlocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.partial.contains_key(hash)
+    }
+
+    /// Fills in what it can of `compact` from `pending` transactions.
+    pub fn received(
+        &mut self,
+        peer: PeerId,
+        compact: CompactBlock,
+        pending: Vec<SignedTransaction>,
+    ) -> Reconstruction {
+        let hash = &compact.header.hash;
+        let pending: HashMap<ShortId, SignedTransaction> = pending
+            .into_iter()
+            .map(|transaction| {
+                (
+                    CompactBlock::short_id(hash, &transaction.hash()),
+                    transaction,
+                )
+            })
+            .collect();
+        let transactions = compact
+            .short_ids
+            .iter()
+            .map(|short_id| pending.get(short_id).cloned())
+            .collect();
+        self.reconstruct(PartialBlock {
+            peer,
+            header: compact.header,
+            transactions,
+            received_at: Instant::now(),
+        })
+    }
+
+    /// Fills in the missing transactions of the block `hash`, in the order they were asked for.
+    /// Returns `None` if `peer` was not asked for any.
+    pub fn transactions_received(
+        &mut self,
+        peer: PeerId,
+        hash: &Hash,
+        transactions: Vec<SignedTransaction>,
+    ) -> Option<Reconstruction> {
+        if self.partial.get(hash)?.peer != peer {
+            return None;
+        }
+        let mut partial = self.partial.remove(hash)?;
+        let mut received = transactions.into_iter();
+        for slot in partial
+            .transactions
+            .iter_mut()
+            .filter(|slot| slot.is_none())
+        {
+            *slot = received.next();
+        }
+        if received.next().is_some() || partial.transactions.iter().any(Option::is_none) {
+            return Some(Reconstruction::Mismatch(partial.header));
+        }
+        Some(self.reconstruct(partial))
+    }
+
+    /// Drops blocks at or below `height`, they can no longer extend the chain.
+    pub fn prune(&mut self, height: u64) {
+        self.partial
+            .retain(|_, partial| partial.header.index.to_u64() > height);
+    }
+
+    fn reconstruct(&mut self, partial: PartialBlock) -> Reconstruction {
+        let missing: Vec<u32> = partial
+            .transactions
+            .iter()
+            .enumerate()
+            .filter(|(_, transaction)| transaction.is_none())
+            .map(|(index, _)| index as u32)
+            .collect();
+        if !missing.is_empty() {
+            if self.partial.len() >= MAX_PARTIAL_BLOCKS {
+                let oldest = self
+                    .partial
+                    .iter()
+                    .min_by_key(|(_, partial)| partial.received_at)
+                    .map(|(hash, _)| hash.clone());
+                if let Some(oldest) = oldest {
+                    self.partial.remove(&oldest);
+                }
+            }
+            let header = partial.header.clone();
+            self.partial.insert(header.hash.clone(), partial);
+            return Reconstruction::Missing(header, missing);
+        }
+
+        let transactions: Vec<SignedTransaction> =
+            partial.transactions.into_iter().flatten().collect();
+        let header = partial.header;
+        // Short IDs can collide, or the peer lied about them.
+        if transactions_root(&transactions) != header.transactions_root {
+            return Reconstruction::Mismatch(header);
+        }
+        let block = Block {
+            index: header.index.clone(),
+            timestamp: header.timestamp,
+            prev_hash: header.prev_hash.clone(),
+            transactions,
+        };
+        Reconstruction::Complete(HashedBlock {
+            block,
+            hash: header.hash,
+            nonce: header.nonce,
+        })
+    }
+}
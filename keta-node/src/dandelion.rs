@@ -0,0 +1,163 @@
+//! Dandelion++ transaction propagation. A new transaction is first passed along a random path of
+//! single peers, the stem, before some node on the way broadcasts it, the fluff. Observers then
+//! only see it spreading from that node rather than from where it entered the network.
+//!
+//! Every epoch a node decides whether it fluffs whatever reaches it, or relays it to one of two
+//! peers picked for the epoch, each peer sending to it always being routed to the same one. Nodes
+//! on the stem keep an embargo timer and fluff a transaction themselves if it is not seen
+//! broadcast in time, in case it got lost on the way.
+
+use keta_crypto::Hash;
+use keta_network::Direction;
+use keta_network::PeerId;
+use keta_network::PeerInfo;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::HashMap;
+use std::time::Duration;
+use std::time::Instant;
+
+/// Peers transactions are relayed to during an epoch.
+const RELAYS: usize = 2;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Broadcasts transactions right away when disabled.
+    pub enabled: bool,
+    /// Chance of a node to fluff instead of relay during an epoch.
+    pub fluff_probability: f64,
+    pub epoch: Duration,
+    /// Minimum time a relayed transaction has to be seen broadcast in, before it gets fluffed
+    /// here. A random delay of up to the same again is added.
+    pub embargo: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            fluff_probability: 0.1,
+            epoch: Duration::from_secs(10 * 60),
+            embargo: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Dandelion {
+    config: Config,
+    epoch_started: Option<Instant>,
+    fluffing: bool,
+    relays: Vec<PeerId>,
+    /// Relay picked for each peer that sent transactions this epoch, `None` standing for this
+    /// node.
+    routes: HashMap<Option<PeerId>, PeerId>,
+    /// Transactions in their stem phase, with the time they get fluffed here if still unseen.
+    embargoes: HashMap<Hash, Instant>,
+}
+
+impl Dandelion {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            epoch_started: None,
+            fluffing: false,
+            relays: Vec::new(),
+            routes: HashMap::new(),
+            embargoes: HashMap::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Whether `hash` is in its stem phase and must not be announced or handed out.
+    pub fn is_stem(&self, hash: &Hash) -> bool {
+        self.embargoes.contains_key(hash)
+    }
+
+    /// Picks the peer to relay a transaction from `from`, or from this node if `None`, to.
+    /// Returns `None` if it is to be fluffed instead.
+    pub fn route(&mut self, from: Option<PeerId>, peers: &[(PeerId, PeerInfo)]) -> Option<PeerId> {
+        let now = Instant::now();
+        let connected = |relay: &PeerId| peers.iter().any(|(peer, _)| peer == relay);
+        let expired = match self.epoch_started {
+            Some(started) => now.duration_since(started) >= self.config.epoch,
+            None => true,
+        };
+        let missing = self.relays.is_empty() && !peers.is_empty();
+        if expired || missing || !self.relays.iter().all(connected) {
+            self.new_epoch(now, peers);
+        }
+        // Transactions from here always go the same way, regardless of the epoch mode.
+        if (self.fluffing && from.is_some()) || self.relays.is_empty() {
+            return None;
+        }
+
+        let relays = &self.relays;
+        let route = *self
+            .routes
+            .entry(from)
+            .or_insert_with(|| *relays.choose(&mut rand::thread_rng()).unwrap());
+        if Some(route) == from {
+            // Never back to where it came from, try the other relay.
+            return self
+                .relays
+                .iter()
+                .copied()
+                .find(|relay| Some(*relay) != from);
+        }
+        Some(route)
+    }
+
+    /// Starts the embargo of a transaction in its stem phase.
+    pub fn stemmed(&mut self, hash: Hash) {
+        let embargo = self.config.embargo;
+        let delay = embargo + embargo.mul_f64(rand::thread_rng().gen::<f64>());
+        self.embargoes.insert(hash, Instant::now() + delay);
+    }
+
+    /// Ends the stem phase of a transaction, it got broadcast or is gone.
+    pub fn fluffed(&mut self, hash: &Hash) {
+        self.embargoes.remove(hash);
+    }
+
+    /// Transactions whose embargo ran out, they are to be fluffed here.
+    pub fn expired(&mut self) -> Vec<Hash> {
+        let now = Instant::now();
+        let expired: Vec<Hash> = self
+            .embargoes
+            .iter()
+            .filter(|(_, until)| **until <= now)
+            .map(|(hash, _)| hash.clone())
+            .collect();
+        for hash in &expired {
+            self.embargoes.remove(hash);
+        }
+        expired
+    }
+
+    fn new_epoch(&mut self, now: Instant, peers: &[(PeerId, PeerInfo)]) {
+        let mut rng = rand::thread_rng();
+        // Relays are picked among the peers this node chose itself, when there are any, as those
+        // are harder for an attacker to take the place of.
+        let outbound: Vec<PeerId> = peers
+            .iter()
+            .filter(|(_, info)| info.direction == Direction::Outbound)
+            .map(|(peer, _)| *peer)
+            .collect();
+        let candidates = if outbound.is_empty() {
+            peers.iter().map(|(peer, _)| *peer).collect()
+        } else {
+            outbound
+        };
+        self.relays = candidates
+            .choose_multiple(&mut rng, RELAYS)
+            .copied()
+            .collect();
+        self.fluffing = rng.gen_bool(self.config.fluff_probability.clamp(0.0, 1.0));
+        self.routes.clear();
+        self.epoch_started = Some(now);
+    }
+}
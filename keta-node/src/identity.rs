@@ -0,0 +1,54 @@
+//! The keypair a node authenticates itself to peers with, kept across restarts so peers can pin
+//! it. Stored as the hex encoded secret key.
+
+use keta_crypto::Keypair;
+use keta_crypto::SecretKey;
+use std::io::Write;
+use std::path::Path;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("invalid secret key: {0}")]
+    SecretKey(#[from] keta_crypto::SecretKeyError),
+}
+
+/// Reads the identity at `path`, generating and writing a new one if there is none yet.
+pub fn load_or_generate(path: &Path) -> Result<Keypair, Error> {
+    match std::fs::read_to_string(path) {
+        Ok(secret) => {
+            let secret: SecretKey = secret.trim().parse()?;
+            Ok(Keypair::from_secret(secret))
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            let keypair = Keypair::generate();
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let mut options = std::fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            let mut file = options.open(path)?;
+            writeln!(file, "{}", keypair.secret)?;
+            tracing::info!("Generated node identity at {}", path.display());
+            Ok(keypair)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn identity_persists() {
+        let directory = std::env::temp_dir().join(format!("keta-identity-{}", std::process::id()));
+        let path = directory.join("identity");
+        let generated = super::load_or_generate(&path).unwrap();
+        let loaded = super::load_or_generate(&path).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(generated, loaded);
+    }
+}
@@ -1,5 +1,18 @@
+mod archive;
+mod chain;
 mod cli;
+mod commands;
+mod compact;
+mod dandelion;
+mod identity;
+mod mempool;
+mod network;
+mod orphans;
+mod peers;
 mod rpc;
+mod snapshot;
+mod state;
+mod sync;
 mod world;
 
 const LOG_ENVIRONMENT_VARIABLE: &str = "KETA_LOG";
@@ -26,6 +39,43 @@ fn init_logging() {
     tracing_subscriber::fmt().with_env_filter(env_filter).init();
 }
 
+/// Stores the addresses of every bootnode in the peer store.
+async fn add_bootnodes(peers: &peers::Peers, bootnodes: &[String]) -> Result<(), anyhow::Error> {
+    for bootnode in bootnodes {
+        match tokio::net::lookup_host(bootnode.as_str()).await {
+            Ok(addresses) => {
+                for address in addresses {
+                    peers.learned(address)?;
+                }
+            }
+            Err(err) => tracing::warn!("Failed to resolve bootnode {}: {}", bootnode, err),
+        }
+    }
+    Ok(())
+}
+
+async fn log_events(mut events: tokio::sync::broadcast::Receiver<world::Event>) {
+    use tokio::sync::broadcast::error::RecvError;
+    use world::Event;
+
+    loop {
+        match events.recv().await {
+            Ok(Event::NewBlock(block)) => {
+                tracing::debug!("New block {} at {}", block.hash, block.index)
+            }
+            Ok(Event::TransactionAdded(hash)) => tracing::debug!("New transaction {}", hash),
+            Ok(Event::TransactionReplaced { replaced, by }) => {
+                tracing::debug!("Transaction {} replaced by {}", replaced, by)
+            }
+            Ok(Event::TransactionDropped(dropped)) => {
+                tracing::debug!("Transaction {} dropped: {}", dropped.hash, dropped.reason)
+            }
+            Err(RecvError::Lagged(missed)) => tracing::debug!("Missed {} events", missed),
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
     use world::World;
@@ -33,10 +83,65 @@ async fn main() -> Result<(), anyhow::Error> {
     init_logging();
     let args = cli::parse_args();
     tracing::trace!("args: {:?}", args);
+    let chain_spec = args.network.chain_spec();
     let database = keta_node_db::Database::new(args.database)?;
-    let world = World::new(database)?;
-    let rpc_server = rpc::Server::new(world);
+    match args.command {
+        Some(cli::Command::Bootstrap(command)) => return command.run(database, &chain_spec),
+        Some(cli::Command::ExportBlocks(command)) => return command.run(database),
+        Some(cli::Command::ImportBlocks(command)) => return command.run(database, &chain_spec),
+        Some(cli::Command::Reindex(command)) => return command.run(database, &chain_spec),
+        Some(cli::Command::Snapshot(command)) => return command.run(database, &chain_spec),
+        Some(cli::Command::VerifyDb(command)) => return command.run(database, &chain_spec),
+        None => {}
+    }
+    let peers = peers::Peers::new(database.peers.clone(), database.bans.clone(), args.peers);
+    add_bootnodes(&peers, &args.bootnodes).await?;
+    tracing::info!("Joining {}", args.network);
+    let genesis = chain_spec.genesis().hash;
+    let world = std::sync::Arc::new(World::new(database, chain_spec.clone(), args.mempool)?);
+    tokio::spawn(log_events(world.subscribe()));
+
+    let identity = identity::load_or_generate(&args.identity)?;
+    if let Some(proxy) = &args.proxy {
+        tracing::info!("Connecting to peers through {}", proxy);
+    }
+    let (network, network_events) = keta_network::Network::start(keta_network::Config {
+        identity,
+        listen_address: args.p2p_address,
+        chain_id: chain_spec.chain_id.to_string(),
+        genesis,
+        ban_duration: args.ban_duration,
+        proxy: args.proxy,
+        max_inbound: args.max_inbound_peers,
+    })
+    .await?;
+    peers.restore_bans(&network)?;
+    tracing::info!(
+        "Listening for peers at {} as {}",
+        network.local_address(),
+        network.identity()
+    );
+    let (sync_status, sync_status_receiver) = tokio::sync::watch::channel(Default::default());
+    tokio::spawn(network::run(
+        world.clone(),
+        network.clone(),
+        network_events,
+        peers.clone(),
+        sync_status,
+        args.dandelion,
+    ));
+
+    let rpc_server = rpc::Server::new(world, network, peers, sync_status_receiver);
     tracing::info!("Start RPC-Server at {}", &args.rpc_address);
-    rpc_server.run(&args.rpc_address).await?;
+    tracing::info!("Start HTTP RPC-Server at {}", &args.http_rpc_address);
+    tracing::info!("Start IPC RPC-Server at {}", args.ipc_path.display());
+    rpc_server
+        .run(
+            &args.rpc_address,
+            args.http_rpc_address,
+            &args.http_rpc_access,
+            &args.ipc_path,
+        )
+        .await?;
     Ok(())
 }
@@ -0,0 +1,611 @@
+use crate::state;
+use crate::state::State;
+use crate::state::TransactionError;
+use chrono::DateTime;
+use chrono::Utc;
+use keta_core::account::Address;
+use keta_core::transaction::SignedTransaction;
+use keta_crypto::Hash;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Maximum number of pending transactions.
+    pub max_size: usize,
+    /// How much higher, in percent, the fee of a replacement transaction must be.
+    pub replacement_fee_bump: u64,
+}
+
+impl Config {
+    /// Lowest fee a transaction replacing one with `fee` has to pay.
+    pub fn replacement_fee(&self, fee: u64) -> u64 {
+        let bump = (fee.saturating_mul(self.replacement_fee_bump) / 100).max(1);
+        fee.saturating_add(bump)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_size: 10_000,
+            replacement_fee_bump: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum AdmissionError {
+    #[error("transaction is already pending")]
+    AlreadyKnown,
+
+    #[error("nonce too low: {received}, expected: {expected}")]
+    NonceTooLow { expected: u64, received: u64 },
+
+    #[error("nonce gap: {received}, expected: {expected}")]
+    NonceGap { expected: u64, received: u64 },
+
+    #[error("replacement fee too low: {fee}, required: {required}")]
+    ReplacementUnderpriced { fee: u64, required: u64 },
+
+    #[error("mempool is full and the fee is too low to evict anything")]
+    Full,
+
+    #[error("{0}")]
+    Transaction(#[from] TransactionError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("rejected: {0}")]
+    Rejected(#[from] AdmissionError),
+
+    #[error("state: {0}")]
+    State(#[from] state::Error),
+}
+
+impl From<TransactionError> for Error {
+    fn from(err: TransactionError) -> Self {
+        Self::Rejected(err.into())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub transaction: SignedTransaction,
+    pub hash: Hash,
+    pub received_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Admitted {
+    pub hash: Hash,
+    /// Pending transaction with the same sender and nonce that this one replaced.
+    pub replaced: Option<Hash>,
+    /// Transactions evicted to make room.
+    pub evicted: Vec<Hash>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Dropped {
+    pub hash: Hash,
+    pub reason: AdmissionError,
+}
+
+/// Pending transactions, validated on admission and kept as a contiguous chain of nonces per
+/// sender, starting at the sender's account nonce.
+#[derive(Debug)]
+pub struct Mempool {
+    config: Config,
+    entries: HashMap<Hash, Entry>,
+    senders: HashMap<Address, BTreeMap<u64, Hash>>,
+}
+
+impl Mempool {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            entries: HashMap::new(),
+            senders: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, hash: &Hash) -> Option<&Entry> {
+        self.entries.get(hash)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.values()
+    }
+
+    /// Nonce the next transaction from `address` should use, counting pending ones.
+    pub fn next_nonce(&self, address: &Address, state: &State) -> Result<u64, state::Error> {
+        let account = state.account(address)?;
+        let pending = self.senders.get(address).map_or(0, BTreeMap::len);
+        Ok(account.nonce + pending as u64)
+    }
+
+    /// Pending transactions sent from `address`, in nonce order.
+    pub fn pending_from<'a>(
+        &'a self,
+        address: &Address,
+    ) -> impl Iterator<Item = &'a SignedTransaction> + 'a {
+        self.senders
+            .get(address)
+            .into_iter()
+            .flat_map(BTreeMap::values)
+            .map(move |hash| &self.entries[hash].transaction)
+    }
+
+    /// Admits `transaction` for inclusion in the block at `height` or later.
+    pub fn insert(
+        &mut self,
+        transaction: SignedTransaction,
+        received_at: DateTime<Utc>,
+        height: u64,
+        state: &State,
+    ) -> Result<Admitted, Error> {
+        let admitted = self.check(&transaction, height, state, true)?;
+        if let Some(replaced) = &admitted.replaced {
+            self.remove(replaced);
+        }
+        for evicted in &admitted.evicted {
+            self.remove(evicted);
+        }
+        self.senders
+            .entry(transaction.from.clone())
+            .or_default()
+            .insert(transaction.nonce, admitted.hash.clone());
+        self.entries.insert(
+            admitted.hash.clone(),
+            Entry {
+                transaction,
+                hash: admitted.hash.clone(),
+                received_at,
+            },
+        );
+        Ok(admitted)
+    }
+
+    /// Whether `transaction` would be admitted and what it would displace, without changing the
+    /// mempool. The signature is only checked if `verify_signature` is set.
+    pub fn check(
+        &self,
+        transaction: &SignedTransaction,
+        height: u64,
+        state: &State,
+        verify_signature: bool,
+    ) -> Result<Admitted, Error> {
+        let hash = transaction.hash();
+        if self.entries.contains_key(&hash) {
+            return Err(AdmissionError::AlreadyKnown.into());
+        }
+        if verify_signature {
+            transaction
+                .verify(&transaction.from)
+                .map_err(|_| TransactionError::InvalidSignature)?;
+        }
+        state::check_expiry(transaction, height)?;
+
+        let account = state.account(&transaction.from)?;
+        let pending = self.senders.get(&transaction.from);
+        let expected = account.nonce + pending.map_or(0, BTreeMap::len) as u64;
+        if transaction.nonce < account.nonce {
+            return Err(AdmissionError::NonceTooLow {
+                expected: account.nonce,
+                received: transaction.nonce,
+            }
+            .into());
+        }
+        if transaction.nonce > expected {
+            return Err(AdmissionError::NonceGap {
+                expected,
+                received: transaction.nonce,
+            }
+            .into());
+        }
+
+        let replaced = pending.and_then(|pending| pending.get(&transaction.nonce));
+        if let Some(replaced) = replaced {
+            let required = self
+                .config
+                .replacement_fee(self.entries[replaced].transaction.fee);
+            if transaction.fee < required {
+                return Err(AdmissionError::ReplacementUnderpriced {
+                    fee: transaction.fee,
+                    required,
+                }
+                .into());
+            }
+        }
+
+        let pending_cost = pending
+            .into_iter()
+            .flat_map(BTreeMap::values)
+            .filter(|hash| Some(*hash) != replaced)
+            .map(|hash| self.entries[hash].transaction.cost())
+            .chain(std::iter::once(transaction.cost()))
+            .try_fold(0u64, |total, cost| total.checked_add(cost?))
+            .ok_or(TransactionError::BalanceOverflow)?;
+        if pending_cost > account.balance {
+            return Err(TransactionError::InsufficientBalance {
+                balance: account.balance,
+                required: pending_cost,
+            }
+            .into());
+        }
+
+        let replaced = replaced.cloned();
+        let mut evicted = Vec::new();
+        if replaced.is_none() && self.entries.len() >= self.config.max_size {
+            let candidate = self
+                .eviction_candidate(&transaction.from)
+                .filter(|candidate| candidate.transaction.fee < transaction.fee)
+                .map(|candidate| candidate.hash.clone())
+                .ok_or(AdmissionError::Full)?;
+            evicted.push(candidate);
+        }
+        Ok(Admitted {
+            hash,
+            replaced,
+            evicted,
+        })
+    }
+
+    /// Cheapest transaction that can go without breaking a nonce chain, i.e. the last one of
+    /// some other sender.
+    fn eviction_candidate(&self, except: &Address) -> Option<&Entry> {
+        self.senders
+            .iter()
+            .filter(|(sender, _)| *sender != except)
+            .filter_map(|(_, nonces)| nonces.values().next_back())
+            .map(|hash| &self.entries[hash])
+            .min_by_key(|entry| (entry.transaction.fee, Reverse(entry.received_at)))
+    }
+
+    fn remove(&mut self, hash: &Hash) -> Option<Entry> {
+        let entry = self.entries.remove(hash)?;
+        if let Some(nonces) = self.senders.get_mut(&entry.transaction.from) {
+            nonces.remove(&entry.transaction.nonce);
+            if nonces.is_empty() {
+                self.senders.remove(&entry.transaction.from);
+            }
+        }
+        Some(entry)
+    }
+
+    /// Pending transactions, highest fee first, without ever putting a transaction before one
+    /// from the same sender with a lower nonce.
+    pub fn select(&self) -> Vec<SignedTransaction> {
+        let mut chains: HashMap<&Address, _> = self
+            .senders
+            .iter()
+            .map(|(sender, nonces)| (sender, nonces.values()))
+            .collect();
+        let mut heads = BinaryHeap::new();
+        for (sender, chain) in chains.iter_mut() {
+            if let Some(hash) = chain.next() {
+                let entry = &self.entries[hash];
+                heads.push((
+                    entry.transaction.fee,
+                    Reverse(entry.received_at),
+                    *sender,
+                    hash,
+                ));
+            }
+        }
+
+        let mut transactions = Vec::with_capacity(self.entries.len());
+        while let Some((_, _, sender, hash)) = heads.pop() {
+            transactions.push(self.entries[hash].transaction.clone());
+            if let Some(hash) = chains.get_mut(sender).and_then(Iterator::next) {
+                let entry = &self.entries[hash];
+                heads.push((
+                    entry.transaction.fee,
+                    Reverse(entry.received_at),
+                    sender,
+                    hash,
+                ));
+            }
+        }
+        transactions
+    }
+
+    /// Re-checks every pending transaction against `state` and the height of the next block,
+    /// typically after a new block. Drops transactions that were included, expired or became
+    /// invalid, together with everything queued behind them.
+    pub fn revalidate(&mut self, state: &State, height: u64) -> Result<Vec<Dropped>, state::Error> {
+        let mut dropped = Vec::new();
+        let senders: Vec<Address> = self.senders.keys().cloned().collect();
+        for sender in senders {
+            let account = state.account(&sender)?;
+            let mut balance = account.balance;
+            let mut expected = account.nonce;
+            let mut failed = false;
+            for (nonce, hash) in self.senders[&sender].clone() {
+                let transaction = &self.entries[&hash].transaction;
+                let cost = transaction.cost();
+                let reason = if nonce < account.nonce {
+                    Some(AdmissionError::NonceTooLow {
+                        expected: account.nonce,
+                        received: nonce,
+                    })
+                } else if failed || nonce != expected {
+                    Some(AdmissionError::NonceGap {
+                        expected,
+                        received: nonce,
+                    })
+                } else if let Err(err) = state::check_expiry(transaction, height) {
+                    Some(err.into())
+                } else {
+                    match cost.and_then(|cost| balance.checked_sub(cost)) {
+                        Some(remaining) => {
+                            balance = remaining;
+                            expected += 1;
+                            None
+                        }
+                        None => Some(
+                            TransactionError::InsufficientBalance {
+                                balance,
+                                required: cost.unwrap_or(u64::MAX),
+                            }
+                            .into(),
+                        ),
+                    }
+                };
+                if let Some(reason) = reason {
+                    failed |= nonce >= account.nonce;
+                    self.remove(&hash);
+                    dropped.push(Dropped { hash, reason });
+                }
+            }
+        }
+        Ok(dropped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AdmissionError;
+    use super::Config;
+    use super::Error;
+    use super::Mempool;
+    use crate::state::State;
+    use crate::state::TransactionError;
+    use keta_core::account::Account;
+    use keta_core::transaction::SignedTransaction;
+    use keta_core::transaction::Transaction;
+    use keta_crypto::Keypair;
+    use std::collections::BTreeMap;
+
+    fn transaction(from: &Keypair, nonce: u64, fee: u64) -> SignedTransaction {
+        expiring_transaction(from, nonce, fee, None)
+    }
+
+    fn expiring_transaction(
+        from: &Keypair,
+        nonce: u64,
+        fee: u64,
+        valid_until: Option<u64>,
+    ) -> SignedTransaction {
+        Transaction {
+            from: from.public.clone(),
+            to: Keypair::generate().public,
+            value: 10,
+            fee,
+            nonce,
+            valid_until,
+        }
+        .sign(from)
+    }
+
+    fn state(accounts: &[(&Keypair, u64, u64)]) -> State<'static> {
+        State::with_accounts(
+            accounts
+                .iter()
+                .map(|(keypair, balance, nonce)| {
+                    (
+                        keypair.public.clone(),
+                        Account {
+                            balance: *balance,
+                            nonce: *nonce,
+                        },
+                    )
+                })
+                .collect::<BTreeMap<_, _>>(),
+        )
+    }
+
+    fn rejection(result: Result<super::Admitted, Error>) -> AdmissionError {
+        match result {
+            Err(Error::Rejected(err)) => err,
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn admission() {
+        let alice = Keypair::generate();
+        let state = state(&[(&alice, 25, 1)]);
+        let mut mempool = Mempool::new(Config::default());
+        let now = chrono::Utc::now();
+
+        let first = transaction(&alice, 1, 1);
+        mempool.insert(first.clone(), now, 1, &state).unwrap();
+        assert!(matches!(
+            rejection(mempool.insert(first, now, 1, &state)),
+            AdmissionError::AlreadyKnown
+        ));
+        assert!(matches!(
+            rejection(mempool.insert(transaction(&alice, 0, 1), now, 1, &state)),
+            AdmissionError::NonceTooLow { .. }
+        ));
+        assert!(matches!(
+            rejection(mempool.insert(transaction(&alice, 3, 1), now, 1, &state)),
+            AdmissionError::NonceGap { .. }
+        ));
+        assert!(matches!(
+            rejection(mempool.insert(transaction(&alice, 2, 5), now, 1, &state)),
+            AdmissionError::Transaction(TransactionError::InsufficientBalance { .. })
+        ));
+
+        let mut forged = transaction(&alice, 2, 1);
+        forged.transaction.value = 1;
+        assert!(matches!(
+            rejection(mempool.insert(forged, now, 1, &state)),
+            AdmissionError::Transaction(TransactionError::InvalidSignature)
+        ));
+        assert_eq!(mempool.len(), 1);
+    }
+
+    #[test]
+    fn select_by_fee_in_nonce_order() {
+        let (alice, bob) = (Keypair::generate(), Keypair::generate());
+        let state = state(&[(&alice, 100, 0), (&bob, 100, 0)]);
+        let mut mempool = Mempool::new(Config::default());
+        let now = chrono::Utc::now();
+
+        let alice_low = transaction(&alice, 0, 1);
+        let alice_high = transaction(&alice, 1, 9);
+        let bob_mid = transaction(&bob, 0, 5);
+        for transaction in [alice_low.clone(), alice_high.clone(), bob_mid.clone()] {
+            mempool.insert(transaction, now, 1, &state).unwrap();
+        }
+        assert_eq!(mempool.select(), vec![bob_mid, alice_low, alice_high]);
+    }
+
+    #[test]
+    fn evict_when_full() {
+        let (alice, bob) = (Keypair::generate(), Keypair::generate());
+        let state = state(&[(&alice, 100, 0), (&bob, 100, 0)]);
+        let mut mempool = Mempool::new(Config {
+            max_size: 1,
+            ..Config::default()
+        });
+        let now = chrono::Utc::now();
+
+        let cheap = transaction(&alice, 0, 1);
+        mempool.insert(cheap.clone(), now, 1, &state).unwrap();
+        assert!(matches!(
+            rejection(mempool.insert(transaction(&bob, 0, 1), now, 1, &state)),
+            AdmissionError::Full
+        ));
+        let admitted = mempool
+            .insert(transaction(&bob, 0, 2), now, 1, &state)
+            .unwrap();
+        assert_eq!(admitted.evicted, vec![cheap.hash()]);
+        assert_eq!(mempool.len(), 1);
+    }
+
+    #[test]
+    fn check_without_admitting() {
+        let alice = Keypair::generate();
+        let state = state(&[(&alice, 100, 0)]);
+        let mempool = Mempool::new(Config::default());
+
+        let mut unsigned = transaction(&alice, 0, 1);
+        unsigned.signature = Keypair::generate().sign(b"unsigned");
+        assert!(matches!(
+            rejection(mempool.check(&unsigned, 1, &state, true)),
+            AdmissionError::Transaction(TransactionError::InvalidSignature)
+        ));
+        mempool.check(&unsigned, 1, &state, false).unwrap();
+        assert!(mempool.is_empty());
+    }
+
+    #[test]
+    fn replace_by_fee() {
+        let alice = Keypair::generate();
+        let state = state(&[(&alice, 100, 0)]);
+        let mut mempool = Mempool::new(Config {
+            max_size: 2,
+            replacement_fee_bump: 50,
+        });
+        let now = chrono::Utc::now();
+
+        let stuck = transaction(&alice, 0, 10);
+        let queued = transaction(&alice, 1, 10);
+        mempool.insert(stuck.clone(), now, 1, &state).unwrap();
+        mempool.insert(queued.clone(), now, 1, &state).unwrap();
+        assert!(matches!(
+            rejection(mempool.insert(transaction(&alice, 0, 14), now, 1, &state)),
+            AdmissionError::ReplacementUnderpriced {
+                fee: 14,
+                required: 15
+            }
+        ));
+        // Replacing must not free up balance the queued transactions rely on.
+        assert!(matches!(
+            rejection(mempool.insert(transaction(&alice, 0, 75), now, 1, &state)),
+            AdmissionError::Transaction(TransactionError::InsufficientBalance { .. })
+        ));
+
+        let replacement = transaction(&alice, 0, 15);
+        let admitted = mempool.insert(replacement.clone(), now, 1, &state).unwrap();
+        assert_eq!(admitted.replaced, Some(stuck.hash()));
+        assert!(admitted.evicted.is_empty());
+        assert_eq!(mempool.select(), vec![replacement, queued]);
+    }
+
+    #[test]
+    fn revalidate_after_block() {
+        let alice = Keypair::generate();
+        let mut mempool = Mempool::new(Config::default());
+        let now = chrono::Utc::now();
+        let transactions: Vec<_> = (0..3).map(|nonce| transaction(&alice, nonce, 1)).collect();
+        for transaction in &transactions {
+            mempool
+                .insert(transaction.clone(), now, 1, &state(&[(&alice, 100, 0)]))
+                .unwrap();
+        }
+
+        // First transaction got included, and the balance dropped below what the third needs.
+        let dropped = mempool.revalidate(&state(&[(&alice, 15, 1)]), 2).unwrap();
+        let dropped: Vec<_> = dropped.into_iter().map(|dropped| dropped.hash).collect();
+        assert_eq!(
+            dropped,
+            vec![transactions[0].hash(), transactions[2].hash()]
+        );
+        assert_eq!(mempool.select(), vec![transactions[1].clone()]);
+    }
+
+    #[test]
+    fn drop_expired() {
+        let (alice, bob) = (Keypair::generate(), Keypair::generate());
+        let state = state(&[(&alice, 100, 0), (&bob, 100, 0)]);
+        let mut mempool = Mempool::new(Config::default());
+        let now = chrono::Utc::now();
+
+        assert!(matches!(
+            rejection(mempool.insert(expiring_transaction(&alice, 0, 1, Some(1)), now, 2, &state)),
+            AdmissionError::Transaction(TransactionError::Expired {
+                valid_until: 1,
+                height: 2
+            })
+        ));
+
+        let expiring = expiring_transaction(&alice, 0, 1, Some(2));
+        let queued = transaction(&alice, 1, 1);
+        let lasting = transaction(&bob, 0, 1);
+        for transaction in [expiring.clone(), queued.clone(), lasting.clone()] {
+            mempool.insert(transaction, now, 2, &state).unwrap();
+        }
+        assert!(mempool.revalidate(&state, 2).unwrap().is_empty());
+
+        // Nothing got included at height 2, so the next block is too late for it.
+        let dropped = mempool.revalidate(&state, 3).unwrap();
+        let dropped: Vec<_> = dropped.into_iter().map(|dropped| dropped.hash).collect();
+        assert_eq!(dropped, vec![expiring.hash(), queued.hash()]);
+        assert_eq!(mempool.select(), vec![lasting]);
+    }
+}
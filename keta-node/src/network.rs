@@ -0,0 +1,826 @@
+//! Connects the world to its peers: gossips new blocks and pending transactions, answers their
+//! requests, exchanges addresses of other peers and drives the block download from peers that
+//! are ahead. Transactions submitted here are propagated with Dandelion++ unless disabled. New
+//! blocks are relayed as compact blocks, rebuilt from the pending transactions.
+//! Blocks arriving before their parent are held until the parent is fetched. Peers sending
+//! invalid blocks or transactions are reported for misbehaviour.
+
+use crate::compact::CompactBlocks;
+use crate::compact::Reconstruction;
+use crate::dandelion;
+use crate::dandelion::Dandelion;
+use crate::mempool;
+use crate::orphans;
+use crate::orphans::Orphans;
+use crate::peers;
+use crate::peers::Peers;
+use crate::state;
+use crate::sync;
+use crate::sync::Sync;
+use crate::world;
+use crate::world::World;
+use keta_core::block::HashedBlock;
+use keta_core::transaction::SignedTransaction;
+use keta_crypto::Hash;
+use keta_network::CompactBlock;
+use keta_network::Direction;
+use keta_network::Event;
+use keta_network::Message;
+use keta_network::Misbehaviour;
+use keta_network::Network;
+use keta_network::PeerId;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use tokio::sync::watch;
+
+/// Most blocks sent in reply to a single `GetBlocks`.
+const MAX_BLOCKS_PER_MESSAGE: u32 = 128;
+
+/// How often timed out requests are retried, lost peers replaced and sync progress updated.
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Service {
+    world: Arc<World>,
+    network: Network,
+    peers: Peers,
+    sync: Sync,
+    orphans: Orphans,
+    compact: CompactBlocks,
+    dandelion: Dandelion,
+    /// Transactions being admitted from peers, as opposed to submitted here.
+    received: HashSet<Hash>,
+    /// Peers asked for addresses that have not answered yet. Only the first answer is taken.
+    asked_addresses: HashSet<PeerId>,
+}
+
+pub async fn run(
+    world: Arc<World>,
+    network: Network,
+    mut events: mpsc::Receiver<Event>,
+    peers: Peers,
+    sync_status: watch::Sender<sync::Status>,
+    dandelion: dandelion::Config,
+) {
+    let mut world_events = world.subscribe();
+    let mut service = Service {
+        world,
+        network,
+        peers,
+        sync: Sync::new(sync_status),
+        orphans: Orphans::new(),
+        compact: CompactBlocks::new(),
+        dandelion: Dandelion::new(dandelion),
+        received: HashSet::new(),
+        asked_addresses: HashSet::new(),
+    };
+    if let Ok(height) = service.world.height() {
+        service.network.set_best_height(height);
+    }
+    let mut tick = tokio::time::interval(TICK_INTERVAL);
+    loop {
+        let result = tokio::select! {
+            event = events.recv() => match event {
+                Some(event) => service.handle_network_event(event),
+                None => break,
+            },
+            event = world_events.recv() => match event {
+                Ok(event) => service.handle_world_event(event),
+                Err(RecvError::Lagged(_)) => Ok(()),
+                Err(RecvError::Closed) => break,
+            },
+            _ = tick.tick() => service.tick(),
+        };
+        if let Err(err) = result {
+            tracing::warn!("Network: {}", err);
+        }
+    }
+}
+
+impl Service {
+    fn handle_world_event(&mut self, event: world::Event) -> Result<(), world::Error> {
+        match event {
+            world::Event::NewBlock(block) => {
+                let height = block.index.to_u64();
+                self.network.set_best_height(height);
+                for transaction in &block.transactions {
+                    self.dandelion.fluffed(&transaction.hash());
+                }
+                // Catching up, peers are not interested in every block on the way.
+                let message = if self.sync.is_syncing() {
+                    Message::NewBlock {
+                        hash: block.hash,
+                        height,
+                    }
+                } else {
+                    Message::CompactBlock(CompactBlock::new(&block))
+                };
+                self.network.broadcast(message, None);
+                self.compact.prune(height);
+                self.connect_orphans()?;
+            }
+            world::Event::TransactionAdded(hash)
+            | world::Event::TransactionReplaced { by: hash, .. } => self.transaction_added(hash),
+            world::Event::TransactionDropped(dropped) => self.dandelion.fluffed(&dropped.hash),
+        }
+        Ok(())
+    }
+
+    fn handle_network_event(&mut self, event: Event) -> Result<(), world::Error> {
+        match event {
+            Event::Connected(peer, info) => {
+                self.peers.connected(&info)?;
+                if info.direction == Direction::Outbound {
+                    self.asked_addresses.insert(peer);
+                    self.send(peer, Message::GetAddresses);
+                }
+                self.sync.peer_height(peer, info.best_height);
+                self.synchronize()
+            }
+            Event::Disconnected(peer, info) => {
+                self.peers.disconnected(&info)?;
+                self.asked_addresses.remove(&peer);
+                self.sync.peer_disconnected(peer);
+                self.tick()
+            }
+            Event::Message(peer, message) => self.handle_message(peer, message),
+            Event::Banned(ban) => Ok(self.peers.banned(&ban)?),
+        }
+    }
+
+    fn handle_message(&mut self, peer: PeerId, message: Message) -> Result<(), world::Error> {
+        match message {
+            Message::NewBlock { height, .. } => {
+                self.sync.peer_height(peer, height);
+                self.synchronize()?;
+            }
+            Message::CompactBlock(compact) => {
+                self.sync.peer_height(peer, compact.header.index.to_u64());
+                self.compact_block_received(peer, compact)?;
+                self.synchronize()?;
+            }
+            Message::GetBlockTransactions {
+                hash,
+                height,
+                indexes,
+            } => {
+                let block = self.world.get_blocks(height, 1)?.into_iter().next();
+                if let Some(block) = block.filter(|block| block.hash == hash) {
+                    let transactions = indexes
+                        .iter()
+                        .filter_map(|index| block.transactions.get(*index as usize).cloned())
+                        .collect();
+                    self.send(peer, Message::BlockTransactions { hash, transactions });
+                }
+            }
+            Message::BlockTransactions { hash, transactions } => {
+                if let Some(reconstruction) =
+                    self.compact
+                        .transactions_received(peer, &hash, transactions)
+                {
+                    self.reconstructed(peer, reconstruction)?;
+                }
+                self.synchronize()?;
+            }
+            Message::GetHeaders { locator, count } => {
+                let count = count.min(sync::MAX_HEADERS_PER_MESSAGE) as usize;
+                let locator = &locator[..locator.len().min(sync::MAX_LOCATOR_LENGTH)];
+                // Knowing none of them, the peer is not on our chain at all.
+                let headers = match self.world.locate(locator)? {
+                    Some(height) => self.world.get_headers(height + 1, count)?,
+                    None => Vec::new(),
+                };
+                self.send(peer, Message::Headers(headers));
+            }
+            Message::Headers(headers) => {
+                self.sync
+                    .headers_received(&self.world, &self.network, peer, headers)?;
+                self.synchronize()?;
+            }
+            Message::GetBlocks { from, count } => {
+                let count = count.min(MAX_BLOCKS_PER_MESSAGE) as usize;
+                let blocks = self.world.get_blocks(from, count)?;
+                self.send(peer, Message::Blocks(blocks));
+            }
+            Message::Blocks(blocks) => {
+                for block in self.sync.blocks_received(&self.network, peer, blocks) {
+                    self.block_received(peer, block)?;
+                }
+                self.sync.apply(&self.world, &self.network)?;
+                self.synchronize()?;
+            }
+            Message::NewTransactions(hashes) => {
+                // Broadcast by someone else, the stem ended.
+                for hash in &hashes {
+                    if self.dandelion.is_stem(hash) {
+                        self.fluff(hash.clone());
+                    }
+                }
+                let unknown: Vec<_> = hashes
+                    .into_iter()
+                    .filter(|hash| self.world.get_pending_transaction(hash).is_none())
+                    .collect();
+                if !unknown.is_empty() {
+                    self.send(peer, Message::GetTransactions(unknown));
+                }
+            }
+            Message::GetTransactions(hashes) => {
+                let transactions: Vec<_> = hashes
+                    .iter()
+                    .filter(|hash| !self.dandelion.is_stem(hash))
+                    .filter_map(|hash| self.world.get_pending_transaction(hash))
+                    .collect();
+                if !transactions.is_empty() {
+                    self.send(peer, Message::Transactions(transactions));
+                }
+            }
+            Message::Transactions(transactions) => {
+                for transaction in transactions {
+                    let hash = transaction.hash();
+                    if self.dandelion.is_stem(&hash) {
+                        self.fluff(hash);
+                        continue;
+                    }
+                    self.received.insert(hash.clone());
+                    if !self.admit(peer, transaction)? {
+                        self.received.remove(&hash);
+                    }
+                }
+            }
+            Message::StemTransaction(transaction) => {
+                let hash = transaction.hash();
+                if self.world.get_pending_transaction(&hash).is_some() {
+                    return Ok(());
+                }
+                match self.dandelion.route(Some(peer), &self.network.peers()) {
+                    Some(relay) => {
+                        self.dandelion.stemmed(hash.clone());
+                        if self.admit(peer, transaction.clone())? {
+                            self.send(relay, Message::StemTransaction(transaction));
+                        } else {
+                            self.dandelion.fluffed(&hash);
+                        }
+                    }
+                    // Broadcast like any other transaction once admitted.
+                    None => {
+                        self.received.insert(hash.clone());
+                        if !self.admit(peer, transaction)? {
+                            self.received.remove(&hash);
+                        }
+                    }
+                }
+            }
+            Message::GetAddresses => {
+                let addresses = self.peers.addresses()?;
+                self.send(peer, Message::Addresses(addresses));
+            }
+            Message::Addresses(addresses) => {
+                if !self.asked_addresses.remove(&peer) {
+                    tracing::debug!("Ignoring unsolicited addresses from {}", peer);
+                    return Ok(());
+                }
+                let mut stored = 0;
+                for address in addresses.into_iter().take(peers::MAX_ADDRESSES_PER_MESSAGE) {
+                    if stored == peers::MAX_ADDRESSES_PER_PEER {
+                        break;
+                    }
+                    if self.peers.learned(address)? {
+                        stored += 1;
+                    }
+                }
+                self.peers.maintain(&self.network)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn compact_block_received(
+        &mut self,
+        peer: PeerId,
+        compact: CompactBlock,
+    ) -> Result<(), world::Error> {
+        let tip_height = self.world.height()?;
+        let height = compact.header.index.to_u64();
+        let hash = &compact.header.hash;
+        if height <= tip_height
+            || height - tip_height > orphans::MAX_ORPHANS as u64
+            || self.orphans.contains(hash)
+            || self.compact.contains(hash)
+        {
+            return Ok(());
+        }
+        // Checked before asking for anything, it is all a peer can not fake cheaply.
+        if let Err(err) = state::validate_proof_of_work(&compact.header) {
+            tracing::warn!("Invalid block {} from peer {}: {}", hash, peer, err);
+            self.network.report(peer, Misbehaviour::InvalidBlock);
+            return Ok(());
+        }
+        let pending = self.world.pending_transactions();
+        let reconstruction = self.compact.received(peer, compact, pending);
+        self.reconstructed(peer, reconstruction)
+    }
+
+    fn reconstructed(
+        &mut self,
+        peer: PeerId,
+        reconstruction: Reconstruction,
+    ) -> Result<(), world::Error> {
+        match reconstruction {
+            Reconstruction::Complete(block) => self.block_received(peer, block)?,
+            Reconstruction::Missing(header, indexes) => {
+                let message = Message::GetBlockTransactions {
+                    hash: header.hash.clone(),
+                    height: header.index.to_u64(),
+                    indexes,
+                };
+                self.send(peer, message);
+            }
+            Reconstruction::Mismatch(header) => {
+                tracing::debug!("Fetching block {} from peer {} in full", header.hash, peer);
+                let message = Message::GetBlocks {
+                    from: header.index.to_u64(),
+                    count: 1,
+                };
+                self.send(peer, message);
+            }
+        }
+        Ok(())
+    }
+
+    /// Imports a block that was relayed or fetched outside of the block download if it extends
+    /// the tip. Blocks further ahead are held as orphans while their missing ancestors are
+    /// requested from the same peer.
+    fn block_received(&mut self, peer: PeerId, block: HashedBlock) -> Result<(), world::Error> {
+        let tip = self.world.tip()?;
+        let tip_height = tip.index.to_u64();
+        let height = block.index.to_u64();
+        if height <= tip_height || self.orphans.contains(&block.hash) {
+            return Ok(());
+        }
+        if block.prev_hash == tip.hash {
+            if self.import(peer, &block)? {
+                self.connect_orphans()?;
+            }
+            return Ok(());
+        }
+        if height - tip_height > orphans::MAX_ORPHANS as u64 {
+            // Too far ahead to fill the gap this way, the block download catches up instead.
+            return Ok(());
+        }
+        if let Err(err) = state::validate_proof_of_work(&block.header()) {
+            tracing::warn!("Invalid block {} from peer {}: {}", block.hash, peer, err);
+            self.network.report(peer, Misbehaviour::InvalidBlock);
+            return Ok(());
+        }
+
+        let hash = block.hash.clone();
+        tracing::debug!("Holding orphan block {} from peer {}", hash, peer);
+        self.orphans.insert(peer, block);
+        let root = match self.orphans.root(&hash) {
+            Some(root) => root.index.to_u64(),
+            None => return Ok(()),
+        };
+        // A root right above the tip is on a fork of ours, there is nothing to fetch.
+        if root > tip_height + 1 {
+            let message = Message::GetBlocks {
+                from: tip_height + 1,
+                count: (root - tip_height - 1) as u32,
+            };
+            self.send(peer, message);
+        }
+        Ok(())
+    }
+
+    /// Imports held orphans that extend the tip, and the ones built on those in turn.
+    fn connect_orphans(&mut self) -> Result<(), world::Error> {
+        let mut tip = self.world.tip()?;
+        self.orphans.prune(tip.index.to_u64());
+        let mut parents = vec![tip.hash.clone()];
+        while let Some(parent) = parents.pop() {
+            for (peer, block) in self.orphans.take_children(&parent) {
+                // A sibling already got connected, this one is on a fork.
+                if block.prev_hash != tip.hash {
+                    continue;
+                }
+                if self.import(peer, &block)? {
+                    parents.push(block.hash.clone());
+                    tip = block;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Imports `block` from `peer`, reporting the peer if the block is invalid. Returns whether
+    /// the block was imported.
+    fn import(&self, peer: PeerId, block: &HashedBlock) -> Result<bool, world::Error> {
+        match self.world.import_block(block) {
+            Ok(()) => Ok(true),
+            Err(world::Error::State(state::Error::Block(err))) if err.is_proof_failure() => {
+                tracing::warn!("Invalid block {} from peer {}: {}", block.hash, peer, err);
+                self.network.report(peer, Misbehaviour::InvalidBlock);
+                Ok(false)
+            }
+            // Does not fit on our chain, e.g. the tip moved in the meantime or the block is on a
+            // fork.
+            Err(world::Error::State(state::Error::Block(err))) => {
+                tracing::debug!("Not importing block {}: {}", block.hash, err);
+                Ok(false)
+            }
+            Err(world::Error::State(state::Error::Transaction(err))) => {
+                tracing::debug!("Not importing block {}: {}", block.hash, err);
+                Ok(false)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Passes a transaction that entered the mempool on. Ones submitted here start their stem
+    /// phase, the rest are broadcast.
+    fn transaction_added(&mut self, hash: Hash) {
+        if self.dandelion.is_stem(&hash) {
+            return;
+        }
+        let submitted = !self.received.remove(&hash);
+        if submitted && self.dandelion.is_enabled() {
+            let relay = self.dandelion.route(None, &self.network.peers());
+            let transaction = self.world.get_pending_transaction(&hash);
+            if let (Some(relay), Some(transaction)) = (relay, transaction) {
+                self.dandelion.stemmed(hash);
+                self.send(relay, Message::StemTransaction(transaction));
+                return;
+            }
+        }
+        self.network
+            .broadcast(Message::NewTransactions(vec![hash]), None);
+    }
+
+    /// Ends the stem phase of a transaction by broadcasting it.
+    fn fluff(&mut self, hash: Hash) {
+        self.dandelion.fluffed(&hash);
+        self.network
+            .broadcast(Message::NewTransactions(vec![hash]), None);
+    }
+
+    /// Adds a transaction from `peer` to the mempool, reporting the peer if it is at fault for a
+    /// rejection. Returns whether the transaction was admitted.
+    fn admit(&self, peer: PeerId, transaction: SignedTransaction) -> Result<bool, world::Error> {
+        let hash = transaction.hash();
+        match self.world.send_transaction(transaction) {
+            Ok(_) => Ok(true),
+            Err(world::Error::Mempool(mempool::Error::Rejected(err))) => {
+                tracing::debug!("Transaction {} from peer {}: {}", hash, peer, err);
+                if let Some(misbehaviour) = rejection_misbehaviour(&err) {
+                    self.network.report(peer, misbehaviour);
+                }
+                Ok(false)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn tick(&mut self) -> Result<(), world::Error> {
+        // Not seen broadcast in time, it may have been lost along the stem.
+        for hash in self.dandelion.expired() {
+            if self.world.get_pending_transaction(&hash).is_some() {
+                self.fluff(hash);
+            }
+        }
+        self.peers.maintain(&self.network)?;
+        self.synchronize()
+    }
+
+    fn synchronize(&mut self) -> Result<(), world::Error> {
+        self.sync.schedule(&self.world, &self.network)?;
+        self.sync.update_status(&self.world)
+    }
+
+    fn send(&self, peer: PeerId, message: Message) {
+        if let Err(err) = self.network.send(peer, message) {
+            tracing::debug!("Failed to send to peer {}: {}", peer, err);
+        }
+    }
+}
+
+/// How much a peer is to blame for sending a transaction the mempool rejected. Transactions that
+/// are already known, or got rejected because of what else is pending here, are not its fault.
+fn rejection_misbehaviour(err: &mempool::AdmissionError) -> Option<Misbehaviour> {
+    use mempool::AdmissionError;
+    use state::TransactionError;
+
+    match err {
+        AdmissionError::AlreadyKnown | AdmissionError::NonceGap { .. } | AdmissionError::Full => {
+            None
+        }
+        AdmissionError::Transaction(TransactionError::InvalidSignature) => {
+            Some(Misbehaviour::InvalidTransaction)
+        }
+        AdmissionError::NonceTooLow { .. }
+        | AdmissionError::ReplacementUnderpriced { .. }
+        | AdmissionError::Transaction(_) => Some(Misbehaviour::RejectedTransaction),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dandelion;
+    use crate::mempool;
+    use crate::peers;
+    use crate::peers::Peers;
+    use crate::world::World;
+    use keta_core::block::HashedBlock;
+    use keta_core::chain_spec::ChainSpec;
+    use keta_core::transaction::Transaction;
+    use keta_crypto::Keypair;
+    use keta_network::CompactBlock;
+    use keta_network::Config;
+    use keta_network::Event;
+    use keta_network::Message;
+    use keta_network::Network;
+    use keta_node_db::Database;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    async fn node() -> (Arc<World>, Network) {
+        let (world, network, _) = node_with_peers().await;
+        (world, network)
+    }
+
+    async fn node_with_peers() -> (Arc<World>, Network, Peers) {
+        let database = Database::temporary().unwrap();
+        let peers = Peers::new(
+            database.peers.clone(),
+            database.bans.clone(),
+            peers::Config::default(),
+        );
+        let world =
+            Arc::new(World::new(database, ChainSpec::MAINNET, mempool::Config::default()).unwrap());
+        let (network, events) = Network::start(Config {
+            identity: Keypair::generate(),
+            listen_address: "127.0.0.1:0".parse().unwrap(),
+            chain_id: ChainSpec::MAINNET.chain_id.to_string(),
+            genesis: ChainSpec::MAINNET.genesis().hash,
+            ban_duration: Duration::from_secs(60),
+            proxy: None,
+            max_inbound: 8,
+        })
+        .await
+        .unwrap();
+        let (status, _) = tokio::sync::watch::channel(Default::default());
+        tokio::spawn(super::run(
+            world.clone(),
+            network.clone(),
+            events,
+            peers.clone(),
+            status,
+            dandelion::Config {
+                embargo: Duration::from_millis(500),
+                ..dandelion::Config::default()
+            },
+        ));
+        (world, network, peers)
+    }
+
+    async fn eventually(condition: impl Fn() -> bool) {
+        for _ in 0..500 {
+            if condition() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("condition not met in time");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn gossip_between_nodes() {
+        let (a, a_network) = node().await;
+        let (_, b_network) = node().await;
+        let (c, c_network) = node().await;
+        b_network.connect(a_network.local_address()).await.unwrap();
+        c_network.connect(b_network.local_address()).await.unwrap();
+
+        let block = a.generate_block().unwrap();
+        eventually(|| c.height().unwrap() == 1).await;
+        assert_eq!(c.get_blocks(1, 1).unwrap()[0].hash, block.hash);
+
+        let keypair = Keypair::generate();
+        let transaction = Transaction {
+            from: keypair.public.clone(),
+            to: Keypair::generate().public,
+            value: 0,
+            fee: 0,
+            nonce: 0,
+            valid_until: None,
+        }
+        .sign(&keypair);
+        let hash = c.send_transaction(transaction).unwrap().hash;
+        eventually(|| a.get_pending_transaction(&hash).is_some()).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn initial_block_download() {
+        let (a, a_network) = node().await;
+        for _ in 0..100 {
+            a.generate_block().unwrap();
+        }
+        let (b, b_network) = node().await;
+        b_network.connect(a_network.local_address()).await.unwrap();
+        eventually(|| b.height().unwrap() == 100).await;
+
+        // Downloads bodies from both peers that have them.
+        let (c, c_network) = node().await;
+        c_network.connect(a_network.local_address()).await.unwrap();
+        c_network.connect(b_network.local_address()).await.unwrap();
+        eventually(|| c.height().unwrap() == 100).await;
+        assert_eq!(c.tip().unwrap().hash, a.tip().unwrap().hash);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn switches_to_longer_fork() {
+        let (a, a_network) = node().await;
+        let (b, b_network) = node().await;
+        a.generate_block().unwrap();
+        a.generate_block().unwrap();
+        // Differs from the first block of `a`, even if mined in the same second.
+        let keypair = Keypair::generate();
+        let transaction = Transaction {
+            from: keypair.public.clone(),
+            to: keypair.public.clone(),
+            value: 0,
+            fee: 0,
+            nonce: 0,
+            valid_until: None,
+        }
+        .sign(&keypair);
+        let hash = transaction.hash();
+        b.send_transaction(transaction).unwrap();
+        b.generate_block().unwrap();
+        b_network.connect(a_network.local_address()).await.unwrap();
+
+        eventually(|| b.tip().unwrap() == a.tip().unwrap()).await;
+        // Not part of the chain of `a`, so it is pending again.
+        assert!(b.get_pending_transaction(&hash).is_some());
+        let address = a_network.local_address().ip();
+        assert!(!b_network.is_banned(address));
+        assert_eq!(b_network.peers().len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn stem_and_fluff_transaction() {
+        // A line of nodes, each connected to the one before.
+        let mut nodes: Vec<(Arc<World>, Network)> = Vec::new();
+        for _ in 0..5 {
+            let (world, network) = node().await;
+            if let Some((_, previous)) = nodes.last() {
+                network.connect(previous.local_address()).await.unwrap();
+            }
+            nodes.push((world, network));
+        }
+
+        let keypair = Keypair::generate();
+        let transaction = Transaction {
+            from: keypair.public.clone(),
+            to: Keypair::generate().public,
+            value: 0,
+            fee: 0,
+            nonce: 0,
+            valid_until: None,
+        }
+        .sign(&keypair);
+        let (origin, _) = &nodes[2];
+        let hash = origin.send_transaction(transaction).unwrap().hash;
+        eventually(|| {
+            nodes
+                .iter()
+                .all(|(world, _)| world.get_pending_transaction(&hash).is_some())
+        })
+        .await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn connect_orphan_blocks() {
+        let source = World::new(
+            Database::temporary().unwrap(),
+            ChainSpec::MAINNET,
+            mempool::Config::default(),
+        )
+        .unwrap();
+        let blocks: Vec<HashedBlock> = (0..3).map(|_| source.generate_block().unwrap()).collect();
+        let (world, network) = node().await;
+        let (peer, mut events) = Network::start(Config {
+            identity: Keypair::generate(),
+            listen_address: "127.0.0.1:0".parse().unwrap(),
+            chain_id: ChainSpec::MAINNET.chain_id.to_string(),
+            genesis: ChainSpec::MAINNET.genesis().hash,
+            ban_duration: Duration::from_secs(60),
+            proxy: None,
+            max_inbound: 8,
+        })
+        .await
+        .unwrap();
+        let id = peer.connect(network.local_address()).await.unwrap();
+
+        // The last block first, its parents have to be fetched from the peer that sent it.
+        peer.send(id, Message::Blocks(vec![blocks[2].clone()]))
+            .unwrap();
+        loop {
+            match events.recv().await.unwrap() {
+                Event::Message(_, Message::GetBlocks { from, count }) => {
+                    assert_eq!((from, count), (1, 2));
+                    break;
+                }
+                _ => continue,
+            }
+        }
+        peer.send(id, Message::Blocks(blocks[..2].to_vec()))
+            .unwrap();
+        eventually(|| world.height().unwrap() == 3).await;
+        assert_eq!(world.tip().unwrap().hash, blocks[2].hash);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn rebuild_compact_block() {
+        let transaction = || {
+            let keypair = Keypair::generate();
+            Transaction {
+                from: keypair.public.clone(),
+                to: Keypair::generate().public,
+                value: 0,
+                fee: 0,
+                nonce: 0,
+                valid_until: None,
+            }
+            .sign(&keypair)
+        };
+        let (known, missing) = (transaction(), transaction());
+        let source = World::new(
+            Database::temporary().unwrap(),
+            ChainSpec::MAINNET,
+            mempool::Config::default(),
+        )
+        .unwrap();
+        source.send_transaction(known.clone()).unwrap();
+        source.send_transaction(missing.clone()).unwrap();
+        let block = source.generate_block().unwrap();
+        let index = block
+            .transactions
+            .iter()
+            .position(|transaction| *transaction == missing)
+            .unwrap() as u32;
+
+        let (world, network) = node().await;
+        world.send_transaction(known).unwrap();
+        let (peer, mut events) = Network::start(Config {
+            identity: Keypair::generate(),
+            listen_address: "127.0.0.1:0".parse().unwrap(),
+            chain_id: ChainSpec::MAINNET.chain_id.to_string(),
+            genesis: ChainSpec::MAINNET.genesis().hash,
+            ban_duration: Duration::from_secs(60),
+            proxy: None,
+            max_inbound: 8,
+        })
+        .await
+        .unwrap();
+        let id = peer.connect(network.local_address()).await.unwrap();
+
+        // Only the transaction it does not have is fetched.
+        let compact = CompactBlock::new(&block);
+        peer.send(id, Message::CompactBlock(compact)).unwrap();
+        loop {
+            if let Event::Message(_, Message::GetBlockTransactions { indexes, .. }) =
+                events.recv().await.unwrap()
+            {
+                assert_eq!(indexes, vec![index]);
+                break;
+            }
+        }
+        let message = Message::BlockTransactions {
+            hash: block.hash.clone(),
+            transactions: vec![missing],
+        };
+        peer.send(id, message).unwrap();
+        eventually(|| world.height().unwrap() == 1).await;
+        assert_eq!(world.tip().unwrap().hash, block.hash);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn discover_peers_through_bootnode() {
+        let (_, a_network, _) = node_with_peers().await;
+        let (_, b_network, _) = node_with_peers().await;
+        a_network.connect(b_network.local_address()).await.unwrap();
+
+        // Only knows `a`, learns about `b` from it.
+        let (_, c_network, c_peers) = node_with_peers().await;
+        c_peers.learned(a_network.local_address()).unwrap();
+        eventually(|| c_network.peers().len() == 2).await;
+        let listening: Vec<_> = c_network
+            .peers()
+            .into_iter()
+            .map(|(_, info)| info.listen_address.port())
+            .collect();
+        assert!(listening.contains(&b_network.local_address().port()));
+        eventually(|| c_peers.addresses().unwrap().len() == 2).await;
+    }
+}
@@ -0,0 +1,94 @@
+//! Blocks that arrived before their parent. They are held, up to a limit, until the parent gets
+//! imported and they can be connected to the chain.
+
+use keta_core::block::HashedBlock;
+use keta_crypto::Hash;
+use keta_network::PeerId;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+/// Most blocks held at once, the oldest are evicted first.
+pub const MAX_ORPHANS: usize = 64;
+
+#[derive(Debug, Default)]
+pub struct Orphans {
+    /// Held blocks by hash, with the peer they came from.
+    blocks: HashMap<Hash, (PeerId, HashedBlock)>,
+    /// Hashes of the held blocks, by the hash of their parent.
+    children: HashMap<Hash, Vec<Hash>>,
+    /// Hashes of the held blocks, oldest first.
+    order: VecDeque<Hash>,
+}
+
+impl Orphans {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.blocks.contains_key(hash)
+    }
+
+    /// Holds `block` until its parent arrives, evicting the oldest block if the pool is full.
+    pub fn insert(&mut self, peer: PeerId, block: HashedBlock) {
+        if self.contains(&block.hash) {
+            return;
+        }
+        while self.blocks.len() >= MAX_ORPHANS {
+            match self.order.front().cloned() {
+                Some(oldest) => self.remove(&oldest),
+                None => break,
+            }
+        }
+        self.children
+            .entry(block.prev_hash.clone())
+            .or_default()
+            .push(block.hash.clone());
+        self.order.push_back(block.hash.clone());
+        self.blocks.insert(block.hash.clone(), (peer, block));
+    }
+
+    /// The lowest held ancestor of the held block `hash`, the one whose parent is missing.
+    pub fn root(&self, hash: &Hash) -> Option<&HashedBlock> {
+        let mut root = &self.blocks.get(hash)?.1;
+        while let Some((_, parent)) = self.blocks.get(&root.prev_hash) {
+            root = parent;
+        }
+        Some(root)
+    }
+
+    /// Removes and returns the held blocks built directly on `parent`.
+    pub fn take_children(&mut self, parent: &Hash) -> Vec<(PeerId, HashedBlock)> {
+        let children = self.children.remove(parent).unwrap_or_default();
+        self.order.retain(|hash| !children.contains(hash));
+        children
+            .iter()
+            .filter_map(|hash| self.blocks.remove(hash))
+            .collect()
+    }
+
+    /// Drops held blocks at or below `height`, they can no longer extend the chain.
+    pub fn prune(&mut self, height: u64) {
+        let stale: Vec<Hash> = self
+            .blocks
+            .values()
+            .filter(|(_, block)| block.index.to_u64() <= height)
+            .map(|(_, block)| block.hash.clone())
+            .collect();
+        for hash in stale {
+            self.remove(&hash);
+        }
+    }
+
+    fn remove(&mut self, hash: &Hash) {
+        if let Some((_, block)) = self.blocks.remove(hash) {
+            if let Some(siblings) = self.children.get_mut(&block.prev_hash) {
+                siblings.retain(|sibling| sibling != hash);
+                if siblings.is_empty() {
+                    self.children.remove(&block.prev_hash);
+                }
+            }
+            self.order.retain(|held| held != hash);
+        }
+    }
+}
@@ -0,0 +1,382 @@
+//! Remembers peer addresses and bans across restarts and keeps enough outbound connections open.
+
+use chrono::Utc;
+use keta_network::Ban;
+use keta_network::Direction;
+use keta_network::Network;
+use keta_network::PeerAddress;
+use keta_network::PeerInfo;
+use keta_node_db::BansTree;
+use keta_node_db::Peer;
+use keta_node_db::PeersTree;
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// Most addresses sent in, or taken from, a single `Addresses` message.
+pub const MAX_ADDRESSES_PER_MESSAGE: usize = 1000;
+
+/// Most new addresses taken from a single peer per connection, so one peer cannot crowd out the
+/// addresses learned from others.
+pub const MAX_ADDRESSES_PER_PEER: usize = 100;
+
+/// Addresses beyond this are not stored, so peers cannot fill up the database.
+const MAX_STORED_PEERS: usize = 10_000;
+
+/// Addresses failing this many times in a row are forgotten.
+const MAX_FAILURES: u32 = 10;
+
+/// Wait before retrying an address after its first failure, doubling with every further one.
+const BASE_RETRY_DELAY_SECONDS: i64 = 30;
+
+const MAX_RETRY_DELAY_SECONDS: i64 = 60 * 60;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Outbound connections to keep open.
+    pub target_peers: usize,
+    /// Peers to always stay connected to, on top of the target.
+    pub connect: Vec<PeerAddress>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            target_peers: 8,
+            connect: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Peers {
+    store: PeersTree,
+    bans: BansTree,
+    config: Config,
+    /// Addresses with a connection attempt underway.
+    dialing: Arc<Mutex<HashSet<SocketAddr>>>,
+}
+
+impl Peers {
+    pub fn new(store: PeersTree, bans: BansTree, config: Config) -> Self {
+        Self {
+            store,
+            bans,
+            config,
+            dialing: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// Stores an address heard of from configuration or another peer, unless already known.
+    /// Returns whether it was stored.
+    pub fn learned(&self, address: SocketAddr) -> Result<bool, keta_node_db::Error> {
+        if address.ip().is_unspecified() || address.port() == 0 {
+            return Ok(false);
+        }
+        if self.store.len() >= MAX_STORED_PEERS || self.store.get(&address)?.is_some() {
+            return Ok(false);
+        }
+        let now = Utc::now();
+        let peer = Peer {
+            last_seen: now,
+            failures: 0,
+            last_failure: now,
+            last_connected: None,
+        };
+        self.store.insert(&address, &peer)?;
+        Ok(true)
+    }
+
+    pub fn connected(&self, info: &PeerInfo) -> Result<(), keta_node_db::Error> {
+        match info.direction {
+            Direction::Outbound => self.seen(info.address),
+            // Nothing proves the peer actually listens where it says.
+            Direction::Inbound => self.learned(info.listen_address).map(drop),
+        }
+    }
+
+    pub fn disconnected(&self, info: &PeerInfo) -> Result<(), keta_node_db::Error> {
+        match info.direction {
+            Direction::Outbound => self.seen(info.address),
+            Direction::Inbound => Ok(()),
+        }
+    }
+
+    fn seen(&self, address: SocketAddr) -> Result<(), keta_node_db::Error> {
+        let now = Utc::now();
+        let peer = Peer {
+            last_seen: now,
+            failures: 0,
+            last_failure: now,
+            last_connected: Some(now),
+        };
+        self.store.insert(&address, &peer)
+    }
+
+    fn failed(
+        &self,
+        address: SocketAddr,
+        err: &keta_network::Error,
+    ) -> Result<(), keta_node_db::Error> {
+        let configured = self
+            .config
+            .connect
+            .iter()
+            .any(|peer| peer.address == address);
+        let now = Utc::now();
+        let mut peer = match self.store.get(&address)? {
+            Some(peer) => peer,
+            None if configured => Peer {
+                last_seen: now,
+                failures: 0,
+                last_failure: now,
+                last_connected: None,
+            },
+            None => return Ok(()),
+        };
+        peer.failures += 1;
+        peer.last_failure = now;
+        let forget = peer.failures >= MAX_FAILURES && !configured;
+        if forget || matches!(err, keta_network::Error::SelfConnection) {
+            self.store.remove(&address)
+        } else {
+            self.store.insert(&address, &peer)
+        }
+    }
+
+    /// Hands the stored bans that have not expired yet to `network`.
+    pub fn restore_bans(&self, network: &Network) -> Result<(), keta_node_db::Error> {
+        let now = Utc::now();
+        for item in self.bans.iter() {
+            let (address, ban) = item?;
+            if ban.until <= now {
+                self.bans.remove(&address)?;
+                continue;
+            }
+            network.ban(Ban {
+                address,
+                until: ban.until,
+                reason: ban.reason,
+            });
+        }
+        Ok(())
+    }
+
+    /// Persists a ban the network imposed.
+    pub fn banned(&self, ban: &Ban) -> Result<(), keta_node_db::Error> {
+        let stored = keta_node_db::Ban {
+            until: ban.until,
+            reason: ban.reason.clone(),
+        };
+        self.bans.insert(&ban.address, &stored)
+    }
+
+    /// Lifts the ban on `address`, returning whether there was one.
+    pub fn unban(&self, network: &Network, address: IpAddr) -> Result<bool, keta_node_db::Error> {
+        self.bans.remove(&address)?;
+        Ok(network.unban(address).is_some())
+    }
+
+    /// Lifts every ban, returning how many there were.
+    pub fn clear_bans(&self, network: &Network) -> Result<usize, keta_node_db::Error> {
+        self.bans.clear()?;
+        let bans = network.bans();
+        for ban in &bans {
+            network.unban(ban.address);
+        }
+        Ok(bans.len())
+    }
+
+    /// Addresses worth passing on: the ones we connected to ourselves and that worked last time,
+    /// most recently connected first. Addresses only heard of are never passed on.
+    pub fn addresses(&self) -> Result<Vec<SocketAddr>, keta_node_db::Error> {
+        let mut peers = Vec::new();
+        for item in self.store.iter() {
+            let (address, peer) = item?;
+            if let (0, Some(last_connected)) = (peer.failures, peer.last_connected) {
+                peers.push((address, last_connected));
+            }
+        }
+        peers.sort_by_key(|(_, last_connected)| std::cmp::Reverse(*last_connected));
+        Ok(peers
+            .into_iter()
+            .take(MAX_ADDRESSES_PER_MESSAGE)
+            .map(|(address, _)| address)
+            .collect())
+    }
+
+    /// Dials the configured peers that are not connected, then stored addresses until the number
+    /// of outbound connections reaches the target, preferring the ones that failed least and were
+    /// seen last. Addresses that failed recently are left alone for a while, longer the more often
+    /// they failed.
+    pub fn maintain(&self, network: &Network) -> Result<(), keta_node_db::Error> {
+        let peers = network.peers();
+        let connected: HashSet<_> = peers.iter().map(|(_, info)| info.listen_address).collect();
+        let identities: HashSet<_> = peers.iter().map(|(_, info)| &info.identity).collect();
+        let now = Utc::now();
+        let mut dialing = self.dialing.lock().unwrap();
+
+        for peer in &self.config.connect {
+            let is_connected = match &peer.identity {
+                Some(identity) => identities.contains(identity),
+                None => connected.contains(&peer.address),
+            };
+            if is_connected || dialing.contains(&peer.address) {
+                continue;
+            }
+            if let Some(stored) = self.store.get(&peer.address)? {
+                if !is_due(&stored, now) {
+                    continue;
+                }
+            }
+            dialing.insert(peer.address);
+            self.dial(network, peer.clone());
+        }
+
+        let outbound = peers
+            .iter()
+            .filter(|(_, info)| info.direction == Direction::Outbound)
+            .count();
+        let wanted = self
+            .config
+            .target_peers
+            .saturating_sub(outbound + dialing.len());
+        if wanted == 0 {
+            return Ok(());
+        }
+        let mut candidates = Vec::new();
+        for item in self.store.iter() {
+            let (address, peer) = item?;
+            if connected.contains(&address) || dialing.contains(&address) || !is_due(&peer, now) {
+                continue;
+            }
+            candidates.push((address, peer));
+        }
+        candidates.sort_by_key(|(_, peer)| (peer.failures, std::cmp::Reverse(peer.last_seen)));
+        for (address, _) in candidates.into_iter().take(wanted) {
+            dialing.insert(address);
+            self.dial(
+                network,
+                PeerAddress {
+                    identity: None,
+                    address,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    fn dial(&self, network: &Network, peer: PeerAddress) {
+        let (peers, network) = (self.clone(), network.clone());
+        tokio::spawn(async move {
+            let result = match &peer.identity {
+                Some(identity) => network.connect_pinned(peer.address, identity).await,
+                None => network.connect(peer.address).await,
+            };
+            // Success is recorded when the connection shows up as an event.
+            if let Err(err) = result {
+                tracing::debug!("Failed to connect to {}: {}", peer, err);
+                if let Err(err) = peers.failed(peer.address, &err) {
+                    tracing::warn!("Failed to update peer {}: {}", peer, err);
+                }
+            }
+            peers.dialing.lock().unwrap().remove(&peer.address);
+        });
+    }
+}
+
+/// Whether enough time passed since the last failure to try `peer` again.
+fn is_due(peer: &Peer, now: chrono::DateTime<Utc>) -> bool {
+    peer.failures == 0 || now >= peer.last_failure + retry_delay(peer.failures)
+}
+
+fn retry_delay(failures: u32) -> chrono::Duration {
+    let seconds = BASE_RETRY_DELAY_SECONDS.saturating_mul(2i64.saturating_pow(failures - 1));
+    chrono::Duration::seconds(seconds.min(MAX_RETRY_DELAY_SECONDS))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::retry_delay;
+    use super::Config;
+    use super::Peers;
+    use super::MAX_RETRY_DELAY_SECONDS;
+    use keta_crypto::Hash;
+    use keta_network::Ban;
+    use keta_network::Network;
+    use keta_node_db::Database;
+    use std::time::Duration;
+
+    async fn network() -> Network {
+        let (network, _) = Network::start(keta_network::Config {
+            identity: keta_crypto::Keypair::generate(),
+            listen_address: "127.0.0.1:0".parse().unwrap(),
+            chain_id: "test".to_string(),
+            genesis: Hash::ZERO,
+            ban_duration: Duration::from_secs(60),
+            proxy: None,
+            max_inbound: 8,
+        })
+        .await
+        .unwrap();
+        network
+    }
+
+    #[test]
+    fn retry_delay_backs_off() {
+        assert_eq!(retry_delay(1), chrono::Duration::seconds(30));
+        assert_eq!(retry_delay(3), chrono::Duration::seconds(120));
+        assert_eq!(
+            retry_delay(9),
+            chrono::Duration::seconds(MAX_RETRY_DELAY_SECONDS)
+        );
+    }
+
+    #[test]
+    fn only_connected_addresses_are_passed_on() {
+        let database = Database::temporary().unwrap();
+        let peers = Peers::new(
+            database.peers.clone(),
+            database.bans.clone(),
+            Config::default(),
+        );
+        let (heard, connected) = (
+            "10.0.0.1:5455".parse().unwrap(),
+            "10.0.0.2:5455".parse().unwrap(),
+        );
+        assert!(peers.learned(heard).unwrap());
+        assert!(peers.learned(connected).unwrap());
+        assert!(!peers.learned(connected).unwrap());
+        assert!(peers.addresses().unwrap().is_empty());
+
+        peers.seen(connected).unwrap();
+        assert_eq!(peers.addresses().unwrap(), vec![connected]);
+    }
+
+    #[tokio::test]
+    async fn bans_survive_restart() {
+        let database = Database::temporary().unwrap();
+        let peers = Peers::new(
+            database.peers.clone(),
+            database.bans.clone(),
+            Config::default(),
+        );
+        let address = "10.0.0.1".parse().unwrap();
+        peers
+            .banned(&Ban {
+                address,
+                until: chrono::Utc::now() + chrono::Duration::hours(1),
+                reason: "invalid block".to_string(),
+            })
+            .unwrap();
+
+        let restarted = network().await;
+        peers.restore_bans(&restarted).unwrap();
+        assert!(restarted.is_banned(address));
+        assert!(peers.unban(&restarted, address).unwrap());
+        assert!(!restarted.is_banned(address));
+        assert!(database.bans.iter().next().is_none());
+    }
+}
@@ -1,24 +1,158 @@
+use crate::mempool;
+use crate::mempool::AdmissionError;
+use crate::peers::Peers;
+use crate::state;
+use crate::state::TransactionError;
+use crate::sync;
 use crate::world;
 use crate::world::World;
+use keta_core::account::Address;
 use keta_core::block::HashedBlock;
 use keta_core::transaction::SignedTransaction;
+use keta_crypto::Hash;
+use keta_crypto::Signature;
+use keta_network::Network;
+use keta_rpc::AccountUpdate;
+use keta_rpc::Ban;
+use keta_rpc::ChainInfo;
 use keta_rpc::Error;
+use keta_rpc::NodeStatus;
 use keta_rpc::RpcServer;
+use keta_rpc::SentTransaction;
+use keta_rpc::Simulation;
+use keta_rpc::SimulationRequest;
+use keta_rpc::SubscriptionSink;
+use keta_rpc::SyncStatus;
+use keta_rpc::TransactionStatus;
+use serde::Serialize;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tokio::sync::watch;
 
+#[derive(Clone)]
 pub struct Server {
-    world: World,
+    world: Arc<World>,
+    network: Network,
+    peers: Peers,
+    sync_status: watch::Receiver<sync::Status>,
 }
 
 impl From<world::Error> for Error {
     fn from(err: world::Error) -> Self {
-        Error::World(err.to_string())
+        match err {
+            world::Error::Database(err) => Error::Database(err.to_string()),
+            world::Error::State(err) => err.into(),
+            world::Error::Mempool(mempool::Error::Rejected(err)) => err.into(),
+            world::Error::Mempool(mempool::Error::State(err)) => err.into(),
+            world::Error::Replay(err) => Error::Database(err.to_string()),
+        }
+    }
+}
+
+impl From<state::Error> for Error {
+    fn from(err: state::Error) -> Self {
+        match err {
+            state::Error::Database(err) => Error::Database(err.to_string()),
+            state::Error::Block(err) => Error::InvalidBlock(err.to_string()),
+            state::Error::Transaction(err) => err.into(),
+        }
+    }
+}
+
+impl From<AdmissionError> for Error {
+    fn from(err: AdmissionError) -> Self {
+        match err {
+            AdmissionError::AlreadyKnown => Error::AlreadyKnown,
+            AdmissionError::NonceTooLow { expected, received } => {
+                Error::NonceTooLow { expected, received }
+            }
+            AdmissionError::NonceGap { expected, received } => {
+                Error::NonceGap { expected, received }
+            }
+            AdmissionError::ReplacementUnderpriced { fee, required } => {
+                Error::ReplacementUnderpriced { fee, required }
+            }
+            AdmissionError::Full => Error::MempoolFull,
+            AdmissionError::Transaction(err) => err.into(),
+        }
+    }
+}
+
+impl From<TransactionError> for Error {
+    fn from(err: TransactionError) -> Self {
+        match err {
+            TransactionError::InvalidSignature => Error::InvalidSignature,
+            TransactionError::InvalidNonce { expected, received } => {
+                Error::InvalidNonce { expected, received }
+            }
+            TransactionError::InsufficientBalance { balance, required } => {
+                Error::InsufficientBalance { balance, required }
+            }
+            TransactionError::BalanceOverflow => Error::BalanceOverflow,
+            TransactionError::Expired {
+                valid_until,
+                height,
+            } => Error::Expired {
+                valid_until,
+                height,
+            },
+        }
     }
 }
 
 impl RpcServer for Server {
-    fn send_transaction(&self, transaction: SignedTransaction) -> Result<(), keta_rpc::Error> {
-        self.world.send_transaction(transaction)?;
-        Ok(())
+    fn send_transaction(
+        &self,
+        transaction: SignedTransaction,
+    ) -> Result<SentTransaction, keta_rpc::Error> {
+        let admitted = self.world.send_transaction(transaction)?;
+        Ok(SentTransaction {
+            hash: admitted.hash,
+            replaced: admitted.replaced,
+        })
+    }
+
+    fn simulate_transaction(
+        &self,
+        request: SimulationRequest,
+    ) -> Result<Simulation, keta_rpc::Error> {
+        let signature = match request.signature {
+            Some(signature) => signature,
+            // Never checked, any signature will do.
+            None if request.skip_signature => Signature::from_bytes([0; 64]).unwrap(),
+            None => return Err(Error::InvalidSignature),
+        };
+        let transaction = SignedTransaction {
+            signature,
+            transaction: request.transaction,
+        };
+        let simulation = self
+            .world
+            .simulate_transaction(&transaction, !request.skip_signature)?;
+        Ok(Simulation {
+            replaced: simulation.admitted.replaced,
+            evicted: simulation.admitted.evicted,
+            accounts: simulation.accounts,
+        })
+    }
+
+    fn get_transaction_status(&self, hash: Hash) -> Result<TransactionStatus, keta_rpc::Error> {
+        let status = match self.world.transaction_status(&hash)? {
+            world::TransactionStatus::Unknown => TransactionStatus::Unknown,
+            world::TransactionStatus::Pending => TransactionStatus::Pending,
+            world::TransactionStatus::Included {
+                height,
+                confirmations,
+            } => TransactionStatus::Included {
+                height,
+                confirmations,
+            },
+            world::TransactionStatus::Dropped(reason) => TransactionStatus::Dropped {
+                reason: reason.to_string(),
+            },
+        };
+        Ok(status)
     }
 
     fn generate_block(&self) -> Result<HashedBlock, keta_rpc::Error> {
@@ -31,17 +165,192 @@ impl RpcServer for Server {
         Ok(balance)
     }
 
+    fn get_nonce(&self, address: keta_core::account::Address) -> Result<u64, keta_rpc::Error> {
+        let nonce = self.world.get_nonce(&address)?;
+        Ok(nonce)
+    }
+
+    fn get_chain_info(&self) -> Result<ChainInfo, keta_rpc::Error> {
+        let chain_spec = self.world.chain_spec();
+        let tip = self.world.tip()?;
+        Ok(ChainInfo {
+            chain_id: chain_spec.chain_id.to_string(),
+            genesis_hash: chain_spec.genesis().hash,
+            height: tip.index.to_u64(),
+            tip_hash: tip.hash.clone(),
+            tip_timestamp: tip.timestamp,
+            difficulty: keta_miner::TARGET_BITS,
+            total_supply: self.world.total_supply()?,
+        })
+    }
+
+    fn get_node_status(&self) -> Result<NodeStatus, keta_rpc::Error> {
+        Ok(NodeStatus {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            peer_count: self.network.peers().len() as u64,
+            mempool_size: self.world.mempool_size() as u64,
+            sync: self.sync_status(),
+        })
+    }
+
+    fn get_sync_status(&self) -> Result<SyncStatus, keta_rpc::Error> {
+        Ok(self.sync_status())
+    }
+
+    fn list_bans(&self) -> Result<Vec<Ban>, keta_rpc::Error> {
+        let bans = self
+            .network
+            .bans()
+            .into_iter()
+            .map(|ban| Ban {
+                address: ban.address,
+                until: ban.until,
+                reason: ban.reason,
+            })
+            .collect();
+        Ok(bans)
+    }
+
+    fn clear_ban(&self, address: IpAddr) -> Result<bool, keta_rpc::Error> {
+        let banned = self
+            .peers
+            .unban(&self.network, address)
+            .map_err(world::Error::from)?;
+        Ok(banned)
+    }
+
+    fn clear_bans(&self) -> Result<u64, keta_rpc::Error> {
+        let cleared = self
+            .peers
+            .clear_bans(&self.network)
+            .map_err(world::Error::from)?;
+        Ok(cleared as u64)
+    }
+
     fn get_all_blocks(&self) -> Result<Vec<keta_core::block::HashedBlock>, keta_rpc::Error> {
         todo!()
     }
+
+    fn subscribe_new_heads(&self, sink: SubscriptionSink) {
+        tokio::spawn(forward(self.world.subscribe(), sink, |event| match event {
+            world::Event::NewBlock(block) => Some(block.header()),
+            _ => None,
+        }));
+    }
+
+    fn subscribe_pending_transactions(&self, sink: SubscriptionSink) {
+        let world = self.world.clone();
+        tokio::spawn(forward(
+            self.world.subscribe(),
+            sink,
+            move |event| match event {
+                world::Event::TransactionAdded(hash)
+                | world::Event::TransactionReplaced { by: hash, .. } => {
+                    world.get_pending_transaction(&hash)
+                }
+                _ => None,
+            },
+        ));
+    }
+
+    fn subscribe_account(&self, mut sink: SubscriptionSink, address: Address) {
+        let world = self.world.clone();
+        // Subscribed before reading the current state, so no change gets lost in between.
+        let events = self.world.subscribe();
+        let mut last = None;
+        let mut changed = move || match account_update(&world, &address) {
+            Ok(update) if last != Some((update.balance, update.nonce)) => {
+                last = Some((update.balance, update.nonce));
+                Some(update)
+            }
+            Ok(_) => None,
+            Err(err) => {
+                tracing::warn!("Failed to read account {}: {}", address, err);
+                None
+            }
+        };
+        if let Some(update) = changed() {
+            if sink.send(&update).is_err() {
+                return;
+            }
+        }
+        // Blocks change balances and nonces, pending transactions change nonces.
+        tokio::spawn(forward(events, sink, move |_| changed()));
+    }
+}
+
+/// Sends what `item` makes of world events to `sink`, until the subscriber goes away.
+async fn forward<T: Serialize>(
+    mut events: broadcast::Receiver<world::Event>,
+    mut sink: SubscriptionSink,
+    mut item: impl FnMut(world::Event) -> Option<T>,
+) {
+    use tokio::sync::broadcast::error::RecvError;
+
+    loop {
+        match events.recv().await {
+            Ok(event) => {
+                if let Some(item) = item(event) {
+                    if sink.send(&item).is_err() {
+                        break;
+                    }
+                }
+            }
+            Err(RecvError::Lagged(missed)) => {
+                tracing::debug!("Subscription missed {} events", missed)
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+fn account_update(world: &World, address: &Address) -> Result<AccountUpdate, world::Error> {
+    Ok(AccountUpdate {
+        height: world.height()?,
+        balance: world.get_balance(address)?,
+        nonce: world.get_nonce(address)?,
+    })
 }
 
 impl Server {
-    pub fn new(world: World) -> Self {
-        Self { world }
+    fn sync_status(&self) -> SyncStatus {
+        let status = self.sync_status.borrow().clone();
+        SyncStatus {
+            syncing: status.syncing,
+            current_height: status.current_height,
+            target_height: status.target_height,
+            blocks_per_second: status.blocks_per_second,
+        }
     }
 
-    pub async fn run(self, address: &std::net::SocketAddr) -> Result<(), keta_rpc::Error> {
-        keta_rpc::serve(self, address).await
+    pub fn new(
+        world: Arc<World>,
+        network: Network,
+        peers: Peers,
+        sync_status: watch::Receiver<sync::Status>,
+    ) -> Self {
+        Self {
+            world,
+            network,
+            peers,
+            sync_status,
+        }
+    }
+
+    /// Serves RPC over WebSocket at `address`, over HTTP at `http_address` and over the Unix
+    /// socket at `ipc_path`. Admin methods are only available through the socket.
+    pub async fn run(
+        self,
+        address: &std::net::SocketAddr,
+        http_address: std::net::SocketAddr,
+        access_control: &keta_rpc::AccessControl,
+        ipc_path: &std::path::Path,
+    ) -> Result<(), keta_rpc::Error> {
+        tokio::try_join!(
+            keta_rpc::serve(self.clone(), address),
+            keta_rpc::serve_http(self.clone(), http_address, access_control),
+            keta_rpc::serve_ipc(self, ipc_path),
+        )?;
+        Ok(())
     }
 }
@@ -0,0 +1,262 @@
+//! Account state as of a given block. The binary form starts with a magic and a format version,
+//! followed by the bincode-encoded chain ID and genesis hash of the network it was taken on and
+//! the bincode-encoded `Snapshot`.
+
+use keta_core::account;
+use keta_core::account::Account;
+use keta_core::account::Address;
+use keta_core::block::HashedBlock;
+use keta_core::chain_spec::ChainSpec;
+use keta_crypto::Hash;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::io::Write;
+
+const MAGIC: &[u8; 4] = b"KETS";
+
+/// Bump whenever the encoding of `Snapshot` changes.
+pub const FORMAT_VERSION: u32 = 4;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("bincode: {0}")]
+    Bincode(#[from] bincode::Error),
+
+    #[error("json: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("not a snapshot")]
+    InvalidMagic,
+
+    #[error("snapshot format v{found} is not supported, expected v{supported}")]
+    UnsupportedVersion { found: u32, supported: u32 },
+
+    #[error("state root {computed} does not match {expected}")]
+    StateRootMismatch { expected: Hash, computed: Hash },
+
+    #[error("snapshot is of chain {found}, expected {expected}")]
+    ChainMismatch { expected: String, found: String },
+
+    #[error("snapshot has genesis block {found}, expected {expected}")]
+    GenesisMismatch { expected: Hash, found: Hash },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Csv,
+    Binary,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            "binary" => Ok(Self::Binary),
+            _ => Err(format!("unknown snapshot format: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Last block applied to `accounts`.
+    pub block: HashedBlock,
+    pub state_root: Hash,
+    pub accounts: BTreeMap<Address, Account>,
+}
+
+/// Network a binary snapshot was taken on.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    chain_id: String,
+    genesis: Hash,
+}
+
+impl Header {
+    fn new(chain_spec: &ChainSpec) -> Self {
+        Self {
+            chain_id: chain_spec.chain_id.to_string(),
+            genesis: chain_spec.genesis().hash,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonSnapshot<'a> {
+    height: u64,
+    block_hash: &'a Hash,
+    state_root: &'a Hash,
+    accounts: &'a BTreeMap<Address, Account>,
+}
+
+impl Snapshot {
+    pub fn new(block: HashedBlock, accounts: BTreeMap<Address, Account>) -> Self {
+        Self {
+            state_root: account::state_root(&accounts),
+            block,
+            accounts,
+        }
+    }
+
+    pub fn verify(&self) -> Result<(), Error> {
+        let computed = account::state_root(&self.accounts);
+        if computed != self.state_root {
+            return Err(Error::StateRootMismatch {
+                expected: self.state_root.clone(),
+                computed,
+            });
+        }
+        Ok(())
+    }
+
+    /// Writes the snapshot, taken on the network of `chain_spec`, in `format`.
+    pub fn write(
+        &self,
+        format: Format,
+        chain_spec: &ChainSpec,
+        mut writer: impl Write,
+    ) -> Result<(), Error> {
+        match format {
+            Format::Json => {
+                let snapshot = JsonSnapshot {
+                    height: self.block.index.to_u64(),
+                    block_hash: &self.block.hash,
+                    state_root: &self.state_root,
+                    accounts: &self.accounts,
+                };
+                serde_json::to_writer_pretty(&mut writer, &snapshot)?;
+                writeln!(writer)?;
+            }
+            Format::Csv => {
+                writeln!(writer, "address,balance")?;
+                for (address, account) in &self.accounts {
+                    writeln!(writer, "{},{}", address, account.balance)?;
+                }
+            }
+            Format::Binary => {
+                writer.write_all(MAGIC)?;
+                writer.write_all(&FORMAT_VERSION.to_be_bytes())?;
+                bincode::serialize_into(&mut writer, &Header::new(chain_spec))?;
+                bincode::serialize_into(&mut writer, self)?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Reads a binary snapshot and checks that it was taken on the network of `chain_spec` and
+    /// that its state root matches.
+    pub fn read(mut reader: impl Read, chain_spec: &ChainSpec) -> Result<Self, Error> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+        let mut version = [0; 4];
+        reader.read_exact(&mut version)?;
+        let version = u32::from_be_bytes(version);
+        if version != FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion {
+                found: version,
+                supported: FORMAT_VERSION,
+            });
+        }
+        let header: Header = bincode::deserialize_from(&mut reader)?;
+        let expected = Header::new(chain_spec);
+        if header.chain_id != expected.chain_id {
+            return Err(Error::ChainMismatch {
+                expected: expected.chain_id,
+                found: header.chain_id,
+            });
+        }
+        if header.genesis != expected.genesis {
+            return Err(Error::GenesisMismatch {
+                expected: expected.genesis,
+                found: header.genesis,
+            });
+        }
+        let snapshot: Self = bincode::deserialize_from(reader)?;
+        snapshot.verify()?;
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Error;
+    use super::Format;
+    use super::Snapshot;
+    use keta_core::account::Account;
+    use keta_core::chain_spec::ChainSpec;
+    use keta_crypto::Keypair;
+    use std::collections::BTreeMap;
+
+    fn snapshot() -> Snapshot {
+        let mut accounts = BTreeMap::new();
+        accounts.insert(
+            Keypair::generate().public,
+            Account {
+                balance: 10,
+                nonce: 1,
+            },
+        );
+        accounts.insert(
+            Keypair::generate().public,
+            Account {
+                balance: 20,
+                nonce: 0,
+            },
+        );
+        Snapshot::new(ChainSpec::MAINNET.genesis(), accounts)
+    }
+
+    #[test]
+    fn binary_write_read() {
+        let snapshot = snapshot();
+        let mut buffer = Vec::new();
+        snapshot
+            .write(Format::Binary, &ChainSpec::MAINNET, &mut buffer)
+            .unwrap();
+        assert_eq!(
+            Snapshot::read(buffer.as_slice(), &ChainSpec::MAINNET).unwrap(),
+            snapshot
+        );
+    }
+
+    #[test]
+    fn other_network() {
+        let mut buffer = Vec::new();
+        snapshot()
+            .write(Format::Binary, &ChainSpec::MAINNET, &mut buffer)
+            .unwrap();
+        assert!(matches!(
+            Snapshot::read(buffer.as_slice(), &ChainSpec::DEVNET),
+            Err(Error::ChainMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn tampered_accounts() {
+        let mut snapshot = snapshot();
+        for account in snapshot.accounts.values_mut() {
+            account.balance += 1;
+        }
+        let mut buffer = Vec::new();
+        snapshot
+            .write(Format::Binary, &ChainSpec::MAINNET, &mut buffer)
+            .unwrap();
+        assert!(matches!(
+            Snapshot::read(buffer.as_slice(), &ChainSpec::MAINNET),
+            Err(Error::StateRootMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,248 @@
+use keta_core::account::Account;
+use keta_core::account::Address;
+use keta_core::block::HashedBlock;
+use keta_core::block::HashedHeader;
+use keta_core::transaction::SignedTransaction;
+use keta_crypto::Hash;
+use keta_node_db::AccountsTree;
+use keta_node_db::Tree;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum TransactionError {
+    #[error("invalid signature")]
+    InvalidSignature,
+
+    #[error("invalid nonce: {received}, expected: {expected}")]
+    InvalidNonce { expected: u64, received: u64 },
+
+    #[error("insufficient balance: {balance}, required: {required}")]
+    InsufficientBalance { balance: u64, required: u64 },
+
+    #[error("balance overflow")]
+    BalanceOverflow,
+
+    #[error("expired at height {valid_until}, included at: {height}")]
+    Expired { valid_until: u64, height: u64 },
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum BlockError {
+    #[error("genesis block does not match, expected: {expected}")]
+    InvalidGenesis { expected: Hash },
+
+    #[error("invalid index: {received}, expected: {expected}")]
+    InvalidIndex { expected: u64, received: u64 },
+
+    #[error("invalid previous hash: {received}, expected: {expected}")]
+    InvalidPrevHash { expected: Hash, received: Hash },
+
+    #[error("hash does not match block contents")]
+    InvalidHash,
+
+    #[error("hash does not meet the target difficulty")]
+    InsufficientWork,
+
+    #[error("transaction {index}: {error}")]
+    Transaction {
+        index: usize,
+        error: TransactionError,
+    },
+}
+
+impl BlockError {
+    /// Whether the block is bogus on any chain, as opposed to not fitting on ours, e.g. because
+    /// it belongs to a competing fork. Only the former is the fault of the peer that sent it.
+    pub fn is_proof_failure(&self) -> bool {
+        matches!(
+            self,
+            Self::InvalidHash
+                | Self::InsufficientWork
+                | Self::Transaction {
+                    error: TransactionError::InvalidSignature,
+                    ..
+                }
+        )
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("database: {0}")]
+    Database(#[from] keta_node_db::Error),
+
+    #[error("invalid block: {0}")]
+    Block(#[from] BlockError),
+
+    #[error("invalid transaction: {0}")]
+    Transaction(#[from] TransactionError),
+}
+
+pub fn validate_genesis(block: &HashedBlock, genesis: &HashedBlock) -> Result<(), BlockError> {
+    if block != genesis {
+        return Err(BlockError::InvalidGenesis {
+            expected: genesis.hash.clone(),
+        });
+    }
+    Ok(())
+}
+
+/// Checks that `header` extends `prev` and carries valid proof of work.
+pub fn validate_header(prev: &HashedHeader, header: &HashedHeader) -> Result<(), BlockError> {
+    let expected_index = prev.index.increment();
+    if header.index != expected_index {
+        return Err(BlockError::InvalidIndex {
+            expected: expected_index.to_u64(),
+            received: header.index.to_u64(),
+        });
+    }
+    if header.prev_hash != prev.hash {
+        return Err(BlockError::InvalidPrevHash {
+            expected: prev.hash.clone(),
+            received: header.prev_hash.clone(),
+        });
+    }
+    validate_proof_of_work(header)
+}
+
+/// Checks that `header` hashes to its claimed hash and that the hash meets the target, without
+/// knowing the block before it.
+pub fn validate_proof_of_work(header: &HashedHeader) -> Result<(), BlockError> {
+    if header.header.hash_with_nonce(header.nonce) != header.hash {
+        return Err(BlockError::InvalidHash);
+    }
+    if !keta_miner::meets_target(&header.hash) {
+        return Err(BlockError::InsufficientWork);
+    }
+    Ok(())
+}
+
+/// Checks everything about `block` that does not depend on account state.
+pub fn validate_block(prev: &HashedBlock, block: &HashedBlock) -> Result<(), BlockError> {
+    validate_header(&prev.header(), &block.header())
+}
+
+pub fn check_expiry(transaction: &SignedTransaction, height: u64) -> Result<(), TransactionError> {
+    match transaction.valid_until {
+        Some(valid_until) if transaction.is_expired_at(height) => Err(TransactionError::Expired {
+            valid_until,
+            height,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Account state on top of the stored accounts, collecting changes in memory until they are
+/// committed.
+#[derive(Debug)]
+pub struct State<'a> {
+    accounts: Option<&'a AccountsTree>,
+    changes: BTreeMap<Address, Account>,
+}
+
+impl<'a> State<'a> {
+    pub fn new(accounts: &'a AccountsTree) -> Self {
+        Self {
+            accounts: Some(accounts),
+            changes: BTreeMap::new(),
+        }
+    }
+
+    /// State consisting only of `accounts`, not backed by the database.
+    pub fn with_accounts(accounts: BTreeMap<Address, Account>) -> Self {
+        Self {
+            accounts: None,
+            changes: accounts,
+        }
+    }
+
+    pub fn account(&self, address: &Address) -> Result<Account, Error> {
+        if let Some(account) = self.changes.get(address) {
+            return Ok(account.clone());
+        }
+        let account = match self.accounts {
+            Some(accounts) => accounts.get(address)?,
+            None => None,
+        };
+        Ok(account.unwrap_or_default())
+    }
+
+    /// Applies `transaction` as part of the block at `height`.
+    pub fn apply_transaction(
+        &mut self,
+        transaction: &SignedTransaction,
+        height: u64,
+    ) -> Result<(), Error> {
+        transaction
+            .verify(&transaction.from)
+            .map_err(|_| TransactionError::InvalidSignature)?;
+        self.apply_unverified(transaction, height)
+    }
+
+    /// Applies `transaction` like `apply_transaction`, but trusts its signature.
+    pub fn apply_unverified(
+        &mut self,
+        transaction: &SignedTransaction,
+        height: u64,
+    ) -> Result<(), Error> {
+        check_expiry(transaction, height)?;
+
+        let mut from = self.account(&transaction.from)?;
+        if transaction.nonce != from.nonce {
+            return Err(TransactionError::InvalidNonce {
+                expected: from.nonce,
+                received: transaction.nonce,
+            }
+            .into());
+        }
+        let cost = transaction
+            .cost()
+            .ok_or(TransactionError::BalanceOverflow)?;
+        from.balance =
+            from.balance
+                .checked_sub(cost)
+                .ok_or(TransactionError::InsufficientBalance {
+                    balance: from.balance,
+                    required: cost,
+                })?;
+        from.nonce += 1;
+        if transaction.from == transaction.to {
+            from.balance += transaction.value;
+            self.changes.insert(transaction.from.clone(), from);
+            return Ok(());
+        }
+
+        let mut to = self.account(&transaction.to)?;
+        to.balance = to
+            .balance
+            .checked_add(transaction.value)
+            .ok_or(TransactionError::BalanceOverflow)?;
+
+        // Fees are burned, coins are only ever created by the genesis allocations.
+        self.changes.insert(transaction.from.clone(), from);
+        self.changes.insert(transaction.to.clone(), to);
+        Ok(())
+    }
+
+    /// Applies every transaction of an already validated block. On error the state may be
+    /// partially modified and should be discarded.
+    pub fn apply_block(&mut self, block: &HashedBlock) -> Result<(), Error> {
+        let height = block.index.to_u64();
+        for (index, transaction) in block.transactions.iter().enumerate() {
+            self.apply_transaction(transaction, height)
+                .map_err(|error| match error {
+                    Error::Transaction(error) => BlockError::Transaction { index, error }.into(),
+                    error => error,
+                })?;
+        }
+        Ok(())
+    }
+
+    pub fn changes(&self) -> &BTreeMap<Address, Account> {
+        &self.changes
+    }
+
+    pub fn into_changes(self) -> BTreeMap<Address, Account> {
+        self.changes
+    }
+}
@@ -0,0 +1,483 @@
+//! Headers-first block download. Headers are fetched from the best peer, starting where its chain
+//! parts from ours, and checked for proof of work before any body is requested. Bodies are then
+//! downloaded in batches from every peer that has them, and applied in order through the usual
+//! block validation. If the peer is on a fork, we switch over once its chain is the longer one.
+
+use crate::chain::ReplayError;
+use crate::state;
+use crate::world;
+use crate::world::World;
+use keta_core::block::HashedBlock;
+use keta_core::block::HashedHeader;
+use keta_crypto::Hash;
+use keta_network::Message;
+use keta_network::Misbehaviour;
+use keta_network::Network;
+use keta_network::PeerId;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::time::Duration;
+use std::time::Instant;
+use tokio::sync::watch;
+
+/// Most headers sent in reply to a single `GetHeaders`.
+pub const MAX_HEADERS_PER_MESSAGE: u32 = 512;
+
+/// Most locator entries looked at in a single `GetHeaders`.
+pub const MAX_LOCATOR_LENGTH: usize = 64;
+
+/// Locator entries one block apart, below them the step doubles with each entry.
+const LOCATOR_DENSE_LENGTH: usize = 10;
+
+/// Blocks asked for in a single `GetBlocks`.
+const BODY_BATCH_SIZE: u64 = 16;
+
+/// Body requests outstanding per peer.
+const MAX_REQUESTS_PER_PEER: usize = 4;
+
+/// Headers kept ahead of the applied blocks, fetching more waits until bodies catch up.
+const MAX_PENDING_HEADERS: usize = 8192;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Status {
+    pub syncing: bool,
+    pub current_height: u64,
+    pub target_height: u64,
+    pub blocks_per_second: f64,
+}
+
+#[derive(Debug, Clone)]
+struct Request {
+    peer: PeerId,
+    from: u64,
+    count: u64,
+    sent_at: Instant,
+}
+
+#[derive(Debug)]
+pub struct Sync {
+    /// Best height each connected peer is known to have.
+    heights: HashMap<PeerId, u64>,
+    /// Validated headers of the best chain we know of that are not on ours, by height. Always
+    /// contiguous and following a block of our chain, which is below the tip if they are on a
+    /// fork.
+    headers: BTreeMap<u64, HashedHeader>,
+    /// Downloaded bodies waiting for the blocks before them, with the peer they came from.
+    bodies: BTreeMap<u64, (PeerId, HashedBlock)>,
+    /// Outstanding body requests, by first height.
+    requests: BTreeMap<u64, Request>,
+    headers_request: Option<Request>,
+    status: watch::Sender<Status>,
+    /// Time and height the current block rate is measured from.
+    window: (Instant, u64),
+    blocks_per_second: f64,
+}
+
+impl Sync {
+    pub fn new(status: watch::Sender<Status>) -> Self {
+        Self {
+            heights: HashMap::new(),
+            headers: BTreeMap::new(),
+            bodies: BTreeMap::new(),
+            requests: BTreeMap::new(),
+            headers_request: None,
+            status,
+            window: (Instant::now(), 0),
+            blocks_per_second: 0.0,
+        }
+    }
+
+    pub fn peer_height(&mut self, peer: PeerId, height: u64) {
+        let known = self.heights.entry(peer).or_default();
+        *known = height.max(*known);
+    }
+
+    pub fn peer_disconnected(&mut self, peer: PeerId) {
+        self.heights.remove(&peer);
+        self.requests.retain(|_, request| request.peer != peer);
+        if matches!(&self.headers_request, Some(request) if request.peer == peer) {
+            self.headers_request = None;
+        }
+    }
+
+    pub fn headers_received(
+        &mut self,
+        world: &World,
+        network: &Network,
+        peer: PeerId,
+        headers: Vec<HashedHeader>,
+    ) -> Result<(), world::Error> {
+        match &self.headers_request {
+            Some(request) if request.peer == peer => self.headers_request = None,
+            _ => return Ok(()),
+        }
+        let first = match headers.first() {
+            Some(first) => first,
+            None => {
+                // The peer has nothing past what we know, its announced height was stale.
+                let height = match self.headers.keys().next_back() {
+                    Some(height) => *height,
+                    None => world.height()?,
+                };
+                self.heights.insert(peer, height);
+                return Ok(());
+            }
+        };
+
+        // The headers follow the last block the peer has in common with the locator we sent,
+        // either a pending header or a block of our chain.
+        let height = match first.index.to_u64().checked_sub(1) {
+            Some(height) => height,
+            None => return Ok(()),
+        };
+        let mut prev = match self.headers.get(&height) {
+            Some(header) if header.hash == first.prev_hash => header.clone(),
+            _ => match world.get_headers(height, 1)?.into_iter().next() {
+                Some(header)
+                    if header.index.to_u64() == height && header.hash == first.prev_hash =>
+                {
+                    // Pending headers, if any, are on another chain.
+                    self.headers.clear();
+                    self.bodies.clear();
+                    self.requests.clear();
+                    header
+                }
+                _ => {
+                    tracing::debug!("Headers from peer {} follow no block we know", peer);
+                    return Ok(());
+                }
+            },
+        };
+        // Whatever we had past the point where the peer's chain parts from it is superseded.
+        self.headers.split_off(&(height + 1));
+        self.bodies.split_off(&(height + 1));
+        self.requests.split_off(&(height + 1));
+
+        for header in headers {
+            if let Err(err) = state::validate_header(&prev, &header) {
+                if err.is_proof_failure() {
+                    tracing::warn!("Invalid header {} from peer {}: {}", header.hash, peer, err);
+                    network.report(peer, Misbehaviour::InvalidBlock);
+                } else {
+                    tracing::debug!("Header {} from peer {}: {}", header.hash, peer, err);
+                }
+                return Ok(());
+            }
+            self.headers.insert(header.index.to_u64(), header.clone());
+            prev = header;
+        }
+        Ok(())
+    }
+
+    /// Takes the downloaded bodies, returning the blocks that were not part of the download.
+    pub fn blocks_received(
+        &mut self,
+        network: &Network,
+        peer: PeerId,
+        blocks: Vec<HashedBlock>,
+    ) -> Vec<HashedBlock> {
+        let mut unrequested = Vec::new();
+        if let Some(first) = blocks.first() {
+            let from = first.index.to_u64();
+            if matches!(self.requests.get(&from), Some(request) if request.peer == peer) {
+                self.requests.remove(&from);
+            }
+        }
+        for block in blocks {
+            let height = block.index.to_u64();
+            match self.headers.get(&height) {
+                Some(header) if *header == block.header() => {
+                    self.bodies.insert(height, (peer, block));
+                }
+                // Claims to be the block we want, but the contents do not match.
+                Some(header) if header.hash == block.hash => {
+                    tracing::warn!("Invalid body for block {} from peer {}", block.hash, peer);
+                    network.report(peer, Misbehaviour::InvalidBlock);
+                }
+                // Blocks asked for by someone else, e.g. missing parents of an orphan.
+                _ => unrequested.push(block),
+            }
+        }
+        unrequested
+    }
+
+    pub fn is_syncing(&self) -> bool {
+        self.status.borrow().syncing
+    }
+
+    /// Imports downloaded bodies that directly extend the tip, or switches to the fork they are on
+    /// once enough of them arrived for it to be longer than our chain.
+    pub fn apply(&mut self, world: &World, network: &Network) -> Result<(), world::Error> {
+        let mut height = world.height()?;
+        // Blocks that got in some other way, e.g. mined locally.
+        while let Some((&first, header)) = self.headers.iter().next() {
+            match world.get_headers(first, 1)?.first() {
+                Some(ours) if first <= height && ours == header => {
+                    self.headers.remove(&first);
+                    self.bodies.remove(&first);
+                }
+                _ => break,
+            }
+        }
+        match self.headers.keys().next() {
+            Some(first) if *first <= height => return self.reorganize(world, network, *first - 1),
+            _ => {}
+        }
+
+        while let Some((peer, block)) = self.bodies.remove(&(height + 1)) {
+            if let Err(err) = world.import_block(&block) {
+                tracing::warn!("Failed to import block {}: {}", block.hash, err);
+                match err {
+                    world::Error::State(state::Error::Block(err)) => {
+                        if err.is_proof_failure() {
+                            network.report(peer, Misbehaviour::InvalidBlock);
+                        }
+                    }
+                    world::Error::State(state::Error::Transaction(_)) => {}
+                    err => return Err(err),
+                }
+                // Everything above was validated against this block, start over.
+                self.clear();
+                break;
+            }
+            height += 1;
+            self.headers.remove(&height);
+        }
+        Ok(())
+    }
+
+    /// Switches to the fork following our block at height `ancestor`, using the bodies
+    /// downloaded so far, if that makes it longer than our chain.
+    fn reorganize(
+        &mut self,
+        world: &World,
+        network: &Network,
+        ancestor: u64,
+    ) -> Result<(), world::Error> {
+        let mut blocks = Vec::new();
+        while let Some((_, block)) = self.bodies.get(&(ancestor + 1 + blocks.len() as u64)) {
+            blocks.push(block.clone());
+        }
+        let last = ancestor + blocks.len() as u64;
+        if last <= world.height()? {
+            if blocks.len() == self.headers.len() {
+                tracing::debug!(
+                    "Fork at height {} is not longer than our chain",
+                    ancestor + 1
+                );
+                self.clear();
+            }
+            return Ok(());
+        }
+
+        match world.reorganize(ancestor, &blocks) {
+            Ok(true) => {
+                self.headers = self.headers.split_off(&(last + 1));
+                self.bodies = self.bodies.split_off(&(last + 1));
+            }
+            // Our chain grew in the meantime.
+            Ok(false) => {}
+            Err(world::Error::Replay(ReplayError::Block { index, hash, error })) => {
+                tracing::warn!("Failed to switch to fork with block {}: {}", hash, error);
+                match (error, self.bodies.get(&index.to_u64())) {
+                    (state::Error::Block(err), Some((peer, _))) if err.is_proof_failure() => {
+                        network.report(*peer, Misbehaviour::InvalidBlock);
+                    }
+                    (state::Error::Database(err), _) => return Err(err.into()),
+                    _ => {}
+                }
+                self.clear();
+            }
+            // The fork parts from our chain before the snapshot we were bootstrapped from.
+            Err(world::Error::Replay(err @ ReplayError::HeightNotAvailable { .. })) => {
+                tracing::warn!("Cannot switch to fork at height {}: {}", ancestor + 1, err);
+                self.clear();
+            }
+            Err(err) => return Err(err),
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        self.headers.clear();
+        self.bodies.clear();
+        self.requests.clear();
+    }
+
+    /// Sends whatever requests are due: the next headers from the best peer and bodies for
+    /// validated headers, spread over every peer that has them.
+    pub fn schedule(&mut self, world: &World, network: &Network) -> Result<(), world::Error> {
+        let now = Instant::now();
+        let timed_out = |request: &Request| now.duration_since(request.sent_at) >= REQUEST_TIMEOUT;
+        self.requests.retain(|_, request| !timed_out(request));
+        if matches!(&self.headers_request, Some(request) if timed_out(request)) {
+            self.headers_request = None;
+        }
+
+        let header_height = match self.headers.keys().next_back() {
+            Some(height) => *height,
+            None => world.height()?,
+        };
+        let best = self.heights.iter().max_by_key(|(_, height)| **height);
+        if let Some((&peer, &best)) = best {
+            if self.headers_request.is_none()
+                && best > header_height
+                && self.headers.len() < MAX_PENDING_HEADERS
+            {
+                let count = (best - header_height).min(MAX_HEADERS_PER_MESSAGE as u64);
+                let request = Request {
+                    peer,
+                    from: header_height + 1,
+                    count,
+                    sent_at: now,
+                };
+                let message = Message::GetHeaders {
+                    locator: self.locator(world, header_height)?,
+                    count: count as u32,
+                };
+                if network.send(peer, message).is_ok() {
+                    self.headers_request = Some(request);
+                }
+            }
+        }
+
+        let mut outstanding: HashMap<PeerId, usize> = HashMap::new();
+        for request in self.requests.values() {
+            *outstanding.entry(request.peer).or_default() += 1;
+        }
+        let requested = |height: u64, requests: &BTreeMap<u64, Request>| {
+            matches!(
+                requests.range(..=height).next_back(),
+                Some((_, request)) if height < request.from + request.count
+            )
+        };
+        let missing: Vec<u64> = self
+            .headers
+            .keys()
+            .copied()
+            .filter(|height| !self.bodies.contains_key(height))
+            .filter(|height| !requested(*height, &self.requests))
+            .collect();
+        let mut missing = missing.into_iter().peekable();
+        while let Some(from) = missing.next() {
+            let mut count = 1;
+            while count < BODY_BATCH_SIZE && missing.peek() == Some(&(from + count)) {
+                missing.next();
+                count += 1;
+            }
+            let last = from + count - 1;
+            let peer = self
+                .heights
+                .iter()
+                .filter(|(_, height)| **height >= last)
+                .map(|(peer, _)| (*peer, outstanding.get(peer).copied().unwrap_or(0)))
+                .filter(|(_, outstanding)| *outstanding < MAX_REQUESTS_PER_PEER)
+                .min_by_key(|(peer, outstanding)| (*outstanding, *peer))
+                .map(|(peer, _)| peer);
+            let peer = match peer {
+                Some(peer) => peer,
+                None => break,
+            };
+            let message = Message::GetBlocks {
+                from,
+                count: count as u32,
+            };
+            if network.send(peer, message).is_ok() {
+                *outstanding.entry(peer).or_default() += 1;
+                let request = Request {
+                    peer,
+                    from,
+                    count,
+                    sent_at: now,
+                };
+                self.requests.insert(from, request);
+            }
+        }
+        Ok(())
+    }
+
+    /// Heights and hashes of the chain we are downloading, from the last pending header at
+    /// `height` down to genesis. The first few are consecutive, then the step doubles each time.
+    fn locator(&self, world: &World, mut height: u64) -> Result<Vec<(u64, Hash)>, world::Error> {
+        let mut locator = Vec::new();
+        let mut step = 1;
+        loop {
+            let hash = match self.headers.get(&height) {
+                Some(header) => Some(header.hash.clone()),
+                None => world
+                    .get_headers(height, 1)?
+                    .into_iter()
+                    .find(|header| header.index.to_u64() == height)
+                    .map(|header| header.hash),
+            };
+            // Missing below the snapshot a database was bootstrapped from.
+            if let Some(hash) = hash {
+                locator.push((height, hash));
+            }
+            if height == 0 || locator.len() == MAX_LOCATOR_LENGTH {
+                return Ok(locator);
+            }
+            if locator.len() >= LOCATOR_DENSE_LENGTH {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+    }
+
+    /// Publishes the current progress, logging it periodically while catching up.
+    pub fn update_status(&mut self, world: &World) -> Result<(), world::Error> {
+        let current_height = world.height()?;
+        let target_height = self
+            .heights
+            .values()
+            .copied()
+            .chain(self.headers.keys().next_back().copied())
+            .max()
+            .unwrap_or(0)
+            .max(current_height);
+        let syncing = target_height > current_height;
+        let was_syncing = self.status.borrow().syncing;
+
+        let now = Instant::now();
+        let (since, from) = self.window;
+        if !was_syncing && syncing {
+            self.window = (now, current_height);
+            // Following along block by block is not worth a message.
+            if target_height > current_height + 1 {
+                tracing::info!(
+                    "Syncing from height {} to {}",
+                    current_height,
+                    target_height
+                );
+            }
+        } else if was_syncing && !syncing {
+            self.blocks_per_second = 0.0;
+            tracing::info!("Synced to height {}", current_height);
+        } else if syncing && now.duration_since(since) >= PROGRESS_INTERVAL {
+            let elapsed = now.duration_since(since).as_secs_f64();
+            self.blocks_per_second = current_height.saturating_sub(from) as f64 / elapsed;
+            self.window = (now, current_height);
+            tracing::info!(
+                "Syncing: {}/{} ({:.1} blocks/s)",
+                current_height,
+                target_height,
+                self.blocks_per_second
+            );
+        }
+
+        let status = Status {
+            syncing,
+            current_height,
+            target_height,
+            blocks_per_second: self.blocks_per_second,
+        };
+        if *self.status.borrow() != status {
+            let _ = self.status.send(status);
+        }
+        Ok(())
+    }
+}
@@ -1,51 +1,409 @@
+use crate::chain;
+use crate::mempool;
+use crate::mempool::Mempool;
+use crate::state;
+use crate::state::State;
+use keta_core::account::Account;
 use keta_core::account::Address;
 use keta_core::block::Block;
 use keta_core::block::HashedBlock;
+use keta_core::block::HashedHeader;
+use keta_core::block::Index;
+use keta_core::chain_spec::ChainSpec;
 use keta_core::transaction::SignedTransaction;
+use keta_crypto::Hash;
 use keta_miner::mine_block;
 use keta_miner::MineResult;
 use keta_node_db::Database;
+use keta_node_db::MempoolEntry;
 use keta_node_db::Tree;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::collections::VecDeque;
 use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+/// How long a transaction may wait in the mempool before it is dropped on restart.
+const MEMPOOL_EXPIRY_HOURS: i64 = 72;
+
+/// Events buffered per subscriber before the slowest ones start missing them.
+const EVENT_CAPACITY: usize = 1024;
+
+/// Dropped transactions whose reason is remembered, the oldest are forgotten first.
+const DROPPED_CAPACITY: usize = 10_000;
+
+/// Changes to the chain and the mempool, published to every subscriber.
+#[derive(Debug, Clone)]
+pub enum Event {
+    NewBlock(HashedBlock),
+    TransactionAdded(Hash),
+    TransactionReplaced { replaced: Hash, by: Hash },
+    TransactionDropped(mempool::Dropped),
+}
+
+/// Outcome of a transaction that would be accepted, see `World::simulate_transaction`.
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    pub admitted: mempool::Admitted,
+    /// Sender and recipient as they would be once the transaction is included.
+    pub accounts: BTreeMap<Address, Account>,
+}
+
+/// Why a pending transaction left the mempool without being included.
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum DropReason {
+    #[error("{0}")]
+    Rejected(mempool::AdmissionError),
+
+    #[error("replaced by {0}")]
+    Replaced(Hash),
+}
+
+/// Where a transaction is, as far as this node knows.
+#[derive(Debug, Clone)]
+pub enum TransactionStatus {
+    Unknown,
+    Pending,
+    Included { height: u64, confirmations: u64 },
+    Dropped(DropReason),
+}
+
+/// Reasons of the most recently dropped transactions, since the node started.
+#[derive(Debug, Default)]
+struct Dropped {
+    reasons: HashMap<Hash, DropReason>,
+    order: VecDeque<Hash>,
+}
+
+impl Dropped {
+    fn insert(&mut self, hash: Hash, reason: DropReason) {
+        if self.reasons.insert(hash.clone(), reason).is_none() {
+            self.order.push_back(hash);
+        }
+        while self.order.len() > DROPPED_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.reasons.remove(&oldest);
+            }
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct World {
+    chain_spec: ChainSpec,
     database: Database,
-    pending_transactions: Mutex<VecDeque<SignedTransaction>>,
+    mempool: Mutex<Mempool>,
+    dropped: Mutex<Dropped>,
+    events: broadcast::Sender<Event>,
+    /// Held while extending the chain, so blocks are committed one at a time.
+    chain: Mutex<()>,
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("database: {0}")]
     Database(#[from] keta_node_db::Error),
+
+    #[error("state: {0}")]
+    State(#[from] state::Error),
+
+    #[error("mempool: {0}")]
+    Mempool(#[from] mempool::Error),
+
+    #[error("replay: {0}")]
+    Replay(#[from] chain::ReplayError),
 }
 
 impl World {
-    pub fn new(database: Database) -> Result<Self, Error> {
+    pub fn new(
+        database: Database,
+        chain_spec: ChainSpec,
+        mempool_config: mempool::Config,
+    ) -> Result<Self, Error> {
+        chain::initialize(&database, &chain_spec)?;
+        let mempool = Self::load_mempool(&database, mempool_config)?;
+        let (events, _) = broadcast::channel(EVENT_CAPACITY);
         Ok(Self {
-            pending_transactions: Default::default(),
+            chain_spec,
+            mempool: Mutex::new(mempool),
+            dropped: Mutex::default(),
             database,
+            events,
+            chain: Mutex::new(()),
         })
     }
 
+    pub fn chain_spec(&self) -> &ChainSpec {
+        &self.chain_spec
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    fn publish(&self, event: Event) {
+        // Nobody listening is not an error.
+        let _ = self.events.send(event);
+    }
+
+    /// Reloads persisted pending transactions, dropping the ones that expired or are no longer
+    /// admissible on top of the current state.
+    fn load_mempool(database: &Database, config: mempool::Config) -> Result<Mempool, Error> {
+        let mut entries = database.mempool.iter().collect::<Result<Vec<_>, _>>()?;
+        // Each sender in nonce order, as replacements and transactions put back by a
+        // reorganization are newer than the ones queued behind them.
+        entries.sort_by(|a, b| {
+            (&a.transaction.from, a.transaction.nonce, a.received_at).cmp(&(
+                &b.transaction.from,
+                b.transaction.nonce,
+                b.received_at,
+            ))
+        });
+
+        let expired_before = chrono::Utc::now() - chrono::Duration::hours(MEMPOOL_EXPIRY_HOURS);
+        let state = State::new(&database.accounts);
+        let height = Self::next_height(database)?;
+        let mut mempool = Mempool::new(config);
+        for MempoolEntry {
+            transaction,
+            received_at,
+        } in entries
+        {
+            let hash = transaction.hash();
+            if received_at < expired_before {
+                tracing::info!("Dropping expired pending transaction {}", hash);
+                database.mempool.remove(&hash)?;
+                continue;
+            }
+            match mempool.insert(transaction, received_at, height, &state) {
+                Ok(admitted) => {
+                    Self::remove_persisted(database, admitted.replaced.iter())?;
+                    Self::remove_persisted(database, &admitted.evicted)?;
+                }
+                Err(mempool::Error::Rejected(err)) => {
+                    tracing::info!("Dropping invalid pending transaction {}: {}", hash, err);
+                    database.mempool.remove(&hash)?;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        if !mempool.is_empty() {
+            tracing::info!("Restored {} pending transactions", mempool.len());
+        }
+        Ok(mempool)
+    }
+
+    fn remove_persisted<'a>(
+        database: &Database,
+        hashes: impl IntoIterator<Item = &'a Hash>,
+    ) -> Result<(), Error> {
+        for hash in hashes {
+            database.mempool.remove(hash)?;
+        }
+        Ok(())
+    }
+
+    /// Height of the block that will be built on top of the current tip.
+    fn next_height(database: &Database) -> Result<u64, Error> {
+        Ok(chain::tip(database)?.index.to_u64() + 1)
+    }
+
+    pub fn tip(&self) -> Result<HashedBlock, Error> {
+        Ok(chain::tip(&self.database)?)
+    }
+
+    pub fn height(&self) -> Result<u64, Error> {
+        Ok(self.tip()?.index.to_u64())
+    }
+
     pub fn generate_block(&self) -> Result<HashedBlock, Error> {
-        let transactions = self
-            .pending_transactions
-            .lock()
-            .unwrap()
-            .drain(..)
-            .collect();
-        let block = Block::generate(
-            &self.database.blocks.iter().next_back().unwrap()?,
-            transactions,
-        );
+        let _chain = self.chain.lock().unwrap();
+        let pending_transactions = self.mempool.lock().unwrap().select();
+        let tip = chain::tip(&self.database)?;
+        let height = tip.index.to_u64() + 1;
+        let mut state = State::new(&self.database.accounts);
+        let mut transactions = Vec::with_capacity(pending_transactions.len());
+        for transaction in pending_transactions {
+            match state.apply_transaction(&transaction, height) {
+                Ok(()) => transactions.push(transaction),
+                Err(state::Error::Transaction(err)) => {
+                    tracing::warn!("Skipping transaction {}: {}", transaction.hash(), err)
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        let block = Block::generate(&tip, transactions);
         let MineResult { hash, nonce } = mine_block(&block);
         let block = HashedBlock { block, hash, nonce };
-        self.database.blocks.insert(&block.index, &block)?;
+        self.database.commit_block(&block, state.changes())?;
+        self.block_committed(&block)?;
         Ok(block)
     }
 
+    /// Validates `block` received from elsewhere and extends the chain with it.
+    pub fn import_block(&self, block: &HashedBlock) -> Result<(), Error> {
+        let _chain = self.chain.lock().unwrap();
+        chain::import_block(&self.database, block)?;
+        self.block_committed(block)
+    }
+
+    /// Switches to the fork made of `blocks`, which follow our block at height `ancestor`.
+    /// Every block carries the same work, so the fork is only taken if it is longer than the
+    /// current chain. Transactions of the abandoned blocks that the fork did not include go back
+    /// to the mempool. Returns whether the chain changed.
+    pub fn reorganize(&self, ancestor: u64, blocks: &[HashedBlock]) -> Result<bool, Error> {
+        let _chain = self.chain.lock().unwrap();
+        let height = chain::tip(&self.database)?.index.to_u64();
+        if ancestor + blocks.len() as u64 <= height {
+            return Ok(false);
+        }
+        let abandoned = chain::reorganize(&self.database, &self.chain_spec, ancestor, blocks)?;
+        tracing::info!(
+            "Reorganized from height {} to {}, {} blocks replaced",
+            height,
+            ancestor + blocks.len() as u64,
+            abandoned.len()
+        );
+
+        {
+            let received_at = chrono::Utc::now();
+            let state = State::new(&self.database.accounts);
+            let height = Self::next_height(&self.database)?;
+            let mut mempool = self.mempool.lock().unwrap();
+            for transaction in abandoned.iter().flat_map(|block| &block.transactions) {
+                let hash = transaction.hash();
+                if self.database.transactions.get(&hash)?.is_some() {
+                    continue;
+                }
+                match mempool.insert(transaction.clone(), received_at, height, &state) {
+                    Ok(admitted) => {
+                        Self::remove_persisted(&self.database, admitted.replaced.iter())?;
+                        Self::remove_persisted(&self.database, &admitted.evicted)?;
+                        let entry = MempoolEntry {
+                            transaction: transaction.clone(),
+                            received_at,
+                        };
+                        self.database.mempool.insert(&hash, &entry)?;
+                    }
+                    Err(mempool::Error::Rejected(err)) => {
+                        tracing::debug!("Not restoring transaction {}: {}", hash, err);
+                    }
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        }
+        for block in blocks {
+            self.block_committed(block)?;
+        }
+        Ok(true)
+    }
+
+    fn block_committed(&self, block: &HashedBlock) -> Result<(), Error> {
+        let dropped = self.mempool.lock().unwrap().revalidate(
+            &State::new(&self.database.accounts),
+            block.index.to_u64() + 1,
+        )?;
+        self.publish(Event::NewBlock(block.clone()));
+        let included: HashSet<Hash> = block
+            .transactions
+            .iter()
+            .map(|transaction| transaction.hash())
+            .collect();
+        for dropped in dropped {
+            self.database.mempool.remove(&dropped.hash)?;
+            // Included transactions leave the mempool too, but they were not dropped.
+            if !included.contains(&dropped.hash) {
+                self.dropped.lock().unwrap().insert(
+                    dropped.hash.clone(),
+                    DropReason::Rejected(dropped.reason.clone()),
+                );
+                self.publish(Event::TransactionDropped(dropped));
+            }
+        }
+        Ok(())
+    }
+
+    /// Up to `count` consecutive blocks starting at height `from`.
+    pub fn get_blocks(&self, from: u64, count: usize) -> Result<Vec<HashedBlock>, Error> {
+        let blocks = self
+            .database
+            .blocks
+            .iter_from(&Index::from(from))
+            .take(count)
+            .collect::<Result<_, _>>()?;
+        Ok(blocks)
+    }
+
+    /// Height of the first block in `locator` that is on our chain, if any. See
+    /// `Message::GetHeaders`.
+    pub fn locate(&self, locator: &[(u64, Hash)]) -> Result<Option<u64>, Error> {
+        for (height, hash) in locator {
+            match self.get_blocks(*height, 1)?.first() {
+                Some(block) if block.index.to_u64() == *height && block.hash == *hash => {
+                    return Ok(Some(*height));
+                }
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Headers of up to `count` consecutive blocks starting at height `from`.
+    pub fn get_headers(&self, from: u64, count: usize) -> Result<Vec<HashedHeader>, Error> {
+        let headers = self
+            .get_blocks(from, count)?
+            .iter()
+            .map(HashedBlock::header)
+            .collect();
+        Ok(headers)
+    }
+
+    /// Sum of all balances. Coins are only created by the genesis allocations and fees are burned,
+    /// so it starts at their total and only ever shrinks.
+    pub fn total_supply(&self) -> Result<u64, Error> {
+        let mut supply = 0u64;
+        for account in self.database.accounts.iter() {
+            let (_, account) = account?;
+            supply = supply.saturating_add(account.balance);
+        }
+        Ok(supply)
+    }
+
+    pub fn mempool_size(&self) -> usize {
+        self.mempool.lock().unwrap().len()
+    }
+
+    pub fn get_pending_transaction(&self, hash: &Hash) -> Option<SignedTransaction> {
+        let mempool = self.mempool.lock().unwrap();
+        mempool.get(hash).map(|entry| entry.transaction.clone())
+    }
+
+    pub fn pending_transactions(&self) -> Vec<SignedTransaction> {
+        let mempool = self.mempool.lock().unwrap();
+        mempool
+            .iter()
+            .map(|entry| entry.transaction.clone())
+            .collect()
+    }
+
+    pub fn transaction_status(&self, hash: &Hash) -> Result<TransactionStatus, Error> {
+        if let Some(index) = self.database.transactions.get(hash)? {
+            let height = index.to_u64();
+            return Ok(TransactionStatus::Included {
+                height,
+                confirmations: self.height()? - height + 1,
+            });
+        }
+        if self.mempool.lock().unwrap().get(hash).is_some() {
+            return Ok(TransactionStatus::Pending);
+        }
+        match self.dropped.lock().unwrap().reasons.get(hash) {
+            Some(reason) => Ok(TransactionStatus::Dropped(reason.clone())),
+            None => Ok(TransactionStatus::Unknown),
+        }
+    }
+
     pub fn get_balance(&self, address: &Address) -> Result<u64, Error> {
         let balance = self
             .database
@@ -56,11 +414,141 @@ impl World {
         Ok(balance)
     }
 
-    pub fn send_transaction(&self, transaction: SignedTransaction) -> Result<(), Error> {
-        self.pending_transactions
+    pub fn get_nonce(&self, address: &Address) -> Result<u64, Error> {
+        let nonce = self
+            .mempool
             .lock()
             .unwrap()
-            .push_back(transaction);
-        Ok(())
+            .next_nonce(address, &State::new(&self.database.accounts))?;
+        Ok(nonce)
+    }
+
+    /// Runs `transaction` through mempool admission and, after the pending transactions it
+    /// follows, through the state of the next block, without keeping anything. The signature is
+    /// only checked if `verify_signature` is set.
+    pub fn simulate_transaction(
+        &self,
+        transaction: &SignedTransaction,
+        verify_signature: bool,
+    ) -> Result<Simulation, Error> {
+        let height = Self::next_height(&self.database)?;
+        let mut state = State::new(&self.database.accounts);
+        let mempool = self.mempool.lock().unwrap();
+        let admitted = mempool.check(transaction, height, &state, verify_signature)?;
+
+        for pending in mempool.pending_from(&transaction.from) {
+            if pending.nonce >= transaction.nonce {
+                break;
+            }
+            state.apply_unverified(pending, height)?;
+        }
+        if verify_signature {
+            state.apply_transaction(transaction, height)?;
+        } else {
+            state.apply_unverified(transaction, height)?;
+        }
+
+        let accounts = [&transaction.from, &transaction.to]
+            .iter()
+            .map(|address| Ok(((*address).clone(), state.account(address)?)))
+            .collect::<Result<_, state::Error>>()?;
+        Ok(Simulation { admitted, accounts })
+    }
+
+    pub fn send_transaction(
+        &self,
+        transaction: SignedTransaction,
+    ) -> Result<mempool::Admitted, Error> {
+        let received_at = chrono::Utc::now();
+        let admitted = self.mempool.lock().unwrap().insert(
+            transaction.clone(),
+            received_at,
+            Self::next_height(&self.database)?,
+            &State::new(&self.database.accounts),
+        )?;
+        Self::remove_persisted(&self.database, admitted.replaced.iter())?;
+        Self::remove_persisted(&self.database, &admitted.evicted)?;
+        let entry = MempoolEntry {
+            transaction,
+            received_at,
+        };
+        self.database.mempool.insert(&admitted.hash, &entry)?;
+
+        {
+            let mut dropped = self.dropped.lock().unwrap();
+            if let Some(replaced) = &admitted.replaced {
+                dropped.insert(
+                    replaced.clone(),
+                    DropReason::Replaced(admitted.hash.clone()),
+                );
+            }
+            for evicted in &admitted.evicted {
+                dropped.insert(
+                    evicted.clone(),
+                    DropReason::Rejected(mempool::AdmissionError::Full),
+                );
+            }
+        }
+        match &admitted.replaced {
+            Some(replaced) => {
+                self.publish(Event::TransactionReplaced {
+                    replaced: replaced.clone(),
+                    by: admitted.hash.clone(),
+                });
+            }
+            None => self.publish(Event::TransactionAdded(admitted.hash.clone())),
+        }
+        for evicted in &admitted.evicted {
+            self.publish(Event::TransactionDropped(mempool::Dropped {
+                hash: evicted.clone(),
+                reason: mempool::AdmissionError::Full,
+            }));
+        }
+        Ok(admitted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::World;
+    use crate::mempool;
+    use keta_core::chain_spec::ChainSpec;
+    use keta_core::chain_spec::DEVNET_FAUCET_SECRET_KEY;
+    use keta_core::transaction::Transaction;
+    use keta_crypto::Keypair;
+    use keta_node_db::Database;
+
+    #[test]
+    fn restart_keeps_queued_transactions_behind_replacement() {
+        let database = Database::temporary().unwrap();
+        let world = World::new(
+            database.clone(),
+            ChainSpec::DEVNET,
+            mempool::Config::default(),
+        )
+        .unwrap();
+        let faucet = Keypair::from_secret(DEVNET_FAUCET_SECRET_KEY.parse().unwrap());
+        let to = Keypair::generate().public;
+        let transaction = |nonce, fee| {
+            Transaction {
+                from: faucet.public.clone(),
+                to: to.clone(),
+                value: 1,
+                fee,
+                nonce,
+                valid_until: None,
+            }
+            .sign(&faucet)
+        };
+        world.send_transaction(transaction(0, 10)).unwrap();
+        let queued = world.send_transaction(transaction(1, 10)).unwrap().hash;
+        // Received times are stored in seconds.
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        let replacement = world.send_transaction(transaction(0, 20)).unwrap().hash;
+        drop(world);
+
+        let world = World::new(database, ChainSpec::DEVNET, mempool::Config::default()).unwrap();
+        assert!(world.get_pending_transaction(&replacement).is_some());
+        assert!(world.get_pending_transaction(&queued).is_some());
     }
 }
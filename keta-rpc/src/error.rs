@@ -0,0 +1,236 @@
+use serde_json::json;
+use serde_json::Value;
+
+/// JSON-RPC error codes of the errors raised by the node. They never change meaning, clients may
+/// match on them.
+pub mod code {
+    pub const DATABASE: i32 = -32000;
+    pub const INVALID_BLOCK: i32 = -32001;
+
+    pub const INVALID_SIGNATURE: i32 = -32010;
+    pub const INVALID_NONCE: i32 = -32011;
+    pub const INSUFFICIENT_BALANCE: i32 = -32012;
+    pub const BALANCE_OVERFLOW: i32 = -32013;
+    pub const EXPIRED: i32 = -32014;
+
+    pub const ALREADY_KNOWN: i32 = -32020;
+    pub const NONCE_TOO_LOW: i32 = -32021;
+    pub const NONCE_GAP: i32 = -32022;
+    pub const REPLACEMENT_UNDERPRICED: i32 = -32023;
+    pub const MEMPOOL_FULL: i32 = -32024;
+}
+
+/// Failure of an RPC call. Errors raised by the node are sent as JSON-RPC errors with the code of
+/// the variant and its fields as data, so that clients get the same variant back.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum Error {
+    #[error("database: {0}")]
+    Database(String),
+    #[error("invalid block: {0}")]
+    InvalidBlock(String),
+
+    #[error("invalid signature")]
+    InvalidSignature,
+    #[error("invalid nonce: {received}, expected: {expected}")]
+    InvalidNonce { expected: u64, received: u64 },
+    #[error("insufficient balance: {balance}, required: {required}")]
+    InsufficientBalance { balance: u64, required: u64 },
+    #[error("balance overflow")]
+    BalanceOverflow,
+    #[error("expired at height {valid_until}, included at: {height}")]
+    Expired { valid_until: u64, height: u64 },
+
+    #[error("transaction is already pending")]
+    AlreadyKnown,
+    #[error("nonce too low: {received}, expected: {expected}")]
+    NonceTooLow { expected: u64, received: u64 },
+    #[error("nonce gap: {received}, expected: {expected}")]
+    NonceGap { expected: u64, received: u64 },
+    #[error("replacement fee too low: {fee}, required: {required}")]
+    ReplacementUnderpriced { fee: u64, required: u64 },
+    #[error("mempool is full and the fee is too low to evict anything")]
+    MempoolFull,
+
+    /// JSON-RPC error this version does not know, like invalid params or errors added to newer
+    /// nodes.
+    #[error("{message} (code {code})")]
+    Other { code: i32, message: String },
+    #[error("json-rpc: {0}")]
+    JsonRPC(String),
+    #[error("unsupported RPC URL scheme {0}, expected ws, wss, http, https or ipc")]
+    UnsupportedScheme(String),
+    #[error("ipc: {0}")]
+    Ipc(String),
+}
+
+impl Error {
+    /// JSON-RPC error code the node sends this error with.
+    pub fn code(&self) -> i32 {
+        match self {
+            Error::Database(_) => code::DATABASE,
+            Error::InvalidBlock(_) => code::INVALID_BLOCK,
+            Error::InvalidSignature => code::INVALID_SIGNATURE,
+            Error::InvalidNonce { .. } => code::INVALID_NONCE,
+            Error::InsufficientBalance { .. } => code::INSUFFICIENT_BALANCE,
+            Error::BalanceOverflow => code::BALANCE_OVERFLOW,
+            Error::Expired { .. } => code::EXPIRED,
+            Error::AlreadyKnown => code::ALREADY_KNOWN,
+            Error::NonceTooLow { .. } => code::NONCE_TOO_LOW,
+            Error::NonceGap { .. } => code::NONCE_GAP,
+            Error::ReplacementUnderpriced { .. } => code::REPLACEMENT_UNDERPRICED,
+            Error::MempoolFull => code::MEMPOOL_FULL,
+            Error::Other { code, .. } => *code,
+            // Raised by the client itself, never sent.
+            Error::JsonRPC(_) | Error::UnsupportedScheme(_) | Error::Ipc(_) => {
+                jsonrpsee::types::v2::error::CALL_EXECUTION_FAILED_CODE
+            }
+        }
+    }
+
+    /// Fields of the error, sent as the data of the JSON-RPC error.
+    pub fn data(&self) -> Option<Value> {
+        match self {
+            Error::Database(message) | Error::InvalidBlock(message) => Some(json!(message)),
+            Error::InvalidNonce { expected, received }
+            | Error::NonceTooLow { expected, received }
+            | Error::NonceGap { expected, received } => {
+                Some(json!({ "expected": expected, "received": received }))
+            }
+            Error::InsufficientBalance { balance, required } => {
+                Some(json!({ "balance": balance, "required": required }))
+            }
+            Error::Expired {
+                valid_until,
+                height,
+            } => Some(json!({ "valid_until": valid_until, "height": height })),
+            Error::ReplacementUnderpriced { fee, required } => {
+                Some(json!({ "fee": fee, "required": required }))
+            }
+            _ => None,
+        }
+    }
+
+    /// Rebuilds the error the node sent as JSON-RPC error, or `Other` if the code is unknown or
+    /// the data does not fit it.
+    pub fn from_json_rpc(code: i32, message: &str, data: Option<&Value>) -> Self {
+        Self::from_code(code, data).unwrap_or_else(|| Error::Other {
+            code,
+            message: message.to_string(),
+        })
+    }
+
+    fn from_code(code: i32, data: Option<&Value>) -> Option<Self> {
+        let text = || Some(data?.as_str()?.to_string());
+        let field = |name: &str| data?.get(name)?.as_u64();
+        let error = match code {
+            code::DATABASE => Error::Database(text()?),
+            code::INVALID_BLOCK => Error::InvalidBlock(text()?),
+            code::INVALID_SIGNATURE => Error::InvalidSignature,
+            code::INVALID_NONCE => Error::InvalidNonce {
+                expected: field("expected")?,
+                received: field("received")?,
+            },
+            code::INSUFFICIENT_BALANCE => Error::InsufficientBalance {
+                balance: field("balance")?,
+                required: field("required")?,
+            },
+            code::BALANCE_OVERFLOW => Error::BalanceOverflow,
+            code::EXPIRED => Error::Expired {
+                valid_until: field("valid_until")?,
+                height: field("height")?,
+            },
+            code::ALREADY_KNOWN => Error::AlreadyKnown,
+            code::NONCE_TOO_LOW => Error::NonceTooLow {
+                expected: field("expected")?,
+                received: field("received")?,
+            },
+            code::NONCE_GAP => Error::NonceGap {
+                expected: field("expected")?,
+                received: field("received")?,
+            },
+            code::REPLACEMENT_UNDERPRICED => Error::ReplacementUnderpriced {
+                fee: field("fee")?,
+                required: field("required")?,
+            },
+            code::MEMPOOL_FULL => Error::MempoolFull,
+            _ => return None,
+        };
+        Some(error)
+    }
+}
+
+impl From<jsonrpsee::types::Error> for Error {
+    fn from(err: jsonrpsee::types::Error) -> Self {
+        use jsonrpsee::types::v2::error::JsonRpcError;
+
+        // Clients hand over error responses in their JSON form.
+        if let jsonrpsee::types::Error::Request(response) = &err {
+            if let Ok(response) = serde_json::from_str::<JsonRpcError>(response) {
+                let data = response
+                    .error
+                    .data
+                    .and_then(|data| serde_json::from_str(data.get()).ok());
+                return Self::from_json_rpc(
+                    response.error.code.code(),
+                    response.error.message,
+                    data.as_ref(),
+                );
+            }
+        }
+        Self::JsonRPC(err.to_string())
+    }
+}
+
+#[cfg(feature = "server")]
+impl From<Error> for jsonrpsee::types::CallError {
+    fn from(err: Error) -> Self {
+        Self::Custom {
+            code: err.code(),
+            message: err.to_string(),
+            data: err
+                .data()
+                .and_then(|data| serde_json::value::to_raw_value(&data).ok()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Error;
+
+    #[test]
+    fn reconstruct_from_json_rpc() {
+        let errors = vec![
+            Error::Database("sled error".to_string()),
+            Error::InvalidSignature,
+            Error::InsufficientBalance {
+                balance: 5,
+                required: 12,
+            },
+            Error::Expired {
+                valid_until: 10,
+                height: 11,
+            },
+            Error::ReplacementUnderpriced {
+                fee: 2,
+                required: 3,
+            },
+            Error::MempoolFull,
+        ];
+        for error in errors {
+            let message = error.to_string();
+            let data = error.data();
+            assert_eq!(
+                Error::from_json_rpc(error.code(), &message, data.as_ref()),
+                error
+            );
+        }
+        assert_eq!(
+            Error::from_json_rpc(-32601, "Method not found", None),
+            Error::Other {
+                code: -32601,
+                message: "Method not found".to_string()
+            }
+        );
+    }
+}
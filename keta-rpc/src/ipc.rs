@@ -0,0 +1,292 @@
+//! JSON-RPC over a Unix domain socket, one JSON object per line, for tools on the same machine
+//! as the node. Access is controlled by the permissions of the socket file.
+
+#[cfg(feature = "server")]
+pub use server::serve;
+
+#[cfg(feature = "client")]
+pub use client::IpcClient;
+
+#[cfg(feature = "server")]
+mod server {
+    use crate::Error;
+    use futures::StreamExt;
+    use jsonrpsee::types::v2::error::JsonRpcError;
+    use jsonrpsee::types::v2::error::JsonRpcErrorCode;
+    use jsonrpsee::types::v2::params::Id;
+    use jsonrpsee::types::v2::params::TwoPointZero;
+    use jsonrpsee::types::v2::request::JsonRpcInvalidRequest;
+    use jsonrpsee::types::v2::request::JsonRpcNotification;
+    use jsonrpsee::types::v2::request::JsonRpcRequest;
+    use jsonrpsee::RpcModule;
+    use serde_json::value::RawValue;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use std::sync::Arc;
+    use tokio::io::AsyncBufReadExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio::io::BufReader;
+    use tokio::net::UnixListener;
+    use tokio::net::UnixStream;
+
+    /// Serves `module` on a socket at `path`, replacing whatever file is left there. Only the
+    /// user running the node can connect.
+    pub async fn serve<Context: Send + Sync + 'static>(
+        module: RpcModule<Context>,
+        path: &Path,
+    ) -> Result<(), Error> {
+        if path.exists() {
+            std::fs::remove_file(path).map_err(ipc_error)?;
+        }
+        let listener = UnixListener::bind(path).map_err(ipc_error)?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+            .map_err(ipc_error)?;
+
+        let module = Arc::new(module);
+        let mut connection_id = 0;
+        loop {
+            let (stream, _) = listener.accept().await.map_err(ipc_error)?;
+            tokio::spawn(serve_connection(module.clone(), stream, connection_id));
+            connection_id += 1;
+        }
+    }
+
+    async fn serve_connection<Context: Send + Sync + 'static>(
+        module: Arc<RpcModule<Context>>,
+        stream: UnixStream,
+        connection_id: usize,
+    ) {
+        let (reader, mut writer) = stream.into_split();
+        // Responses and subscription notifications, written out in the order they are sent.
+        let (sink, mut messages) = futures::channel::mpsc::unbounded::<String>();
+        tokio::spawn(async move {
+            while let Some(mut message) = messages.next().await {
+                message.push('\n');
+                if writer.write_all(message.as_bytes()).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if let Ok(request) = serde_json::from_str::<JsonRpcRequest>(&line) {
+                module.execute(&sink, request, connection_id).await;
+                continue;
+            }
+            // Never answered, whether or not the method exists.
+            if serde_json::from_str::<JsonRpcNotification<Option<&RawValue>>>(&line).is_ok() {
+                continue;
+            }
+            let error = match serde_json::from_str::<JsonRpcInvalidRequest>(&line) {
+                Ok(request) => JsonRpcError {
+                    jsonrpc: TwoPointZero,
+                    error: JsonRpcErrorCode::InvalidRequest.into(),
+                    id: request.id,
+                },
+                Err(_) => JsonRpcError {
+                    jsonrpc: TwoPointZero,
+                    error: JsonRpcErrorCode::ParseError.into(),
+                    id: Id::Null,
+                },
+            };
+            let _ = sink.unbounded_send(error.to_string());
+        }
+    }
+
+    fn ipc_error(err: std::io::Error) -> Error {
+        Error::Ipc(err.to_string())
+    }
+}
+
+#[cfg(feature = "client")]
+mod client {
+    use jsonrpsee::types::traits::Client;
+    use jsonrpsee::types::v2::error::JsonRpcError;
+    use jsonrpsee::types::v2::params::Id;
+    use jsonrpsee::types::v2::params::JsonRpcParams;
+    use jsonrpsee::types::v2::request::JsonRpcCallSer;
+    use jsonrpsee::types::v2::request::JsonRpcNotificationSer;
+    use jsonrpsee::types::v2::response::JsonRpcResponse;
+    use jsonrpsee::types::Error;
+    use serde::de::DeserializeOwned;
+    use std::path::Path;
+    use std::sync::atomic::AtomicU64;
+    use std::sync::atomic::Ordering;
+    use tokio::io::AsyncBufReadExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio::io::BufReader;
+    use tokio::io::Lines;
+    use tokio::net::unix::OwnedReadHalf;
+    use tokio::net::unix::OwnedWriteHalf;
+    use tokio::net::UnixStream;
+    use tokio::sync::Mutex;
+
+    #[derive(Debug)]
+    struct Connection {
+        reader: Lines<BufReader<OwnedReadHalf>>,
+        writer: OwnedWriteHalf,
+    }
+
+    /// Client for the socket of a node. Requests are sent one at a time, and notifications
+    /// received in between are skipped.
+    #[derive(Debug)]
+    pub struct IpcClient {
+        connection: Mutex<Connection>,
+        request_id: AtomicU64,
+    }
+
+    impl IpcClient {
+        pub async fn connect(path: &Path) -> std::io::Result<Self> {
+            let (reader, writer) = UnixStream::connect(path).await?.into_split();
+            Ok(Self {
+                connection: Mutex::new(Connection {
+                    reader: BufReader::new(reader).lines(),
+                    writer,
+                }),
+                request_id: AtomicU64::new(0),
+            })
+        }
+    }
+
+    impl Connection {
+        async fn send(&mut self, message: &impl serde::Serialize) -> Result<(), Error> {
+            let mut message = serde_json::to_string(message)?;
+            message.push('\n');
+            self.writer
+                .write_all(message.as_bytes())
+                .await
+                .map_err(|err| Error::Transport(Box::new(err)))
+        }
+
+        async fn receive(&mut self) -> Result<String, Error> {
+            match self.reader.next_line().await {
+                Ok(Some(line)) => Ok(line),
+                Ok(None) => Err(Error::RestartNeeded("connection closed".to_string())),
+                Err(err) => Err(Error::Transport(Box::new(err))),
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Client for IpcClient {
+        async fn notification<'a>(
+            &self,
+            method: &'a str,
+            params: JsonRpcParams<'a>,
+        ) -> Result<(), Error> {
+            let mut connection = self.connection.lock().await;
+            connection
+                .send(&JsonRpcNotificationSer::new(method, params))
+                .await
+        }
+
+        async fn request<'a, R>(
+            &self,
+            method: &'a str,
+            params: JsonRpcParams<'a>,
+        ) -> Result<R, Error>
+        where
+            R: DeserializeOwned,
+        {
+            let id = self.request_id.fetch_add(1, Ordering::SeqCst);
+            let mut connection = self.connection.lock().await;
+            connection
+                .send(&JsonRpcCallSer::new(Id::Number(id), method, params))
+                .await?;
+            loop {
+                let line = connection.receive().await?;
+                if let Some(result) = parse_response(&line, id) {
+                    return result;
+                }
+            }
+        }
+
+        async fn batch_request<'a, R>(
+            &self,
+            batch: Vec<(&'a str, JsonRpcParams<'a>)>,
+        ) -> Result<Vec<R>, Error>
+        where
+            R: DeserializeOwned + Default + Clone,
+        {
+            // Kept as JSON until all responses are in, `R` need not be `Send`.
+            let mut responses: Vec<serde_json::Value> = Vec::with_capacity(batch.len());
+            for (method, params) in batch {
+                responses.push(self.request(method, params).await?);
+            }
+            responses
+                .into_iter()
+                .map(|response| serde_json::from_value(response).map_err(Error::ParseError))
+                .collect()
+        }
+    }
+
+    /// The outcome of request `id` if `line` is the response to it. Errors without ID answer
+    /// requests the server could not parse, which ours always are not.
+    fn parse_response<R: DeserializeOwned>(line: &str, id: u64) -> Option<Result<R, Error>> {
+        if let Ok(response) = serde_json::from_str::<JsonRpcResponse<R>>(line) {
+            if response.id.as_number() == Some(&id) {
+                return Some(Ok(response.result));
+            }
+        } else if let Ok(err) = serde_json::from_str::<JsonRpcError>(line) {
+            if err.id.as_number() == Some(&id) {
+                return Some(Err(Error::Request(err.to_string())));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(all(test, feature = "server", feature = "client"))]
+mod tests {
+    use super::IpcClient;
+    use jsonrpsee::types::traits::Client;
+    use jsonrpsee::types::v2::params::JsonRpcParams;
+    use jsonrpsee::RpcModule;
+    use std::time::Duration;
+    use tokio::io::AsyncBufReadExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio::io::BufReader;
+
+    #[tokio::test]
+    async fn notification_is_not_answered() {
+        let path = std::env::temp_dir().join(format!("keta-rpc-{}.ipc", std::process::id()));
+        let mut module = RpcModule::new(());
+        module
+            .register_method("ping", |_, _| Ok::<_, jsonrpsee::types::CallError>("pong"))
+            .unwrap();
+        let server_path = path.clone();
+        tokio::spawn(async move { super::serve(module, &server_path).await });
+
+        let client = loop {
+            match IpcClient::connect(&path).await {
+                Ok(client) => break client,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        client
+            .notification("ping", JsonRpcParams::NoParams)
+            .await
+            .unwrap();
+        let response: String = client
+            .request("ping", JsonRpcParams::NoParams)
+            .await
+            .unwrap();
+        assert_eq!(response, "pong");
+
+        // The first line back answers the request, not the notification before it.
+        let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(b"{\"jsonrpc\":\"2.0\",\"method\":\"ping\"}\n")
+            .await
+            .unwrap();
+        stream
+            .write_all(b"{\"jsonrpc\":\"2.0\",\"id\":7,\"method\":\"ping\"}\n")
+            .await
+            .unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        let line = lines.next_line().await.unwrap().unwrap();
+        assert_eq!(line, r#"{"jsonrpc":"2.0","result":"pong","id":7}"#);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,45 +1,296 @@
-use jsonrpsee::proc_macros::rpc;
+use chrono::DateTime;
+use chrono::Utc;
+use keta_core::account::Account;
 use keta_core::account::Address;
+#[cfg(feature = "client")]
 use keta_core::block::HashedBlock;
+#[cfg(feature = "client")]
 use keta_core::transaction::SignedTransaction;
+use keta_core::transaction::Transaction;
+use keta_crypto::Hash;
+use keta_crypto::Signature;
 use serde::Deserialize;
 use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+mod error;
+mod ipc;
+#[cfg(feature = "server")]
+mod server;
 
-#[derive(Debug, Serialize, Deserialize, thiserror::Error)]
-pub enum Error {
-    #[error("world: {0}")]
-    World(String),
-    #[error("json-rpc: {0}")]
-    JsonRPC(String),
+pub use error::code;
+pub use error::Error;
+#[cfg(feature = "client")]
+pub use ipc::IpcClient;
+#[cfg(feature = "server")]
+pub use server::RpcServer;
+
+/// Outcome of `sendTransaction`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SentTransaction {
+    pub hash: Hash,
+    /// Pending transaction with the same sender and nonce that was replaced by this one.
+    pub replaced: Option<Hash>,
 }
 
-impl From<jsonrpsee::types::Error> for Error {
-    fn from(err: jsonrpsee::types::Error) -> Self {
-        Self::JsonRPC(err.to_string())
-    }
+/// Transaction to run through `simulateTransaction`. A `SignedTransaction` is accepted as is, an
+/// unsigned one needs `skip_signature`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimulationRequest {
+    pub transaction: Transaction,
+    pub signature: Option<Signature>,
+    #[serde(default)]
+    pub skip_signature: bool,
+}
+
+/// Outcome of `simulateTransaction` for a transaction the node would accept.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Simulation {
+    /// Pending transaction with the same sender and nonce that would be replaced.
+    pub replaced: Option<Hash>,
+    /// Pending transactions that would be evicted to make room.
+    pub evicted: Vec<Hash>,
+    /// Sender and recipient as they would be once the transaction is included in the next block.
+    pub accounts: BTreeMap<Address, Account>,
+}
+
+/// Where a transaction is, as returned by `getTransactionStatus`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TransactionStatus {
+    /// Never seen by the node, or dropped so long ago that it was forgotten.
+    Unknown,
+    Pending,
+    /// Included in the block at `height`, which counts as the first confirmation.
+    Included {
+        height: u64,
+        confirmations: u64,
+    },
+    /// Left the mempool without being included.
+    Dropped {
+        reason: String,
+    },
+}
+
+/// Progress of downloading blocks from peers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncStatus {
+    pub syncing: bool,
+    pub current_height: u64,
+    pub target_height: u64,
+    pub blocks_per_second: f64,
+}
+
+/// The chain as seen by the node, returned by `getChainInfo`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainInfo {
+    pub chain_id: String,
+    pub genesis_hash: Hash,
+    pub height: u64,
+    pub tip_hash: Hash,
+    pub tip_timestamp: DateTime<Utc>,
+    /// Leading zero bits a block hash needs.
+    pub difficulty: u64,
+    /// Sum of all balances.
+    pub total_supply: u64,
+}
+
+/// The node itself, returned by `getNodeStatus`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeStatus {
+    pub version: String,
+    pub peer_count: u64,
+    /// Number of pending transactions.
+    pub mempool_size: u64,
+    pub sync: SyncStatus,
+}
+
+/// A peer address the node refuses to talk to until `until`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ban {
+    pub address: IpAddr,
+    pub until: DateTime<Utc>,
+    pub reason: String,
+}
+
+/// Balance and nonce of an account, as sent by `subscribeAccount` whenever either changes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountUpdate {
+    /// Height of the tip when the change was seen.
+    pub height: u64,
+    pub balance: u64,
+    /// Nonce the next transaction of the account needs, counting pending ones.
+    pub nonce: u64,
 }
 
-#[cfg_attr(feature = "server", rpc(server))]
-#[cfg_attr(feature = "client", rpc(client))]
+#[cfg(feature = "server")]
+pub use jsonrpsee::SubscriptionSink;
+
+// Failed calls come back as `jsonrpsee::types::Error`, which converts into the `Error` the node
+// raised. The server side is `RpcServer`. Admin methods are only served over IPC.
+#[cfg(feature = "client")]
+#[jsonrpsee::proc_macros::rpc(client)]
 pub trait Rpc {
     #[method(name = "sendTransaction")]
-    fn send_transaction(&self, transaction: SignedTransaction) -> Result<(), Error>;
+    fn send_transaction(&self, transaction: SignedTransaction) -> SentTransaction;
+    #[method(name = "simulateTransaction")]
+    fn simulate_transaction(&self, request: SimulationRequest) -> Simulation;
+    #[method(name = "getTransactionStatus")]
+    fn get_transaction_status(&self, hash: Hash) -> TransactionStatus;
     #[method(name = "generateBlock")]
-    fn generate_block(&self) -> Result<HashedBlock, Error>;
+    fn generate_block(&self) -> HashedBlock;
     #[method(name = "getBalance")]
-    fn get_balance(&self, address: Address) -> Result<u64, Error>;
+    fn get_balance(&self, address: Address) -> u64;
+    #[method(name = "getNonce")]
+    fn get_nonce(&self, address: Address) -> u64;
+    #[method(name = "getChainInfo")]
+    fn get_chain_info(&self) -> ChainInfo;
+    #[method(name = "getNodeStatus")]
+    fn get_node_status(&self) -> NodeStatus;
+    #[method(name = "getSyncStatus")]
+    fn get_sync_status(&self) -> SyncStatus;
+    #[method(name = "listBans")]
+    fn list_bans(&self) -> Vec<Ban>;
+    #[method(name = "clearBan")]
+    fn clear_ban(&self, address: IpAddr) -> bool;
+    #[method(name = "clearBans")]
+    fn clear_bans(&self) -> u64;
     #[method(name = "getAllBlocks")]
-    fn get_all_blocks(&self) -> Result<Vec<HashedBlock>, Error>;
+    fn get_all_blocks(&self) -> Vec<HashedBlock>;
+    #[subscription(
+        name = "subscribeNewHeads",
+        unsub = "unsubscribeNewHeads",
+        item = keta_core::block::HashedHeader
+    )]
+    fn subscribe_new_heads(&self);
+    #[subscription(
+        name = "subscribePendingTransactions",
+        unsub = "unsubscribePendingTransactions",
+        item = SignedTransaction
+    )]
+    fn subscribe_pending_transactions(&self);
+    #[subscription(
+        name = "subscribeAccount",
+        unsub = "unsubscribeAccount",
+        item = AccountUpdate
+    )]
+    fn subscribe_account(&self, address: Address);
+}
+
+/// Connection to a node, over WebSocket, HTTP or a Unix socket depending on the URL it was opened
+/// with.
+#[cfg(feature = "client")]
+#[derive(Debug)]
+pub enum Client {
+    Ws(jsonrpsee::ws_client::WsClient),
+    Http(jsonrpsee::http_client::HttpClient),
+    Ipc(IpcClient),
+}
+
+#[cfg(feature = "client")]
+#[async_trait::async_trait]
+impl jsonrpsee::types::traits::Client for Client {
+    async fn notification<'a>(
+        &self,
+        method: &'a str,
+        params: jsonrpsee::types::v2::params::JsonRpcParams<'a>,
+    ) -> Result<(), jsonrpsee::types::Error> {
+        match self {
+            Client::Ws(client) => client.notification(method, params).await,
+            Client::Http(client) => client.notification(method, params).await,
+            Client::Ipc(client) => client.notification(method, params).await,
+        }
+    }
+
+    async fn request<'a, R>(
+        &self,
+        method: &'a str,
+        params: jsonrpsee::types::v2::params::JsonRpcParams<'a>,
+    ) -> Result<R, jsonrpsee::types::Error>
+    where
+        R: serde::de::DeserializeOwned,
+    {
+        match self {
+            Client::Ws(client) => client.request(method, params).await,
+            Client::Http(client) => client.request(method, params).await,
+            Client::Ipc(client) => client.request(method, params).await,
+        }
+    }
+
+    async fn batch_request<'a, R>(
+        &self,
+        batch: Vec<(&'a str, jsonrpsee::types::v2::params::JsonRpcParams<'a>)>,
+    ) -> Result<Vec<R>, jsonrpsee::types::Error>
+    where
+        R: serde::de::DeserializeOwned + Default + Clone,
+    {
+        match self {
+            Client::Ws(client) => client.batch_request(batch).await,
+            Client::Http(client) => client.batch_request(batch).await,
+            Client::Ipc(client) => client.batch_request(batch).await,
+        }
+    }
 }
 
 #[cfg(feature = "client")]
-pub type Client = jsonrpsee::ws_client::WsClient;
+#[async_trait::async_trait]
+impl jsonrpsee::types::traits::SubscriptionClient for Client {
+    async fn subscribe<'a, Notif>(
+        &self,
+        subscribe_method: &'a str,
+        params: jsonrpsee::types::v2::params::JsonRpcParams<'a>,
+        unsubscribe_method: &'a str,
+    ) -> Result<jsonrpsee::types::Subscription<Notif>, jsonrpsee::types::Error>
+    where
+        Notif: serde::de::DeserializeOwned,
+    {
+        match self {
+            Client::Ws(client) => {
+                client
+                    .subscribe(subscribe_method, params, unsubscribe_method)
+                    .await
+            }
+            Client::Http(_) | Client::Ipc(_) => Err(subscriptions_unsupported()),
+        }
+    }
+
+    async fn subscribe_to_method<'a, Notif>(
+        &self,
+        method: &'a str,
+    ) -> Result<jsonrpsee::types::Subscription<Notif>, jsonrpsee::types::Error>
+    where
+        Notif: serde::de::DeserializeOwned,
+    {
+        match self {
+            Client::Ws(client) => client.subscribe_to_method(method).await,
+            Client::Http(_) | Client::Ipc(_) => Err(subscriptions_unsupported()),
+        }
+    }
+}
 
 #[cfg(feature = "client")]
-pub async fn connect(url: &url::Url) -> Result<jsonrpsee::ws_client::WsClient, Error> {
+fn subscriptions_unsupported() -> jsonrpsee::types::Error {
+    jsonrpsee::types::Error::Custom("subscriptions need a WebSocket connection".to_string())
+}
+
+/// Connects to the node at `url`, over WebSocket for `ws://` and `wss://` URLs, over HTTP for
+/// `http://` and `https://` ones and over the Unix socket at the path of `ipc://` ones.
+#[cfg(feature = "client")]
+pub async fn connect(url: &url::Url) -> Result<Client, Error> {
+    use jsonrpsee::http_client::HttpClientBuilder;
     use jsonrpsee::ws_client::WsClientBuilder;
 
-    let client = WsClientBuilder::default().build(url.as_str()).await?;
+    let client = match url.scheme() {
+        "ws" | "wss" => Client::Ws(WsClientBuilder::default().build(url.as_str()).await?),
+        "http" | "https" => Client::Http(HttpClientBuilder::default().build(url.as_str())?),
+        "ipc" => Client::Ipc(
+            IpcClient::connect(std::path::Path::new(url.path()))
+                .await
+                .map_err(|err| Error::Ipc(err.to_string()))?,
+        ),
+        scheme => return Err(Error::UnsupportedScheme(scheme.to_string())),
+    };
     Ok(client)
 }
 
@@ -55,3 +306,57 @@ pub async fn serve(
 
     Ok(())
 }
+
+/// Requests the HTTP server accepts, by their `Origin` and `Host` headers. Empty lists accept
+/// only the server's own address, `*` has to be listed explicitly to accept any.
+#[cfg(feature = "server")]
+#[derive(Debug, Clone, Default)]
+pub struct AccessControl {
+    /// Origins browsers may call from, `*` for any non-null origin and `null` for local files.
+    /// Requests from the server's own host are always accepted.
+    pub cors_origins: Vec<String>,
+    /// Host names, optionally with a port or `*` wildcards, requests must be addressed to.
+    pub allowed_hosts: Vec<String>,
+}
+
+#[cfg(feature = "server")]
+pub async fn serve_http(
+    server: impl RpcServer,
+    address: std::net::SocketAddr,
+    access_control: &AccessControl,
+) -> Result<(), Error> {
+    use jsonrpsee::http_server::AccessControlBuilder;
+    use jsonrpsee::http_server::Host;
+    use jsonrpsee::http_server::HttpServerBuilder;
+
+    // The builder accepts anything for lists left empty.
+    let own_origin = [format!("http://{}", address)];
+    let own_host = [address.to_string()];
+    let cors_origins = match access_control.cors_origins.as_slice() {
+        [] => &own_origin[..],
+        origins => origins,
+    };
+    let allowed_hosts = match access_control.allowed_hosts.as_slice() {
+        [] => &own_host[..],
+        hosts => hosts,
+    };
+
+    let mut builder = AccessControlBuilder::new();
+    for origin in cors_origins {
+        builder = builder.cors_allow_origin(origin.as_str().into());
+    }
+    for host in allowed_hosts {
+        builder = builder.allow_host(Host::parse(host));
+    }
+    let http_server = HttpServerBuilder::default()
+        .set_access_control(builder.build())
+        .build(address)?;
+    http_server.start(server.into_rpc()).await?;
+
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+pub async fn serve_ipc(server: impl RpcServer, path: &std::path::Path) -> Result<(), Error> {
+    ipc::serve(server.into_admin_rpc(), path).await
+}
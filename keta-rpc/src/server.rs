@@ -0,0 +1,116 @@
+//! Server side of the `Rpc` API. Methods are registered by hand instead of being generated from
+//! the trait, so that failures go out as JSON-RPC errors carrying the codes of [`Error`].
+//! Admin methods, which change the node instead of just querying or feeding it, are only
+//! registered by [`RpcServer::into_admin_rpc`].
+
+use crate::Ban;
+use crate::ChainInfo;
+use crate::Error;
+use crate::NodeStatus;
+use crate::SentTransaction;
+use crate::Simulation;
+use crate::SimulationRequest;
+use crate::SyncStatus;
+use crate::TransactionStatus;
+use jsonrpsee::RpcModule;
+use jsonrpsee::SubscriptionSink;
+use keta_core::account::Address;
+use keta_core::block::HashedBlock;
+use keta_core::transaction::SignedTransaction;
+use keta_crypto::Hash;
+use std::net::IpAddr;
+
+pub trait RpcServer: Sized + Send + Sync + 'static {
+    fn send_transaction(&self, transaction: SignedTransaction) -> Result<SentTransaction, Error>;
+    /// Checks whether the node would accept a transaction, without sending it.
+    fn simulate_transaction(&self, request: SimulationRequest) -> Result<Simulation, Error>;
+    fn get_transaction_status(&self, hash: Hash) -> Result<TransactionStatus, Error>;
+    fn get_balance(&self, address: Address) -> Result<u64, Error>;
+    fn get_nonce(&self, address: Address) -> Result<u64, Error>;
+    fn get_chain_info(&self) -> Result<ChainInfo, Error>;
+    fn get_node_status(&self) -> Result<NodeStatus, Error>;
+    fn get_sync_status(&self) -> Result<SyncStatus, Error>;
+    fn get_all_blocks(&self) -> Result<Vec<HashedBlock>, Error>;
+    fn subscribe_new_heads(&self, sink: SubscriptionSink);
+    fn subscribe_pending_transactions(&self, sink: SubscriptionSink);
+    /// Sends `AccountUpdate`s of `address`.
+    fn subscribe_account(&self, sink: SubscriptionSink, address: Address);
+
+    // Admin methods, only registered by `into_admin_rpc`.
+    fn generate_block(&self) -> Result<HashedBlock, Error>;
+    fn list_bans(&self) -> Result<Vec<Ban>, Error>;
+    fn clear_ban(&self, address: IpAddr) -> Result<bool, Error>;
+    fn clear_bans(&self) -> Result<u64, Error>;
+
+    /// Collects the public methods and subscriptions into a module to serve.
+    fn into_rpc(self) -> RpcModule<Self> {
+        let mut module = RpcModule::new(self);
+        register(&mut module).expect("RPC method names never conflict");
+        module
+    }
+
+    /// Like `into_rpc`, but with the admin methods too. Only to be served to local tools.
+    fn into_admin_rpc(self) -> RpcModule<Self> {
+        let mut module = self.into_rpc();
+        register_admin(&mut module).expect("RPC method names never conflict");
+        module
+    }
+}
+
+fn register<S: RpcServer>(module: &mut RpcModule<S>) -> Result<(), jsonrpsee::types::Error> {
+    module.register_method("sendTransaction", |params, server| {
+        Ok(server.send_transaction(params.one()?)?)
+    })?;
+    module.register_method("simulateTransaction", |params, server| {
+        Ok(server.simulate_transaction(params.one()?)?)
+    })?;
+    module.register_method("getTransactionStatus", |params, server| {
+        Ok(server.get_transaction_status(params.one()?)?)
+    })?;
+    module.register_method("getBalance", |params, server| {
+        Ok(server.get_balance(params.one()?)?)
+    })?;
+    module.register_method("getNonce", |params, server| {
+        Ok(server.get_nonce(params.one()?)?)
+    })?;
+    module.register_method("getChainInfo", |_, server| Ok(server.get_chain_info()?))?;
+    module.register_method("getNodeStatus", |_, server| Ok(server.get_node_status()?))?;
+    module.register_method("getSyncStatus", |_, server| Ok(server.get_sync_status()?))?;
+    module.register_method("getAllBlocks", |_, server| Ok(server.get_all_blocks()?))?;
+
+    module.register_subscription(
+        "subscribeNewHeads",
+        "unsubscribeNewHeads",
+        |_, sink, server| {
+            server.subscribe_new_heads(sink);
+            Ok(())
+        },
+    )?;
+    module.register_subscription(
+        "subscribePendingTransactions",
+        "unsubscribePendingTransactions",
+        |_, sink, server| {
+            server.subscribe_pending_transactions(sink);
+            Ok(())
+        },
+    )?;
+    module.register_subscription(
+        "subscribeAccount",
+        "unsubscribeAccount",
+        |params, sink, server| {
+            server.subscribe_account(sink, params.one()?);
+            Ok(())
+        },
+    )?;
+    Ok(())
+}
+
+fn register_admin<S: RpcServer>(module: &mut RpcModule<S>) -> Result<(), jsonrpsee::types::Error> {
+    module.register_method("generateBlock", |_, server| Ok(server.generate_block()?))?;
+    module.register_method("listBans", |_, server| Ok(server.list_bans()?))?;
+    module.register_method("clearBan", |params, server| {
+        Ok(server.clear_ban(params.one()?)?)
+    })?;
+    module.register_method("clearBans", |_, server| Ok(server.clear_bans()?))?;
+    Ok(())
+}